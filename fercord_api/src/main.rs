@@ -1,4 +1,4 @@
-use actix_session::{storage::RedisSessionStore, Session, SessionMiddleware};
+use actix_session::{Session, SessionMiddleware};
 use actix_web::cookie::Key;
 use actix_web::http::header::TryIntoHeaderValue;
 use actix_web::http::{header, StatusCode};
@@ -19,13 +19,17 @@ use fercord_common::{cli, prelude::*};
 use fercord_storage::{db::Pool, prelude::*};
 
 use crate::model::HealthCheck;
+use crate::session::SessionStorage;
 
 mod discord;
 mod model;
+mod session;
 mod util;
 mod user;
 
 pub(crate) const SESSION_DATA_KEY: &str = "session_data";
+/// The Discord id of the logged in user, so the sessions of a user can be found in the database.
+pub(crate) const SESSION_USER_ID_KEY: &str = "user_id";
 pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub(crate) const REPO_URL: &str = env!("CARGO_PKG_REPOSITORY");
 
@@ -124,7 +128,7 @@ async fn discord_auth(
 
     let config = config.get_ref().clone();
 
-    let new_session: model::DiscordSessionData = discord::Client::try_from_auth_code(
+    let discord_client = discord::Client::try_from_auth_code(
         &discord_token,
         config
             .client_id
@@ -134,8 +138,18 @@ async fn discord_auth(
             .expect("client_secret is required when running the API"),
     )
     .await
-    .map_err(|e| ApiError::OAuthTokenExchangeError(e.to_string()))?
-    .into();
+    .map_err(|e| ApiError::OAuthTokenExchangeError(e.to_string()))?;
+    let user_id: u64 = discord_client
+        .get_user_identity()
+        .await
+        .map_err(|e| ApiError::OAuthTokenExchangeError(e.to_string()))?
+        .user
+        .id
+        .parse()
+        .map_err(|_| {
+            ApiError::OAuthTokenExchangeError("Discord returned an invalid user id".into())
+        })?;
+    let new_session: model::DiscordSessionData = discord_client.into();
     if let Some(session_data) = session
         .get::<model::DiscordSessionData>(SESSION_DATA_KEY)
         .map_err(|e| ApiError::OAuthTokenExchangeError(e.to_string()))?
@@ -148,6 +162,7 @@ async fn discord_auth(
                 "Newer token is more up to date, overwriting old sessions data..."
             );
             session.insert(SESSION_DATA_KEY, new_session)?;
+            session.insert(SESSION_USER_ID_KEY, user_id)?;
         } else {
             event!(
                 Level::DEBUG,
@@ -160,6 +175,7 @@ async fn discord_auth(
             "No session data existed yet. Storing session data"
         );
         session.insert(SESSION_DATA_KEY, new_session)?;
+        session.insert(SESSION_USER_ID_KEY, user_id)?;
     }
 
     Ok(HttpResponse::TemporaryRedirect()
@@ -206,10 +222,10 @@ async fn main() -> anyhow::Result<()> {
         bail!("The configuration is missing required fields for use with the API service")
     }
 
-    let kv = KVClient::new(&config).expect("Error constructing KV client");
-    let db = db::setup(&config.database_url.clone())
+    let db = db::setup_with(&config.database_url, &config.database)
         .await
         .expect("Error constructing db pool");
+    let kv = KVClient::new(&config, &db).expect("Error constructing KV client");
     let session_key = Key::from(
         config
             .session_key
@@ -217,9 +233,15 @@ async fn main() -> anyhow::Result<()> {
             .expect("session_key is required in config when running the API")
             .as_bytes(),
     );
-    let redis_session_store = RedisSessionStore::new(&config.redis_url)
+    let session_store = SessionStorage::new(&config, &db)
         .await
-        .expect("Error creating redis session store");
+        .expect("Error creating session store");
+    if config.session_store_kind() == SessionStoreKind::Database {
+        let interval = std::time::Duration::from_secs((config.job_interval_min * 60) as u64);
+        actix_web::rt::spawn(session::session_maintenance(db.clone(), interval));
+    }
+    // actix handles SIGTERM and Ctrl+C itself, we only need to tell it how long in-flight requests get to finish.
+    let shutdown_grace_sec = config.shutdown_grace_sec as u64;
 
     HttpServer::new(move || {
         let (app, _) = App::new()
@@ -230,7 +252,7 @@ async fn main() -> anyhow::Result<()> {
             .app_data(web::Data::new(db.clone()))
             .map(|app| {
                 app.wrap(SessionMiddleware::new(
-                    redis_session_store.clone(),
+                    session_store.clone(),
                     session_key.clone(),
                 ))
             })
@@ -256,8 +278,13 @@ async fn main() -> anyhow::Result<()> {
 
         app.service(web::redirect("/", "/docs"))
     })
+    .shutdown_timeout(shutdown_grace_sec)
     .bind((Ipv4Addr::UNSPECIFIED, 8888))?
     .run()
     .await
-    .map_err(|e| anyhow!(e))
+    .map_err(|e| anyhow!(e))?;
+
+    event!(Level::INFO, "Shutdown complete");
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_session::storage::{
+    generate_session_key, LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore,
+    UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, TimeDelta, Utc};
+use tracing::{event, Level};
+
+use fercord_common::prelude::*;
+use fercord_storage::encryption::FieldCipher;
+use fercord_storage::prelude::*;
+
+use crate::SESSION_USER_ID_KEY;
+
+type SessionState = HashMap<String, String>;
+
+/// Keeps the sessions in the `sessions` table of the fercord database, for installs that run without redis.
+///
+/// The state is encrypted when a cipher is given. The id of the logged in user is stored next to it, so the user can
+/// export and delete their sessions. Expired sessions are never loaded and are deleted by [`session_maintenance`].
+#[derive(Debug, Clone)]
+pub struct DatabaseSessionStore {
+    pool: AnyPool,
+    cipher: Option<Arc<FieldCipher>>,
+}
+
+impl DatabaseSessionStore {
+    pub fn new(pool: AnyPool, cipher: Option<Arc<FieldCipher>>) -> Self {
+        Self { pool, cipher }
+    }
+
+    fn repository(&self) -> SessionRepo<'_> {
+        Session::repository(&self.pool).with_cipher(self.cipher.as_deref())
+    }
+
+    fn session(key: &SessionKey, state: &SessionState, ttl: &Duration) -> anyhow::Result<Session> {
+        let user_id = state
+            .get(SESSION_USER_ID_KEY)
+            .map(|user_id| serde_json::from_str::<u64>(user_id))
+            .transpose()
+            .context("Error reading the user id of the session")?;
+
+        Ok(Session {
+            key: key.as_ref().to_owned(),
+            user_id,
+            state: serde_json::to_string(state).context("Error serializing session state")?,
+            expires_at: expires_at(ttl),
+        })
+    }
+}
+
+/// When a session with the given ttl expires, the end of time when that is too far away.
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+    let now = Utc::now();
+    TimeDelta::try_seconds(ttl.whole_seconds())
+        .and_then(|ttl| now.checked_add_signed(ttl))
+        .unwrap_or(if ttl.is_negative() {
+            DateTime::<Utc>::MIN_UTC
+        } else {
+            DateTime::<Utc>::MAX_UTC
+        })
+}
+
+/// Delete the expired sessions every `interval`, for as long as the API runs.
+///
+/// The `maintenance` job of the bot deletes them as well, but the API may run without the bot. The rest of the
+/// maintenance is left to that job.
+pub async fn session_maintenance(pool: AnyPool, interval: std::time::Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        match Session::repository(&pool)
+            .delete_sessions_expired_before(&Utc::now())
+            .await
+        {
+            Ok(deleted) => event!(Level::DEBUG, deleted, "Deleted expired sessions"),
+            Err(e) => event!(Level::ERROR, ?e, "Error deleting expired sessions"),
+        }
+    }
+}
+
+impl SessionStore for DatabaseSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let Some(session) = self
+            .repository()
+            .get_session(session_key.as_ref())
+            .await
+            .map_err(LoadError::Other)?
+        else {
+            return Ok(None);
+        };
+
+        serde_json::from_str(&session.state)
+            .context("Error deserializing session state")
+            .map(Some)
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let repo = self.repository();
+        // A new key colliding with an existing one is unlikely, but it must never hand out someone else's session
+        for _ in 0..3 {
+            let session_key = generate_session_key();
+            let session = Self::session(&session_key, &session_state, ttl)
+                .map_err(SaveError::Serialization)?;
+            if repo
+                .insert_session(&session)
+                .await
+                .map_err(SaveError::Other)?
+            {
+                return Ok(session_key);
+            }
+        }
+
+        Err(SaveError::Other(anyhow!(
+            "Could not generate a unique session key"
+        )))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let session =
+            Self::session(&session_key, &session_state, ttl).map_err(UpdateError::Serialization)?;
+        let updated = self
+            .repository()
+            .update_session(&session)
+            .await
+            .map_err(UpdateError::Other)?;
+        if updated {
+            return Ok(session_key);
+        }
+
+        // The session expired in the meantime, start a new one like the redis store does
+        event!(
+            Level::DEBUG,
+            "Session to update no longer exists, saving a new one"
+        );
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        self.repository()
+            .update_session_expiry(session_key.as_ref(), &expires_at(ttl))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        self.repository().delete_session(session_key.as_ref()).await
+    }
+}
+
+/// The session store picked by the `session_store` setting, see [`DiscordConfig::session_store_kind`].
+#[derive(Clone)]
+pub enum SessionStorage {
+    Redis(RedisSessionStore),
+    Database(DatabaseSessionStore),
+}
+
+impl SessionStorage {
+    pub async fn new(config: &DiscordConfig, pool: &AnyPool) -> anyhow::Result<Self> {
+        match config.session_store_kind() {
+            SessionStoreKind::Redis => {
+                let redis_url = config
+                    .redis_url
+                    .as_deref()
+                    .context("redis_url is required to keep the sessions in redis")?;
+                let store = RedisSessionStore::new(redis_url)
+                    .await
+                    .context("Error creating redis session store")?;
+
+                Ok(Self::Redis(store))
+            }
+            SessionStoreKind::Database => {
+                let cipher = config
+                    .encryption
+                    .as_ref()
+                    .map(|config| FieldCipher::from_config(config).map(Arc::new))
+                    .transpose()
+                    .context("Error setting up session encryption")?;
+
+                Ok(Self::Database(DatabaseSessionStore::new(
+                    pool.clone(),
+                    cipher,
+                )))
+            }
+        }
+    }
+}
+
+impl SessionStore for SessionStorage {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Redis(store) => store.load(session_key).await,
+            Self::Database(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Redis(store) => store.save(session_state, ttl).await,
+            Self::Database(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Redis(store) => store.update(session_key, session_state, ttl).await,
+            Self::Database(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        match self {
+            Self::Redis(store) => store.update_ttl(session_key, ttl).await,
+            Self::Database(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        match self {
+            Self::Redis(store) => store.delete(session_key).await,
+            Self::Database(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn database_sessions_round_trip() -> Result<()> {
+        let store = DatabaseSessionStore::new(db::setup("sqlite::memory:").await?, None);
+        let state = SessionState::from([("session_data".to_owned(), "{}".to_owned())]);
+
+        let key = store.save(state.clone(), &Duration::hours(1)).await?;
+        assert_eq!(Some(state.clone()), store.load(&key).await?);
+
+        let updated = SessionState::new();
+        let key = store
+            .update(key, updated.clone(), &Duration::hours(1))
+            .await?;
+        assert_eq!(Some(updated), store.load(&key).await?);
+
+        store.delete(&key).await?;
+        assert_eq!(None, store.load(&key).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_sessions_keep_their_user_and_encrypt_state() -> Result<()> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let cipher = Arc::new(FieldCipher::new("test", &[1; 32])?);
+        let store = DatabaseSessionStore::new(pool.clone(), Some(cipher.clone()));
+        let state = SessionState::from([(SESSION_USER_ID_KEY.to_owned(), "42".to_owned())]);
+
+        let key = store.save(state.clone(), &Duration::hours(1)).await?;
+        assert_eq!(Some(state), store.load(&key).await?);
+
+        let sessions = Session::repository(&pool)
+            .with_cipher(Some(&cipher))
+            .sessions_of_user(42)
+            .await?;
+        assert_eq!(
+            vec![key.as_ref()],
+            sessions.iter().map(|s| s.key.as_str()).collect::<Vec<_>>()
+        );
+        // Without the key the state can not be read
+        assert!(DatabaseSessionStore::new(pool, None)
+            .load(&key)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_ttls_saturate() {
+        assert_eq!(DateTime::<Utc>::MAX_UTC, expires_at(&Duration::MAX));
+        assert_eq!(DateTime::<Utc>::MIN_UTC, expires_at(&Duration::MIN));
+    }
+
+    #[tokio::test]
+    async fn expired_database_sessions_are_replaced_on_update() -> Result<()> {
+        let store = DatabaseSessionStore::new(db::setup("sqlite::memory:").await?, None);
+        let state = SessionState::new();
+
+        let expired = store.save(state.clone(), &Duration::seconds(-1)).await?;
+        assert_eq!(None, store.load(&expired).await?);
+
+        let expired_key = expired.as_ref().to_owned();
+        let key = store
+            .update(expired, state.clone(), &Duration::hours(1))
+            .await?;
+        assert_ne!(expired_key, key.as_ref());
+        assert_eq!(Some(state), store.load(&key).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+use anyhow::{bail, Context, Result};
+use tracing::{event, Level};
+
+use fercord_common::prelude::*;
+use fercord_storage::backup::{read_archive, restore_backup, write_backup};
+use fercord_storage::prelude::*;
+use fercord_storage::transfer::copy_database;
+
+use crate::job::JOB_STATE_KV_PATTERNS;
+
+/// Handle the `backup` subcommand and return the output for the user.
+///
+/// The archive is written next to `path` first and only moved in place when it is complete.
+pub(crate) async fn backup_command(
+    app_config: &DiscordConfig,
+    path: &str,
+    dry_run: bool,
+) -> Result<String> {
+    let db_pool = db::setup_with(&app_config.database_url, &app_config.database)
+        .await
+        .context("Error setting up database connection")?;
+    let kv_client = KVClient::new(app_config, &db_pool).context("Error building KV client")?;
+
+    if dry_run {
+        let summary = write_backup(
+            &db_pool,
+            &kv_client,
+            &JOB_STATE_KV_PATTERNS,
+            std::io::sink(),
+        )
+        .await?;
+
+        return Ok(format!("Would back up {summary} to {path}"));
+    }
+
+    let partial_path = format!("{path}.partial");
+    event!(Level::DEBUG, %partial_path, "Writing backup");
+    let file = File::create(&partial_path)
+        .with_context(|| format!("Error creating backup file {}", partial_path))?;
+
+    let summary = write_backup(
+        &db_pool,
+        &kv_client,
+        &JOB_STATE_KV_PATTERNS,
+        BufWriter::new(file),
+    )
+    .await?;
+    std::fs::rename(&partial_path, path)
+        .with_context(|| format!("Error moving the backup to {}", path))?;
+
+    Ok(format!("Backed up {summary} to {path}"))
+}
+
+/// Handle the `restore` subcommand and return the output for the user.
+pub(crate) async fn restore_command(
+    app_config: &DiscordConfig,
+    path: &str,
+    dry_run: bool,
+) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("Error opening backup file {}", path))?;
+    let archive = read_archive(BufReader::new(file))?;
+
+    let db_pool = db::setup_with(&app_config.database_url, &app_config.database)
+        .await
+        .context("Error setting up database connection")?;
+    let kv_client = KVClient::new(app_config, &db_pool).context("Error building KV client")?;
+
+    let summary = restore_backup(&db_pool, &kv_client, &archive, dry_run).await?;
+
+    if !dry_run {
+        let event_bus = EventBus::new(app_config).context("Error setting up the event bus")?;
+        event_bus.send(StorageEvent::RemindersChanged);
+        for settings in &archive.guild_settings {
+            event_bus.send(StorageEvent::GuildSettingsSaved {
+                guild_id: settings.guild_id,
+            });
+        }
+        // The process exits right after, taking the queued events with it
+        event_bus.flush().await;
+    }
+
+    Ok(if dry_run {
+        format!(
+            "Would restore {summary} from the backup of {}",
+            archive.header.created_at
+        )
+    } else {
+        format!(
+            "Restored {summary} from the backup of {}",
+            archive.header.created_at
+        )
+    })
+}
+
+/// Handle the `copy-database` subcommand and return the output for the user.
+///
+/// Fails when the destination does not have the same amount of records as the source afterwards.
+pub(crate) async fn copy_database_command(
+    app_config: &DiscordConfig,
+    source_url: &str,
+    destination_url: &str,
+    batch_size: u32,
+) -> Result<String> {
+    let source = db::setup_with(source_url, &app_config.database)
+        .await
+        .context("Error setting up the source database connection")?;
+    // The destination is migrated, even when auto_migrate is disabled
+    let destination = db::connect(destination_url, &app_config.database)
+        .await
+        .context("Error setting up the destination database connection")?;
+    db::run_migrations(&destination)
+        .await
+        .context("Error migrating the destination database")?;
+
+    let summary = copy_database(&source, &destination, batch_size).await?;
+    if !summary.is_complete() {
+        bail!("The copy is not complete, the databases differ:\n{summary}");
+    }
+
+    // Processes that already use the destination reload the reminders they depend on
+    let event_bus = EventBus::new(app_config).context("Error setting up the event bus")?;
+    event_bus.send(StorageEvent::RemindersChanged);
+    event_bus.flush().await;
+
+    Ok(summary.to_string())
+}
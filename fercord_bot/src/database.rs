@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+
+use fercord_common::cli::DbCommands;
+use fercord_common::prelude::*;
+use fercord_storage::db::{self, MigrationState};
+
+/// Handle a `db` subcommand from the command line and return the output for the user.
+///
+/// The schema is left alone when connecting, so these also work when it is behind or ahead of this build.
+pub(crate) async fn db_command(app_config: &DiscordConfig, command: &DbCommands) -> Result<String> {
+    let db_pool = db::connect(&app_config.database_url, &app_config.database)
+        .await
+        .context("Error setting up database connection")?;
+
+    match command {
+        DbCommands::Status => {
+            let mut output = String::new();
+
+            for migration in db::migration_status(&db_pool).await? {
+                output.push_str(&format!(
+                    "{} {}: {}\n",
+                    migration.version, migration.description, migration.state
+                ));
+            }
+
+            Ok(output)
+        }
+        DbCommands::Migrate => {
+            let applied = db::run_migrations(&db_pool).await?;
+
+            Ok(if applied.is_empty() {
+                "The database is up to date".to_string()
+            } else {
+                format!("Applied migrations {:?}", applied)
+            })
+        }
+        DbCommands::Revert { version, dry_run } => {
+            if *dry_run {
+                let reverted: Vec<i64> = db::migration_status(&db_pool)
+                    .await?
+                    .iter()
+                    .rev()
+                    .filter(|m| m.state == MigrationState::Applied && m.version > *version)
+                    .map(|m| m.version)
+                    .collect();
+
+                return Ok(format!("Would revert migrations {:?}", reverted));
+            }
+
+            let reverted = db::revert_migrations(&db_pool, *version).await?;
+
+            Ok(format!("Reverted migrations {:?}", reverted))
+        }
+    }
+}
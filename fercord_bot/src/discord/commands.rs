@@ -1,4 +1,5 @@
 use std::num::NonZeroU8;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use chrono::{DateTime, Utc};
@@ -9,10 +10,18 @@ use rand::prelude::*;
 use tracing::{debug, event, field, trace_span, warn, Level};
 
 use crate::discord::Context;
-use fercord_storage::prelude::{guild_timezone::GuildTimezone, *};
+use crate::job;
+use crate::ServerData;
+use fercord_common::prelude::SessionStoreKind;
+use fercord_storage::prelude::*;
+use fercord_storage::privacy;
 
 const FROM_NOW: &str = "from now";
+/// The maximum amount of timezones we suggest for an invalid timezone.
+const MAX_TIMEZONE_SUGGESTIONS: usize = 5;
 const AT: &str = "at";
+/// How long `/privacy delete` waits for the user to confirm.
+const PRIVACY_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Register slash commands
 #[poise::command(prefix_command)]
@@ -46,26 +55,51 @@ pub async fn timezone(
 
     ctx.defer_ephemeral().await?;
 
-    if let Some(guild_id) = ctx.guild_id() {
-        span.record("guild_id", field::debug(&guild_id));
+    let parsed_timezone = match parse_timezone(&timezone) {
+        Ok(parsed_timezone) => parsed_timezone,
+        Err(suggestions) => {
+            event!(parent: &span, Level::DEBUG, ?suggestions, "Received an invalid timezone");
+            ctx.say(invalid_timezone_message(&timezone, &suggestions))
+                .await?;
 
-        let guild_timezone = GuildTimezone {
-            guild_id: guild_id.get(),
-            timezone: timezone.clone(),
-        };
+            return Ok(());
+        }
+    };
 
-        debug!(?guild_timezone, "Setting timezone for guild");
-        let kv_client = &ctx.data().kv_client;
+    if let Some(guild_id) = ctx.guild_id() {
+        span.record("guild_id", field::debug(&guild_id));
 
-        match kv_client.save_json(guild_timezone).await {
+        let data = ctx.data();
+        let mut guild_settings = data
+            .settings_cache
+            .get(&data.db_pool, &data.kv_client, guild_id.get())
+            .await?
+            .unwrap_or_else(|| GuildSettings::new(guild_id.get()));
+        guild_settings.timezone = Some(parsed_timezone);
+
+        debug!(?guild_settings, "Setting timezone for guild");
+
+        match data
+            .settings_cache
+            .save(
+                &data.db_pool,
+                &data.kv_client,
+                &guild_settings,
+                Some(&data.event_bus),
+            )
+            .await
+        {
             Ok(_) => {
                 event!(
                     parent: &span,
                     Level::DEBUG,
                     "Successfully set the timezone for this server"
                 );
-                ctx.say(format!("Set timezone {} for the server.", &timezone))
-                    .await?;
+                ctx.say(format!(
+                    "Set timezone {} for the server.",
+                    parsed_timezone.name()
+                ))
+                .await?;
 
                 Ok(())
             }
@@ -86,6 +120,257 @@ pub async fn timezone(
     }
 }
 
+/// Manage the background jobs. Only available to the owners of the bot.
+#[poise::command(
+    slash_command,
+    owners_only,
+    subcommands("jobs_list", "jobs_run", "jobs_pause", "jobs_resume")
+)]
+pub async fn jobs(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// List all background jobs and whether they are paused.
+#[poise::command(slash_command, owners_only, rename = "list")]
+pub async fn jobs_list(ctx: Context<'_>) -> Result<()> {
+    let span = trace_span!("fercord.discord.jobs.list");
+    let _enter = span.enter();
+
+    ctx.defer_ephemeral().await?;
+
+    let data = ctx.data();
+    let mut lines = Vec::with_capacity(data.jobs.len());
+    for job in &data.jobs {
+        let state = if job::is_paused(job.name(), &data.kv_client).await? {
+            "paused"
+        } else {
+            "active"
+        };
+        lines.push(format!("* `{}`: {}", job.name(), state));
+    }
+
+    ctx.say(lines.join("\n")).await?;
+
+    Ok(())
+}
+
+/// Run a background job right now, even when it is paused.
+#[poise::command(slash_command, owners_only, rename = "run")]
+pub async fn jobs_run(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_job"]
+    #[description = "The name of the job"]
+    name: String,
+) -> Result<()> {
+    let span = trace_span!("fercord.discord.jobs.run", job = &name);
+    let _enter = span.enter();
+
+    ctx.defer_ephemeral().await?;
+
+    let data = ctx.data();
+    let job = job::find_job(&data.jobs, &name)?;
+    job::trigger_job(
+        job,
+        &data.config,
+        &data.kv_client,
+        &data.db_pool,
+        &data.discord_sender,
+        &data.event_bus,
+    )
+    .await?;
+
+    ctx.say(format!("Job `{}` completed.", name)).await?;
+
+    Ok(())
+}
+
+/// Pause a background job on every instance of the bot.
+#[poise::command(slash_command, owners_only, rename = "pause")]
+pub async fn jobs_pause(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_job"]
+    #[description = "The name of the job"]
+    name: String,
+) -> Result<()> {
+    set_job_paused(ctx, name, true).await
+}
+
+/// Resume a paused background job on every instance of the bot.
+#[poise::command(slash_command, owners_only, rename = "resume")]
+pub async fn jobs_resume(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_job"]
+    #[description = "The name of the job"]
+    name: String,
+) -> Result<()> {
+    set_job_paused(ctx, name, false).await
+}
+
+async fn set_job_paused(ctx: Context<'_>, name: String, paused: bool) -> Result<()> {
+    let span = trace_span!("fercord.discord.jobs.pause", job = &name, paused);
+    let _enter = span.enter();
+
+    ctx.defer_ephemeral().await?;
+
+    let data = ctx.data();
+    let job = job::find_job(&data.jobs, &name)?;
+    job::set_paused(job.name(), paused, &data.kv_client).await?;
+
+    let action = if paused { "Paused" } else { "Resumed" };
+    ctx.say(format!("{} job `{}`.", action, name)).await?;
+
+    Ok(())
+}
+
+/// Autocomplete renderer for the registered jobs.
+async fn autocomplete_job(ctx: Context<'_>, partial: &str) -> impl Iterator<Item = String> {
+    let partial = partial.to_lowercase();
+
+    ctx.data()
+        .jobs
+        .iter()
+        .map(|j| j.name().to_string())
+        .filter(|name| name.contains(&partial))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// See or delete what fercord stores about you.
+#[poise::command(slash_command, subcommands("privacy_export", "privacy_delete"))]
+pub async fn privacy(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Receive everything fercord stores about you in a direct message.
+#[poise::command(slash_command, rename = "export")]
+pub async fn privacy_export(ctx: Context<'_>) -> Result<()> {
+    let user_id = ctx.author().id;
+    let span = trace_span!("fercord.discord.privacy.export", %user_id);
+    let _enter = span.enter();
+
+    ctx.defer_ephemeral().await?;
+
+    let data = ctx.data();
+    let export = privacy::export_user_data(
+        &data.db_pool,
+        data.reminder_cipher.as_deref(),
+        user_id.get(),
+    )
+    .await?;
+    let json = serde_json::to_vec_pretty(&export).context("Error serializing user data")?;
+
+    let message = serenity::CreateMessage::new()
+        .content("This is everything fercord stores about you.")
+        .add_file(serenity::CreateAttachment::bytes(
+            json,
+            format!("fercord-{}.json", user_id),
+        ));
+    match ctx.author().direct_message(ctx, message).await {
+        Ok(_) => {
+            ctx.say("Sent you a direct message with your data.").await?;
+        }
+        Err(e) => {
+            event!(Level::DEBUG, ?e, "Error sending user data");
+            ctx.say(
+                "Could not send you a direct message. \
+                Allow direct messages from this server and try again.",
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What `/privacy delete` asks before it deletes anything. Only sessions in the database can be found by user.
+fn privacy_delete_prompt(session_store: SessionStoreKind) -> &'static str {
+    match session_store {
+        SessionStoreKind::Database => {
+            "This deletes all your reminders and logs you out of the web API, it can not be undone. \
+            Are you sure?"
+        }
+        SessionStoreKind::Redis => {
+            "This deletes all your reminders, it can not be undone. \
+            It does not log you out of the web API, log out there to end your session. Are you sure?"
+        }
+    }
+}
+
+/// Delete everything fercord stores about you, after you confirm.
+#[poise::command(slash_command, rename = "delete")]
+pub async fn privacy_delete(ctx: Context<'_>) -> Result<()> {
+    let user_id = ctx.author().id;
+    let span = trace_span!("fercord.discord.privacy.delete", %user_id);
+    let _enter = span.enter();
+
+    let confirm_id = format!("{}-confirm", ctx.id());
+    let cancel_id = format!("{}-cancel", ctx.id());
+    let buttons = serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(&confirm_id)
+            .label("Delete everything")
+            .style(serenity::ButtonStyle::Danger),
+        serenity::CreateButton::new(&cancel_id)
+            .label("Cancel")
+            .style(serenity::ButtonStyle::Secondary),
+    ]);
+    let reply = ctx
+        .send(
+            poise::CreateReply::default()
+                .content(privacy_delete_prompt(
+                    ctx.data().config.session_store_kind(),
+                ))
+                .components(vec![buttons])
+                .ephemeral(true),
+        )
+        .await?;
+
+    let (confirm, cancel) = (confirm_id.clone(), cancel_id.clone());
+    let press = serenity::ComponentInteractionCollector::new(ctx)
+        .author_id(user_id)
+        .timeout(PRIVACY_CONFIRMATION_TIMEOUT)
+        .filter(move |press| press.data.custom_id == confirm || press.data.custom_id == cancel)
+        .await;
+
+    let content = match &press {
+        Some(press) if press.data.custom_id == confirm_id => {
+            let data = ctx.data();
+            let summary =
+                privacy::delete_user_data(&data.db_pool, user_id.get(), Some(&data.event_bus))
+                    .await?;
+            format!("Deleted {}.", summary)
+        }
+        Some(_) => "Cancelled, nothing was deleted.".to_string(),
+        None => "You did not confirm in time, nothing was deleted.".to_string(),
+    };
+
+    match press {
+        Some(press) => {
+            press
+                .create_response(
+                    ctx,
+                    serenity::CreateInteractionResponse::UpdateMessage(
+                        serenity::CreateInteractionResponseMessage::new()
+                            .content(content)
+                            .components(vec![]),
+                    ),
+                )
+                .await?;
+        }
+        None => {
+            reply
+                .edit(
+                    ctx,
+                    poise::CreateReply::default()
+                        .content(content)
+                        .components(vec![]),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Roll dice
 ///
 /// * count: The amount of dice to roll
@@ -155,24 +440,37 @@ pub async fn reminder(
 
     ctx.defer_ephemeral().await?;
 
-    let kv_client = &ctx.data().kv_client;
+    let data = ctx.data();
     let guild_timezone = match ctx.guild_id() {
         Some(guild_id) => {
             span.record("guild_id", field::display(&guild_id));
             event!(Level::TRACE, "Retrieving timezone for guild");
 
-            if let Ok(guild_timezone) = get_guild_timezone(kv_client, &guild_id).await {
-                span.record("guild_timezone", field::debug(&guild_timezone));
-                event!(
-                    Level::DEBUG,
-                    "Found specific timezone {:?} for guild {}",
-                    guild_timezone,
-                    guild_id
-                );
-
-                guild_timezone
-            } else {
-                Tz::UTC
+            // Guessing UTC when the lookup failed would silently set the reminder at the wrong time
+            match get_guild_timezone(data, &guild_id).await {
+                Ok(Some(guild_timezone)) => {
+                    span.record("guild_timezone", field::debug(&guild_timezone));
+                    event!(
+                        Level::DEBUG,
+                        "Found specific timezone {:?} for guild {}",
+                        guild_timezone,
+                        guild_id
+                    );
+
+                    guild_timezone
+                }
+                Ok(None) => {
+                    event!(Level::DEBUG, "Guild has no timezone, using UTC");
+
+                    Tz::UTC
+                }
+                Err(e) => {
+                    warn!(?e, "Error looking up the timezone of the guild");
+
+                    return Err(anyhow!(
+                        "Could not look up the timezone of this server, please try again later."
+                    ));
+                }
             }
         }
         None => {
@@ -210,9 +508,13 @@ pub async fn reminder(
             who: ctx.author().id.into(),
             when: parsed_datetime.with_timezone(&Utc),
             what: what.clone(),
+            what_encrypted: false,
+            undeliverable_since: None,
         };
 
-        let repo = Reminder::repository(&ctx.data().db_pool);
+        let repo = Reminder::repository(&ctx.data().db_pool)
+            .with_cipher(ctx.data().reminder_cipher.as_deref())
+            .with_events(Some(&ctx.data().event_bus));
         let id = repo.insert(&reminder).await?;
 
         event!(Level::TRACE, "Saved event with id {}", id);
@@ -231,23 +533,14 @@ pub async fn reminder(
     Ok(())
 }
 
-async fn get_guild_timezone(client: &KVClient, guild_id: &serenity::GuildId) -> Result<Tz> {
-    let kv_identity = GuildTimezone {
-        guild_id: guild_id.get(),
-        timezone: String::new(),
-    };
-
-    let guild_timezone = client.get_json(&kv_identity).await?;
-
-    if guild_timezone.is_none() {
-        return Ok(Tz::UTC);
-    }
+/// The timezone of the guild, `None` when it has not set one.
+async fn get_guild_timezone(data: &ServerData, guild_id: &serenity::GuildId) -> Result<Option<Tz>> {
+    let settings = data
+        .settings_cache
+        .get(&data.db_pool, &data.kv_client, guild_id.get())
+        .await?;
 
-    guild_timezone
-        .unwrap()
-        .timezone
-        .parse::<Tz>()
-        .map_err(|e| anyhow!(e))
+    Ok(settings.and_then(|settings| settings.timezone))
 }
 
 pub(crate) fn parse_human_time<Tz>(
@@ -321,10 +614,7 @@ pub(crate) fn clean_input(natural_input: String) -> String {
 
 /// Autocomplete renderer for the timezones list.
 #[allow(clippy::unnecessary_to_owned)]
-async fn autocomplete_timezone<'a>(
-    _ctx: Context<'_>,
-    partial: &str,
-) -> impl Iterator<Item = String> {
+async fn autocomplete_timezone(_ctx: Context<'_>, partial: &str) -> impl Iterator<Item = String> {
     let timezones: Vec<String> = filter_timezones(partial).collect();
     match timezones.len() {
         1..=100 => timezones.into_iter(),
@@ -349,6 +639,48 @@ fn filter_timezones(pattern: &str) -> impl Iterator<Item = String> + '_ {
         .map(|tz| tz.to_string())
 }
 
+/// Find the timezone that was meant, ignoring case.
+///
+/// When there is no such timezone, the names of the timezones that look the most like it are returned instead.
+fn parse_timezone(input: &str) -> std::result::Result<Tz, Vec<String>> {
+    let input = input.trim();
+    if let Some(timezone) = TZ_VARIANTS
+        .iter()
+        .find(|tz| tz.name().eq_ignore_ascii_case(input))
+    {
+        return Ok(*timezone);
+    }
+
+    let mut suggestions: Vec<String> = filter_timezones(input)
+        .take(MAX_TIMEZONE_SUGGESTIONS)
+        .collect();
+    if suggestions.is_empty() {
+        // Most likely a typo in the city, the start of it usually is right
+        let city = input.rsplit('/').next().unwrap_or(input);
+        let city_start: String = city.chars().take(3).collect();
+        if !city_start.is_empty() {
+            suggestions = filter_timezones(&city_start)
+                .take(MAX_TIMEZONE_SUGGESTIONS)
+                .collect();
+        }
+    }
+
+    Err(suggestions)
+}
+
+fn invalid_timezone_message(timezone: &str, suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        format!(
+            "I don't know the timezone {timezone}. Use the IANA name of the timezone, like Europe/Brussels."
+        )
+    } else {
+        format!(
+            "I don't know the timezone {timezone}. Did you mean one of these? {}",
+            suggestions.join(", ")
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
@@ -375,4 +707,21 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn timezone_is_parsed_ignoring_case() {
+        assert_eq!(Ok(Tz::Europe__Brussels), parse_timezone("europe/BRUSSELS"));
+        assert_eq!(Ok(Tz::UTC), parse_timezone(" UTC "));
+    }
+
+    #[test]
+    fn invalid_timezone_gets_suggestions() {
+        let Err(suggestions) = parse_timezone("Europe/Brusels") else {
+            panic!("Europe/Brusels is not a timezone");
+        };
+        assert!(suggestions.contains(&String::from("Europe/Brussels")));
+        assert!(suggestions.len() <= MAX_TIMEZONE_SUGGESTIONS);
+
+        assert_eq!(Err(Vec::new()), parse_timezone("Qqq/Xxx"));
+    }
 }
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use chrono::Utc;
+use poise::serenity_prelude as serenity;
+use tracing::{event, Level};
+
+use fercord_storage::prelude::*;
+
+use crate::ServerData;
+
+/// Handle the gateway events that change which guilds and channels the bot can send reminders to.
+///
+/// The reminders of guilds the bot was removed from and of deleted channels are marked undeliverable. The
+/// `guild_purge` job deletes them, together with the settings of those guilds, after the grace period.
+pub async fn event_handler(
+    _ctx: &serenity::Context,
+    event: &serenity::FullEvent,
+    _framework: poise::FrameworkContext<'_, ServerData, anyhow::Error>,
+    data: &ServerData,
+) -> Result<()> {
+    match event {
+        serenity::FullEvent::GuildDelete { incomplete, .. } => {
+            // A guild that is unavailable because of an outage comes back by itself
+            if incomplete.unavailable {
+                event!(Level::DEBUG, guild_id = %incomplete.id, "Guild became unavailable");
+                return Ok(());
+            }
+
+            guild_left(&data.db_pool, &data.event_bus, incomplete.id.get()).await
+        }
+        serenity::FullEvent::GuildCreate { guild, .. } => {
+            guild_joined(&data.db_pool, &data.event_bus, guild.id.get()).await
+        }
+        serenity::FullEvent::ChannelDelete { channel, .. } => {
+            channel_deleted(&data.db_pool, &data.event_bus, channel.id.get()).await
+        }
+        serenity::FullEvent::ThreadDelete { thread, .. } => {
+            channel_deleted(&data.db_pool, &data.event_bus, thread.id.get()).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The bot was removed from the guild, or the guild was deleted.
+async fn guild_left(db_pool: &AnyPool, event_bus: &EventBus, guild_id: u64) -> Result<()> {
+    let marked = leave_guild(db_pool, guild_id, &Utc::now(), Some(event_bus)).await?;
+    event!(
+        Level::INFO,
+        guild_id,
+        marked,
+        "Removed from guild, its data is purged after the grace period"
+    );
+
+    Ok(())
+}
+
+/// Guilds are created on every connection, but only one the bot left before has a departure.
+async fn guild_joined(db_pool: &AnyPool, event_bus: &EventBus, guild_id: u64) -> Result<()> {
+    if let Some(departure) = rejoin_guild(db_pool, guild_id, Some(event_bus)).await? {
+        event!(
+            Level::INFO,
+            guild_id,
+            left_at = %departure.left_at,
+            "Added to a guild again before its data was purged"
+        );
+    }
+
+    Ok(())
+}
+
+async fn channel_deleted(db_pool: &AnyPool, event_bus: &EventBus, channel_id: u64) -> Result<()> {
+    let marked = Reminder::repository(db_pool)
+        .with_events(Some(event_bus))
+        .mark_undeliverable(
+            &ReminderFilter {
+                channel: Some(channel_id),
+                ..Default::default()
+            },
+            &Utc::now(),
+        )
+        .await?;
+    if marked > 0 {
+        event!(
+            Level::INFO,
+            channel_id,
+            marked,
+            "Channel was deleted, its reminders are purged after the grace period"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chrono::TimeDelta;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+
+    fn reminder(server: u64, channel: u64) -> Reminder {
+        Reminder {
+            id: 0,
+            who: 1234,
+            when: Utc::now() + TimeDelta::minutes(1),
+            what: "the thing".into(),
+            server,
+            channel,
+            what_encrypted: false,
+            undeliverable_since: None,
+        }
+    }
+
+    async fn is_due(db_pool: &AnyPool, id: i64) -> Result<bool> {
+        let due = Reminder::repository(db_pool)
+            .get_reminders_before(&(Utc::now() + TimeDelta::hours(1)))
+            .await?;
+
+        Ok(due.iter().any(|r| r.id == id))
+    }
+
+    #[tokio::test]
+    async fn leaving_and_rejoining_a_guild_pauses_its_reminders() -> Result<()> {
+        let db_pool = db::setup("sqlite::memory:").await?;
+        let event_bus = EventBus::in_process();
+        let repo = Reminder::repository(&db_pool);
+        let id = repo.insert(&reminder(42, 4242)).await?;
+
+        guild_left(&db_pool, &event_bus, 42).await?;
+        assert!(!is_due(&db_pool, id).await?);
+
+        guild_joined(&db_pool, &event_bus, 42).await?;
+        assert!(is_due(&db_pool, id).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_channels_stop_their_reminders() -> Result<()> {
+        let db_pool = db::setup("sqlite::memory:").await?;
+        let repo = Reminder::repository(&db_pool);
+        let deleted = repo.insert(&reminder(42, 4242)).await?;
+        let kept = repo.insert(&reminder(42, 4343)).await?;
+        let event_bus = EventBus::in_process();
+        let mut events = event_bus.subscribe().await?;
+
+        channel_deleted(&db_pool, &event_bus, 4242).await?;
+
+        assert_eq!(
+            Some(StorageEvent::RemindersChanged),
+            events.next().await.transpose()?
+        );
+
+        assert!(!is_due(&db_pool, deleted).await?);
+        assert!(is_due(&db_pool, kept).await?);
+
+        Ok(())
+    }
+}
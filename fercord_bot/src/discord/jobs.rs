@@ -1,60 +1,134 @@
+use std::sync::Arc;
+
 use chrono::TimeDelta;
 use chrono::Utc;
 use poise::async_trait;
 use poise::serenity_prelude as serenity;
 
-use tracing::{debug_span, event, field, Level};
+use tracing::{debug_span, event, field, Instrument, Level, Span};
 
+use fercord_storage::maintenance::{run_maintenance, MaintenanceSummary};
+use fercord_storage::prelude::model::guild_departure::*;
 use fercord_storage::prelude::model::reminder::*;
 
 use crate::job::{Job, JobArgs, JobResult};
 
+/// The amount of due reminders that are read and sent together, the rest waits in the database.
+const REMINDER_BATCH_SIZE: u32 = 100;
+
 struct RemindersJob;
 
-#[async_trait]
-impl Job for RemindersJob {
-    async fn run(&self, args: &JobArgs) -> JobResult {
-        let span = debug_span!("fercord.jobs.reminders", reminder_id = field::Empty);
-        let _enter = span.enter();
+impl RemindersJob {
+    /// Send the reminders and log the ones that could not be delivered.
+    async fn send_reminders(args: &JobArgs, span: &Span, reminders: &[Reminder]) {
+        let messages = reminders
+            .iter()
+            .map(|reminder| {
+                let channel: serenity::ChannelId = reminder.channel.into();
+                let user: serenity::UserId = reminder.who.into();
 
-        let repo: ReminderRepo = Reminder::repository(&args.db_pool);
-        let expired_reminders = repo.get_reminders_since(&args.last_run_time).await?;
+                let message = format!(
+                    "{} I was supposed to remind you of {}",
+                    serenity::Mention::from(user),
+                    reminder.what
+                );
 
-        event!(
-            Level::DEBUG,
-            "Found {} reminders since {}",
-            &expired_reminders.len(),
-            &args.last_run_time
-        );
+                (channel, message)
+            })
+            .collect();
 
-        let discord_client = args.discord_client.http();
+        let results = args.discord_sender.send_messages(messages).await;
 
-        for reminder in expired_reminders {
-            // get discord client and send reminders
-            span.record("reminder_id", field::display(reminder.id));
+        for (reminder, result) in reminders.iter().zip(results) {
+            if let Err(error) = result {
+                span.record("reminder_id", field::display(reminder.id));
+                event!(Level::ERROR, %error, "Error sending reminder {}", &reminder.id);
+            }
+        }
+    }
 
-            let channel: serenity::ChannelId = reminder.channel.into();
-            let user = discord_client.get_user(reminder.who.into()).await?;
+    /// Send the reminders that became due since the last run, in pages.
+    async fn send_due_reminders(args: &JobArgs) -> JobResult {
+        let span = Span::current();
+        let repo: ReminderRepo =
+            Reminder::repository(&args.db_pool).with_cipher(args.reminder_cipher.as_deref());
+        // Every page is read before it is sent, so no database connection is held while waiting on Discord
+        let mut options = ReminderListOptions {
+            filter: ReminderFilter {
+                due_from: Some(args.last_run_time),
+                // The next run starts where this one ends, also when this run takes a while
+                due_before: Some(args.run_time),
+                deliverable_only: true,
+                ..Default::default()
+            },
+            limit: REMINDER_BATCH_SIZE,
+            ..Default::default()
+        };
 
-            let message = serenity::CreateMessage::new().content(format!(
-                "{} I was supposed to remind you of {}",
-                serenity::Mention::from(user.id),
-                reminder.what
-            ));
+        let mut found = 0;
+        loop {
+            // Any other error fails the job, so the same window is tried again on the next run
+            let page = repo.list_readable(&options).await?;
 
-            if let Err(error) = channel.send_message(&discord_client, message).await {
-                event!(Level::ERROR, %error, "Error sending reminder {}", &reminder.id);
+            let mut batch = Vec::with_capacity(page.items.len());
+            for reminder in page.items {
+                match reminder {
+                    Ok(reminder) if reminder.what_encrypted => event!(
+                        Level::ERROR,
+                        reminder_id = reminder.id,
+                        "Skipping an encrypted reminder, encryption is not configured"
+                    ),
+                    Ok(reminder) => batch.push(reminder),
+                    // Failing the job would send the other reminders again on the next run
+                    Err(unreadable) => event!(
+                        Level::ERROR,
+                        reminder_id = unreadable.id,
+                        error = ?unreadable.error,
+                        "Skipping a reminder that could not be read"
+                    ),
+                }
+            }
+            found += batch.len();
+            Self::send_reminders(args, &span, &batch).await;
+
+            match page.next {
+                Some(next) => options.after = Some(next),
+                None => break,
             }
         }
 
+        event!(
+            Level::DEBUG,
+            "Found {} reminders since {}",
+            found,
+            &args.last_run_time
+        );
+
         Ok(())
     }
 }
 
+#[async_trait]
+impl Job for RemindersJob {
+    fn name(&self) -> &'static str {
+        "reminders"
+    }
+
+    async fn run(&self, args: &JobArgs) -> JobResult {
+        let span = debug_span!("fercord.jobs.reminders", reminder_id = field::Empty);
+
+        Self::send_due_reminders(args).instrument(span).await
+    }
+}
+
 struct RemindersCleanupJob;
 
 #[async_trait]
 impl Job for RemindersCleanupJob {
+    fn name(&self) -> &'static str {
+        "reminders_cleanup"
+    }
+
     async fn run(&self, args: &JobArgs) -> JobResult {
         let discord_config = &args.discord_config;
         let job_interval = discord_config.job_interval_min;
@@ -67,23 +141,318 @@ impl Job for RemindersCleanupJob {
             "fercord.jobs.reminders_cleanup",
             cutoff_time = field::display(now)
         );
-        let _enter = span.enter();
 
-        event!(Level::DEBUG, "Starting reminder cleanup");
+        async {
+            event!(Level::DEBUG, "Starting reminder cleanup");
 
-        let repo = Reminder::repository(&args.db_pool);
+            let repo = Reminder::repository(&args.db_pool).with_events(Some(&args.event_bus));
 
-        if let Ok(expired_reminders) = repo.get_reminders_before(&now).await {
-            repo.delete_reminders(expired_reminders).await?;
+            let deleted = repo.delete_reminders_before(&now).await?;
+            event!(Level::DEBUG, deleted, "Finished reminder cleanup");
+
+            Ok(())
         }
+        .instrument(span)
+        .await
+    }
+}
 
-        Ok(())
+struct GuildPurgeJob;
+
+#[async_trait]
+impl Job for GuildPurgeJob {
+    fn name(&self) -> &'static str {
+        "guild_purge"
+    }
+
+    async fn run(&self, args: &JobArgs) -> JobResult {
+        let grace_hours = args.discord_config.guild_purge_grace_hours;
+        let cutoff = Utc::now() - TimeDelta::try_hours(grace_hours as i64).unwrap_or_default();
+        let span = debug_span!(
+            "fercord.jobs.guild_purge",
+            cutoff_time = field::display(cutoff),
+            guild_id = field::Empty
+        );
+
+        async {
+            let departures = GuildDeparture::repository(&args.db_pool)
+                .guild_departures_before(&cutoff)
+                .await?;
+            for departure in departures {
+                span.record("guild_id", departure.guild_id);
+                let reminders = purge_guild(
+                    &args.db_pool,
+                    &args.kv_client,
+                    departure.guild_id,
+                    Some(&args.event_bus),
+                )
+                .await?;
+                event!(
+                    Level::INFO,
+                    guild_id = departure.guild_id,
+                    left_at = %departure.left_at,
+                    reminders,
+                    "Purged the data of a guild the bot was removed from"
+                );
+            }
+
+            // What is left are the reminders of deleted channels
+            let reminders = Reminder::repository(&args.db_pool)
+                .with_events(Some(&args.event_bus))
+                .delete_undeliverable_before(&cutoff)
+                .await?;
+            if reminders > 0 {
+                event!(
+                    Level::INFO,
+                    reminders,
+                    "Purged the reminders of deleted channels"
+                );
+            }
+
+            Ok(())
+        }
+        .instrument(span.clone())
+        .await
+    }
+}
+
+struct MaintenanceJob;
+
+#[async_trait]
+impl Job for MaintenanceJob {
+    fn name(&self) -> &'static str {
+        "maintenance"
+    }
+
+    async fn run(&self, args: &JobArgs) -> JobResult {
+        let span = debug_span!("fercord.jobs.maintenance");
+
+        async {
+            let summary = run_maintenance(&args.db_pool, &Utc::now()).await?;
+            if summary != MaintenanceSummary::default() {
+                event!(Level::INFO, %summary, "Cleaned up expired data");
+            }
+
+            Ok(())
+        }
+        .instrument(span)
+        .await
     }
 }
 
-pub fn reminders() -> Box<dyn Job> {
-    Box::new(RemindersJob {})
+/// All jobs that are run by the scheduler.
+pub fn all() -> Vec<Arc<dyn Job>> {
+    vec![
+        reminders(),
+        reminders_cleanup(),
+        guild_purge(),
+        maintenance(),
+    ]
 }
-pub fn reminders_cleanup() -> Box<dyn Job> {
-    Box::new(RemindersCleanupJob {})
+
+pub fn reminders() -> Arc<dyn Job> {
+    Arc::new(RemindersJob {})
+}
+pub fn reminders_cleanup() -> Arc<dyn Job> {
+    Arc::new(RemindersCleanupJob {})
+}
+pub fn guild_purge() -> Arc<dyn Job> {
+    Arc::new(GuildPurgeJob {})
+}
+pub fn maintenance() -> Arc<dyn Job> {
+    Arc::new(MaintenanceJob {})
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use poise::serenity_prelude as serenity;
+
+    use fercord_storage::encryption::FieldCipher;
+    use fercord_storage::prelude::*;
+
+    use super::*;
+    use crate::discord::sender::fake::{RecordingSender, SentMessage};
+    use crate::job::tests::test_job_args_with;
+
+    fn reminder_at(when: DateTime<Utc>, what: &str) -> Reminder {
+        Reminder {
+            id: 0,
+            who: 1234,
+            when,
+            what: what.into(),
+            server: 42,
+            channel: 4242,
+            what_encrypted: false,
+            undeliverable_since: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reminders_job_sends_due_reminders() -> Result<()> {
+        let sender = Arc::new(RecordingSender::default());
+        let now = Utc::now();
+        let args = test_job_args_with(sender.clone(), now - TimeDelta::minutes(5)).await?;
+        let repo = Reminder::repository(&args.db_pool);
+        repo.insert(&reminder_at(now - TimeDelta::minutes(1), "the due thing"))
+            .await?;
+        repo.insert(&reminder_at(now - TimeDelta::hours(1), "the old thing"))
+            .await?;
+        repo.insert(&reminder_at(now + TimeDelta::hours(1), "the future thing"))
+            .await?;
+
+        reminders().run(&args).await?;
+
+        assert_eq!(
+            vec![SentMessage {
+                channel: serenity::ChannelId::new(4242),
+                content: "<@1234> I was supposed to remind you of the due thing".into(),
+            }],
+            sender.sent()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reminders_job_sends_every_batch() -> Result<()> {
+        let sender = Arc::new(RecordingSender::default());
+        let now = Utc::now();
+        let args = test_job_args_with(sender.clone(), now - TimeDelta::minutes(5)).await?;
+        let due: Vec<Reminder> = (0..REMINDER_BATCH_SIZE + 10)
+            .map(|i| reminder_at(now - TimeDelta::minutes(1), &format!("thing {i}")))
+            .collect();
+        Reminder::repository(&args.db_pool)
+            .insert_many(&due)
+            .await?;
+
+        reminders().run(&args).await?;
+
+        assert_eq!(due.len(), sender.sent().len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reminders_job_skips_unreadable_reminders() -> Result<()> {
+        let sender = Arc::new(RecordingSender::default());
+        let now = Utc::now();
+        let args = test_job_args_with(sender.clone(), now - TimeDelta::minutes(5)).await?;
+        let cipher = FieldCipher::new("elsewhere", &[1; 32])?;
+        // The job has no cipher, so it can not read this one
+        Reminder::repository(&args.db_pool)
+            .with_cipher(Some(&cipher))
+            .insert(&reminder_at(
+                now - TimeDelta::minutes(2),
+                "the secret thing",
+            ))
+            .await?;
+        Reminder::repository(&args.db_pool)
+            .insert(&reminder_at(now - TimeDelta::minutes(1), "the due thing"))
+            .await?;
+
+        reminders().run(&args).await?;
+
+        assert_eq!(
+            vec!["<@1234> I was supposed to remind you of the due thing".to_owned()],
+            sender
+                .sent()
+                .into_iter()
+                .map(|m| m.content)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reminders_cleanup_job_deletes_expired_reminders() -> Result<()> {
+        let sender = Arc::new(RecordingSender::default());
+        let now = Utc::now();
+        let args = test_job_args_with(sender.clone(), now).await?;
+        let repo = Reminder::repository(&args.db_pool);
+        let expired_id = repo
+            .insert(&reminder_at(now - TimeDelta::days(1), "the expired thing"))
+            .await?;
+        let future_id = repo
+            .insert(&reminder_at(now + TimeDelta::hours(1), "the future thing"))
+            .await?;
+
+        reminders_cleanup().run(&args).await?;
+
+        assert!(
+            repo.get(expired_id).await?.is_none(),
+            "Expired reminder was not deleted"
+        );
+        assert!(
+            repo.get(future_id).await?.is_some(),
+            "Future reminder was deleted"
+        );
+        assert!(sender.sent().is_empty(), "Cleanup should not send messages");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn guild_purge_job_purges_after_the_grace_period() -> Result<()> {
+        let sender = Arc::new(RecordingSender::default());
+        let now = Utc::now();
+        // The grace period is an hour
+        let args = test_job_args_with(sender.clone(), now).await?;
+        let repo = Reminder::repository(&args.db_pool);
+        let mut left_long_ago = reminder_at(now + TimeDelta::hours(1), "the forgotten thing");
+        left_long_ago.server = 1;
+        let left_long_ago = repo.insert(&left_long_ago).await?;
+        let mut left_recently = reminder_at(now + TimeDelta::hours(1), "the recent thing");
+        left_recently.server = 2;
+        let left_recently = repo.insert(&left_recently).await?;
+        let deleted_channel = repo
+            .insert(&reminder_at(now + TimeDelta::hours(1), "the deleted thing"))
+            .await?;
+        leave_guild(&args.db_pool, 1, &(now - TimeDelta::hours(2)), None).await?;
+        leave_guild(&args.db_pool, 2, &(now - TimeDelta::minutes(5)), None).await?;
+        repo.mark_undeliverable(
+            &ReminderFilter {
+                channel: Some(4242),
+                ..Default::default()
+            },
+            &(now - TimeDelta::hours(2)),
+        )
+        .await?;
+
+        guild_purge().run(&args).await?;
+
+        assert!(repo.get(left_long_ago).await?.is_none());
+        assert!(repo.get(deleted_channel).await?.is_none());
+        assert!(repo.get(left_recently).await?.is_some());
+        let departures = GuildDeparture::repository(&args.db_pool);
+        assert!(departures.get_guild_departure(1).await?.is_none());
+        assert!(departures.get_guild_departure(2).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn maintenance_job_deletes_expired_sessions() -> Result<()> {
+        let sender = Arc::new(RecordingSender::default());
+        let now = Utc::now();
+        let args = test_job_args_with(sender.clone(), now).await?;
+        let repo = Session::repository(&args.db_pool);
+        for (key, expires_at) in [
+            ("expired", now - TimeDelta::minutes(1)),
+            ("live", now + TimeDelta::hours(1)),
+        ] {
+            repo.insert_session(&Session {
+                key: key.into(),
+                user_id: None,
+                state: "{}".into(),
+                expires_at,
+            })
+            .await?;
+        }
+
+        maintenance().run(&args).await?;
+
+        assert_eq!(0, repo.delete_sessions_expired_before(&now).await?);
+        assert!(repo.get_session("live").await?.is_some());
+        Ok(())
+    }
 }
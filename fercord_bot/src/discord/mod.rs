@@ -3,7 +3,10 @@ use crate::ServerData;
 pub type Context<'a> = poise::Context<'a, ServerData, anyhow::Error>;
 
 pub mod commands;
+pub mod events;
 pub mod jobs;
+pub mod queue;
+pub mod sender;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use poise::async_trait;
+use poise::serenity_prelude as serenity;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug_span, event, field, Instrument, Level};
+
+use fercord_common::prelude::*;
+
+use crate::discord::sender::{DiscordSender, SerenitySender};
+
+/// Maximum length of the content of a single Discord message.
+const MAX_MESSAGE_LEN: usize = 2000;
+/// How many times we try to deliver a batch before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Returned by a [`DiscordSender`] when Discord refused a message because of a rate limit.
+#[derive(Debug, Error)]
+#[error("Rate limited by Discord, retry after {retry_after:?} (global: {global})")]
+pub struct RateLimited {
+    pub retry_after: Duration,
+    /// Whether the global rate limit was hit, instead of the limit of a single route.
+    pub global: bool,
+}
+
+/// Outbound message queue for unsolicited messages (reminders and the like).
+///
+/// * Messages for the same channel are combined into as few messages as possible and are delivered in order.
+///   A message that is too long for Discord is split over multiple messages.
+/// * At most `concurrency` channels are delivered to at the same time.
+/// * A [`RateLimited`] error pauses the channel (or every channel, for the global limit) for the requested time
+///   before the batch is retried.
+///
+/// The serenity HTTP client already waits for its per-route and global buckets, this queue makes sure a large
+/// backlog does not pile up on them.
+#[derive(Clone)]
+pub struct OutboundQueue {
+    inner: Arc<dyn DiscordSender>,
+    limiter: Arc<Semaphore>,
+    global_blocked_until: Arc<Mutex<Option<Instant>>>,
+}
+
+/// A single message that is sent for one or more queued messages.
+#[derive(Debug, PartialEq, Eq)]
+struct Batch {
+    content: String,
+    /// The index of every queued message included in this batch.
+    indices: Vec<usize>,
+}
+
+impl OutboundQueue {
+    pub fn new(inner: Arc<dyn DiscordSender>, concurrency: usize) -> Self {
+        Self {
+            inner,
+            limiter: Arc::new(Semaphore::new(concurrency)),
+            global_blocked_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Create a queue that delivers through the serenity HTTP client, using the token and limits from the configuration.
+    pub fn from_config(config: &DiscordConfig) -> Self {
+        let http = serenity::HttpBuilder::new(&config.discord_token).build();
+
+        Self::new(
+            Arc::new(SerenitySender::new(Arc::new(http))),
+            config.message_concurrency.get(),
+        )
+    }
+
+    /// Deliver the batches for a single channel, in order.
+    async fn deliver_channel(
+        &self,
+        channel: serenity::ChannelId,
+        batches: Vec<Batch>,
+    ) -> Vec<(Vec<usize>, Result<(), String>)> {
+        let mut results = Vec::with_capacity(batches.len());
+
+        let Ok(_permit) = self.limiter.acquire().await else {
+            return batches
+                .into_iter()
+                .map(|b| (b.indices, Err("Outbound queue was closed".into())))
+                .collect();
+        };
+
+        for batch in batches {
+            let result = self
+                .deliver(channel, batch.content)
+                .await
+                .map_err(|e| format!("{e:#}"));
+            results.push((batch.indices, result));
+        }
+
+        results
+    }
+
+    /// Deliver a single message, retrying when we are rate limited.
+    async fn deliver(&self, channel: serenity::ChannelId, content: String) -> Result<()> {
+        let mut attempt = 1;
+
+        loop {
+            self.wait_for_global_limit().await;
+
+            let error = match self.inner.send_message(channel, content.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+
+            let Some(rate_limited) = error.downcast_ref::<RateLimited>() else {
+                return Err(error);
+            };
+            if attempt >= MAX_ATTEMPTS {
+                return Err(error);
+            }
+
+            event!(
+                Level::WARN,
+                %channel,
+                global = rate_limited.global,
+                "Rate limited, retrying in {:?}",
+                rate_limited.retry_after
+            );
+
+            if rate_limited.global {
+                let until = Instant::now() + rate_limited.retry_after;
+                let mut blocked_until = self.global_blocked_until.lock().unwrap();
+                *blocked_until = Some(blocked_until.map_or(until, |current| current.max(until)));
+            }
+            tokio::time::sleep(rate_limited.retry_after).await;
+
+            attempt += 1;
+        }
+    }
+
+    /// Wait until the global rate limit (if any) is over.
+    async fn wait_for_global_limit(&self) {
+        let blocked_until = *self.global_blocked_until.lock().unwrap();
+
+        if let Some(until) = blocked_until {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+}
+
+#[async_trait]
+impl DiscordSender for OutboundQueue {
+    async fn send_message(&self, channel: serenity::ChannelId, content: String) -> Result<()> {
+        self.send_messages(vec![(channel, content)])
+            .await
+            .pop()
+            .unwrap_or(Ok(()))
+    }
+
+    async fn send_messages(&self, messages: Vec<(serenity::ChannelId, String)>) -> Vec<Result<()>> {
+        let message_count = messages.len();
+        let mut results: Vec<Result<(), String>> = Vec::with_capacity(message_count);
+        results.resize_with(message_count, || Ok(()));
+
+        let mut delivery_set = JoinSet::new();
+        let mut channel_indices = HashMap::new();
+
+        for (channel, batches) in batch_per_channel(messages) {
+            let queue = self.clone();
+            let indices: Vec<usize> = batches.iter().flat_map(|b| b.indices.clone()).collect();
+            let span = debug_span!(
+                "fercord.discord.queue",
+                channel = field::display(channel),
+                batches = batches.len()
+            );
+
+            let handle = delivery_set.spawn(
+                async move { queue.deliver_channel(channel, batches).await }.instrument(span),
+            );
+            channel_indices.insert(handle.id(), indices);
+        }
+
+        while let Some(joined) = delivery_set.join_next_with_id().await {
+            match joined {
+                Ok((_, channel_results)) => {
+                    for (indices, result) in channel_results {
+                        // A split message failed when any of its parts failed
+                        for index in indices {
+                            if results[index].is_ok() {
+                                results[index] = result.clone();
+                            }
+                        }
+                    }
+                }
+                Err(join_error) => {
+                    let message = format!("Delivery task failed: {join_error}");
+                    for &index in channel_indices.get(&join_error.id()).into_iter().flatten() {
+                        results[index] = Err(message.clone());
+                    }
+                }
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|r| r.map_err(|e| anyhow!(e)))
+            .collect()
+    }
+}
+
+/// Group messages per channel and combine consecutive messages for a channel, as long as they fit in a single message.
+///
+/// Messages that do not fit in a single message are split, every part is its own batch.
+fn batch_per_channel(
+    messages: Vec<(serenity::ChannelId, String)>,
+) -> BTreeMap<serenity::ChannelId, Vec<Batch>> {
+    let mut channels: BTreeMap<serenity::ChannelId, Vec<Batch>> = BTreeMap::new();
+
+    for (index, (channel, content)) in messages.into_iter().enumerate() {
+        let batches = channels.entry(channel).or_default();
+
+        if content.chars().count() > MAX_MESSAGE_LEN {
+            batches.extend(split_message(&content).into_iter().map(|part| Batch {
+                content: part,
+                indices: vec![index],
+            }));
+            continue;
+        }
+
+        match batches.last_mut() {
+            Some(batch)
+                if batch.content.chars().count() + 1 + content.chars().count()
+                    <= MAX_MESSAGE_LEN =>
+            {
+                batch.content.push('\n');
+                batch.content.push_str(&content);
+                batch.indices.push(index);
+            }
+            _ => batches.push(Batch {
+                content,
+                indices: vec![index],
+            }),
+        }
+    }
+
+    channels
+}
+
+/// Split content into parts of at most [`MAX_MESSAGE_LEN`] characters, on a line break or space when there is one.
+fn split_message(content: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = content;
+
+    while rest.chars().count() > MAX_MESSAGE_LEN {
+        let limit = rest
+            .char_indices()
+            .nth(MAX_MESSAGE_LEN)
+            .map_or(rest.len(), |(i, _)| i);
+        let split_at = rest[..limit]
+            .rfind('\n')
+            .or_else(|| rest[..limit].rfind(' '))
+            .filter(|&i| i > 0)
+            .unwrap_or(limit);
+
+        parts.push(rest[..split_at].to_owned());
+        rest = rest[split_at..].trim_start_matches(['\n', ' ']);
+    }
+
+    if !rest.is_empty() {
+        parts.push(rest.to_owned());
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::discord::sender::fake::{RecordingSender, SentMessage};
+
+    /// Refuses the first message with a rate limit, then delivers everything.
+    #[derive(Default)]
+    struct RateLimitOnceSender {
+        attempts: AtomicU32,
+        recorder: RecordingSender,
+    }
+
+    #[async_trait]
+    impl DiscordSender for RateLimitOnceSender {
+        async fn send_message(&self, channel: serenity::ChannelId, content: String) -> Result<()> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(RateLimited {
+                    retry_after: Duration::from_millis(10),
+                    global: true,
+                }
+                .into());
+            }
+
+            self.recorder.send_message(channel, content).await
+        }
+    }
+
+    #[test]
+    fn splits_messages_that_are_too_long() {
+        let channel = serenity::ChannelId::new(1);
+        let first_line = "a".repeat(MAX_MESSAGE_LEN - 10);
+        let second_line = "b".repeat(20);
+        let unbroken = "c".repeat(MAX_MESSAGE_LEN + 1);
+
+        let batches = batch_per_channel(vec![
+            (channel, format!("{first_line}\n{second_line}")),
+            (channel, unbroken),
+        ])
+        .remove(&channel)
+        .unwrap_or_default();
+
+        let parts: Vec<(usize, Vec<usize>)> = batches
+            .iter()
+            .map(|b| (b.content.chars().count(), b.indices.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (MAX_MESSAGE_LEN - 10, vec![0]),
+                (20, vec![0]),
+                (MAX_MESSAGE_LEN, vec![1]),
+                (1, vec![1]),
+            ],
+            parts
+        );
+    }
+
+    #[test]
+    fn batches_messages_per_channel() {
+        let first = serenity::ChannelId::new(1);
+        let second = serenity::ChannelId::new(2);
+        let long = "a".repeat(MAX_MESSAGE_LEN - 1);
+
+        let batches = batch_per_channel(vec![
+            (first, "one".into()),
+            (second, "two".into()),
+            (first, "three".into()),
+            (first, long.clone()),
+        ]);
+
+        assert_eq!(
+            vec![
+                Batch {
+                    content: "one\nthree".into(),
+                    indices: vec![0, 2],
+                },
+                Batch {
+                    content: long,
+                    indices: vec![3],
+                },
+            ],
+            batches[&first]
+        );
+        assert_eq!(
+            vec![Batch {
+                content: "two".into(),
+                indices: vec![1],
+            }],
+            batches[&second]
+        );
+    }
+
+    #[tokio::test]
+    async fn delivers_batched_messages() {
+        let recorder = Arc::new(RecordingSender::default());
+        let queue = OutboundQueue::new(recorder.clone(), 2);
+        let channel = serenity::ChannelId::new(1);
+
+        let results = queue
+            .send_messages(vec![(channel, "one".into()), (channel, "two".into())])
+            .await;
+
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(
+            vec![SentMessage {
+                channel,
+                content: "one\ntwo".into(),
+            }],
+            recorder.sent()
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_after_rate_limit() {
+        let sender = Arc::new(RateLimitOnceSender::default());
+        let queue = OutboundQueue::new(sender.clone(), 1);
+        let channel = serenity::ChannelId::new(1);
+
+        let result = queue.send_message(channel, "one".into()).await;
+
+        assert!(result.is_ok(), "Message was not retried: {result:?}");
+        assert_eq!(2, sender.attempts.load(Ordering::SeqCst));
+        assert_eq!(1, sender.recorder.sent().len());
+    }
+}
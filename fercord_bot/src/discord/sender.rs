@@ -0,0 +1,187 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use poise::async_trait;
+use poise::serenity_prelude as serenity;
+use serde::Deserialize;
+use serenity::StatusCode;
+use tracing::{event, Level};
+
+use crate::discord::queue::RateLimited;
+
+/// How long we back off when Discord refuses a message because of a rate limit without saying how long to wait.
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Sends messages to Discord on behalf of the background jobs.
+#[async_trait]
+pub trait DiscordSender: Send + Sync {
+    /// Post a message with the given content in a channel.
+    async fn send_message(&self, channel: serenity::ChannelId, content: String) -> Result<()>;
+
+    /// Post multiple messages. The result at index `i` belongs to `messages[i]`.
+    async fn send_messages(&self, messages: Vec<(serenity::ChannelId, String)>) -> Vec<Result<()>> {
+        let mut results = Vec::with_capacity(messages.len());
+
+        for (channel, content) in messages {
+            results.push(self.send_message(channel, content).await);
+        }
+
+        results
+    }
+}
+
+/// A `DiscordSender` that uses the serenity HTTP client.
+pub struct SerenitySender {
+    http: Arc<serenity::Http>,
+}
+
+impl SerenitySender {
+    pub fn new(http: Arc<serenity::Http>) -> Self {
+        Self { http }
+    }
+}
+
+#[async_trait]
+impl DiscordSender for SerenitySender {
+    async fn send_message(&self, channel: serenity::ChannelId, content: String) -> Result<()> {
+        event!(Level::TRACE, %channel, "Sending message");
+
+        let message = serenity::CreateMessage::new().content(content);
+        let Some(ratelimiter) = &self.http.ratelimiter else {
+            return channel
+                .send_message(&self.http, message)
+                .await
+                .map(|_| ())
+                .map_err(|e| anyhow!(e))
+                .with_context(|| format!("Error sending message to channel {}", channel));
+        };
+
+        // serenity waits out the rate limits it knows the duration of, but the error it returns for the others
+        // does not contain the retry_after and global flag of the response, so we read the response ourselves.
+        let request = serenity::Request::new(
+            serenity::Route::ChannelMessages {
+                channel_id: channel,
+            },
+            serenity::LightMethod::Post,
+        )
+        .body(Some(serde_json::to_vec(&message)?));
+        let response = ratelimiter
+            .perform(request)
+            .await
+            .with_context(|| format!("Error sending message to channel {}", channel))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let retry_after = header("retry-after").and_then(|value| value.parse().ok());
+            let global = header("x-ratelimit-global").is_some()
+                || header("x-ratelimit-scope").as_deref() == Some("global");
+            let body = response.bytes().await.unwrap_or_default();
+
+            return Err(rate_limited(retry_after, global, &body).into());
+        }
+
+        let error = serenity::ErrorResponse::from_response(
+            response,
+            serenity::LightMethod::Post.reqwest_method(),
+        )
+        .await;
+        Err(anyhow!(serenity::Error::Http(
+            serenity::HttpError::UnsuccessfulRequest(error)
+        )))
+        .with_context(|| format!("Error sending message to channel {}", channel))
+    }
+}
+
+/// The body of a response that refused a request because of a rate limit.
+#[derive(Debug, Default, Deserialize)]
+struct RateLimitBody {
+    retry_after: Option<f64>,
+    #[serde(default)]
+    global: bool,
+}
+
+/// Read how long to wait from the `retry-after` header and the body of a rate limited response.
+///
+/// The header wins when both are there, without either we wait [`RATE_LIMIT_BACKOFF`].
+fn rate_limited(retry_after_header: Option<f64>, global_header: bool, body: &[u8]) -> RateLimited {
+    let body: RateLimitBody = serde_json::from_slice(body).unwrap_or_default();
+    let retry_after = retry_after_header
+        .or(body.retry_after)
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or(RATE_LIMIT_BACKOFF);
+
+    RateLimited {
+        retry_after,
+        global: global_header || body.global,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limits_are_read_from_the_response() {
+        let limited = rate_limited(None, false, br#"{"retry_after": 1.5, "global": true}"#);
+        assert_eq!(Duration::from_millis(1500), limited.retry_after);
+        assert!(limited.global);
+
+        let limited = rate_limited(Some(2.0), false, br#"{"retry_after": 1.5}"#);
+        assert_eq!(Duration::from_secs(2), limited.retry_after);
+        assert!(!limited.global);
+
+        let limited = rate_limited(None, false, b"<html>Too many requests</html>");
+        assert_eq!(RATE_LIMIT_BACKOFF, limited.retry_after);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A message recorded by the [`RecordingSender`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SentMessage {
+        pub channel: serenity::ChannelId,
+        pub content: String,
+    }
+
+    /// A `DiscordSender` that does not talk to Discord, but records everything it was asked to send.
+    #[derive(Debug, Default)]
+    pub struct RecordingSender {
+        sent: Mutex<Vec<SentMessage>>,
+    }
+
+    impl RecordingSender {
+        /// All messages sent so far, in the order they were sent.
+        pub fn sent(&self) -> Vec<SentMessage> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl DiscordSender for RecordingSender {
+        async fn send_message(&self, channel: serenity::ChannelId, content: String) -> Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push(SentMessage { channel, content });
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tracing::{event, Level};
+
+use fercord_common::prelude::*;
+use fercord_storage::encryption::FieldCipher;
+use fercord_storage::prelude::*;
+
+/// The cipher for the contents of reminders, `None` when encryption is not configured.
+pub(crate) fn reminder_cipher(app_config: &DiscordConfig) -> Result<Option<Arc<FieldCipher>>> {
+    app_config
+        .encryption
+        .as_ref()
+        .map(|config| FieldCipher::from_config(config).map(Arc::new))
+        .transpose()
+}
+
+/// Handle the `rotate-encryption-key` subcommand and return the output for the user.
+///
+/// Every reminder that is not encrypted with the current key yet is encrypted with it.
+pub(crate) async fn rotate_key_command(
+    app_config: &DiscordConfig,
+    batch_size: u32,
+) -> Result<String> {
+    let Some(cipher) = reminder_cipher(app_config)? else {
+        anyhow::bail!("Encryption is not configured, add an [encryption] section first");
+    };
+
+    let db_pool = db::setup_with(&app_config.database_url, &app_config.database)
+        .await
+        .context("Error setting up database connection")?;
+
+    event!(
+        Level::INFO,
+        key_id = cipher.key_id(),
+        "Encrypting reminders with the current key"
+    );
+    let reencrypted = Reminder::repository(&db_pool)
+        .with_cipher(Some(&cipher))
+        .reencrypt(batch_size)
+        .await?;
+
+    if reencrypted > 0 {
+        let event_bus = EventBus::new(app_config).context("Error setting up the event bus")?;
+        event_bus.send(StorageEvent::RemindersChanged);
+        // The process exits right after, taking the queued events with it
+        event_bus.flush().await;
+    }
+
+    Ok(format!(
+        "Encrypted {reencrypted} reminders with key {}",
+        cipher.key_id()
+    ))
+}
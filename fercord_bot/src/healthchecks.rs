@@ -28,9 +28,10 @@ pub async fn perform_healthchecks(config: &DiscordConfig) -> Result<String> {
 
     let db_check_start = Utc::now();
     event!(Level::TRACE, %db_check_start, "Starting DB Health check");
-    let db_result = db::setup(config.database_url.as_ref()).await;
+    // Checking the connection should not change the schema, or create a sqlite database that is missing
+    let db_result = db::connect_existing(&config.database_url, &config.database).await;
 
-    if let Ok(pool) = db_result {
+    if let Ok(pool) = &db_result {
         let conn = pool.acquire().await;
         let db_check_end = Utc::now();
 
@@ -53,7 +54,13 @@ pub async fn perform_healthchecks(config: &DiscordConfig) -> Result<String> {
 
     let kv_check_start = Utc::now();
     event!(Level::TRACE, %kv_check_start, "Starting KV Health check");
-    let kv_result = KVClient::new(config);
+    // The KV store can live in the database, a pool that never connects is enough for the other kinds.
+    let kv_result = match &db_result {
+        Ok(pool) => KVClient::new(config, pool),
+        Err(_) => AnyPool::connect_lazy(&config.database_url)
+            .map_err(anyhow::Error::from)
+            .and_then(|pool| KVClient::new(config, &pool)),
+    };
 
     if let Ok(kv_client) = kv_result {
         let conn_check = kv_client.connection_check().await;
@@ -1,84 +1,173 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
 use poise::async_trait;
-use poise::serenity_prelude::CacheHttp;
 use serde::{Deserialize, Serialize};
-use tracing::{debug_span, event, field, info, Level};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug_span, event, field, info, Instrument, Level};
 
 use fercord_common::prelude::*;
+use fercord_storage::encryption::FieldCipher;
 use fercord_storage::prelude::*;
 
+use crate::discord::queue::OutboundQueue;
+use crate::discord::sender::DiscordSender;
+use crate::encryption::reminder_cipher;
+use crate::shutdown::ShutdownListener;
+
 //pub type Job = Box<dyn Fn(&Arc<JobArgs>) -> JobResult>;
 pub(crate) type JobResult = Result<()>;
 
 #[async_trait]
-pub trait Job {
+pub trait Job: Send + Sync {
+    /// A short, unique name for this job. Used in logs and traces.
+    fn name(&self) -> &'static str;
+
     async fn run(&self, args: &JobArgs) -> JobResult;
 }
 
-pub struct JobArgs<'j> {
-    #[allow(unused)]
+pub struct JobArgs {
     pub kv_client: Arc<KVClient>,
     pub db_pool: Arc<AnyPool>,
     pub last_run_time: DateTime<Utc>,
-    pub discord_client: Arc<dyn CacheHttp + 'j>,
+    /// When this run started. The run covers the time until then, the next run continues from here.
+    pub run_time: DateTime<Utc>,
+    pub discord_sender: Arc<dyn DiscordSender>,
     pub discord_config: DiscordConfig,
+    /// Encrypts and decrypts the contents of reminders, `None` when encryption is not configured.
+    pub reminder_cipher: Option<Arc<FieldCipher>>,
+    /// Announces the changes the job makes to the stored data.
+    pub event_bus: EventBus,
 }
 
-impl<'j> JobArgs<'j> {
+impl JobArgs {
     /// Create a new JobArgs struct from a `KVClient` and an sqlx Postgres pool.
+    #[allow(clippy::too_many_arguments)]
     fn new(
         kv_client: &Arc<KVClient>,
         db_pool: &Arc<AnyPool>,
         last_run_time: DateTime<Utc>,
-        discord_client: &Arc<impl CacheHttp + 'j>,
+        run_time: DateTime<Utc>,
+        discord_sender: &Arc<dyn DiscordSender>,
         discord_config: DiscordConfig,
+        reminder_cipher: Option<Arc<FieldCipher>>,
+        event_bus: &EventBus,
     ) -> Self {
         Self {
             kv_client: kv_client.clone(),
             db_pool: db_pool.clone(),
             last_run_time,
-            discord_client: discord_client.clone(),
+            run_time,
+            discord_sender: discord_sender.clone(),
             discord_config,
+            reminder_cipher,
+            event_bus: event_bus.clone(),
         }
     }
 }
 
+/// The KV keys that hold job state, see [`JobState`] and [`JobPause`].
+pub(crate) const JOB_STATE_KV_PATTERNS: [&str; 2] = ["jobstate_*", "job_paused_*"];
+
+/// The last completed run of a single job on a single shard.
 #[derive(Debug, Deserialize, Serialize)]
 struct JobState {
     pub last_run: DateTime<Utc>,
     job_shard_key: uuid::Uuid,
+    job: String,
 }
 
 impl JobState {
-    pub fn for_identity(shard_id: &uuid::Uuid) -> Self {
+    pub fn for_identity(shard_id: &uuid::Uuid, job: &str) -> Self {
         Self {
             last_run: chrono::DateTime::<Utc>::default(),
             job_shard_key: *shard_id,
+            job: job.to_string(),
         }
     }
 
-    pub fn new(shard_id: &uuid::Uuid, time: DateTime<Utc>) -> Self {
+    pub fn new(shard_id: &uuid::Uuid, job: &str, time: DateTime<Utc>) -> Self {
         Self {
             last_run: time,
             job_shard_key: *shard_id,
+            job: job.to_string(),
         }
     }
 }
 
 impl Identifiable for JobState {
     fn kv_key(&self) -> KVIdentity {
-        format!("jobstate_{}", self.job_shard_key)
+        format!("jobstate_{}_{}", self.job_shard_key, self.job)
+    }
+}
+
+/// Whether a job is paused. Stored in the KV store, so every replica honors it.
+#[derive(Debug, Deserialize, Serialize)]
+struct JobPause {
+    job: String,
+    paused: bool,
+}
+
+impl Identifiable for JobPause {
+    fn kv_key(&self) -> KVIdentity {
+        format!("job_paused_{}", self.job)
     }
 }
 
-pub(crate) async fn job_scheduler<'j>(
+/// How much longer than the job timeout a [`JobLock`] is kept, in case the process dies without releasing it.
+const JOB_LOCK_MARGIN: Duration = Duration::from_secs(60);
+
+/// Marks a job as running. Stored in the KV store, so a job never runs twice at the same time, not even on another
+/// replica or from a manual trigger.
+///
+/// Every run has its own `token`, so a run that outlived its lock does not release the lock of the next run.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct JobLock {
+    job: String,
+    started_at: DateTime<Utc>,
+    token: uuid::Uuid,
+}
+
+impl Identifiable for JobLock {
+    fn kv_key(&self) -> KVIdentity {
+        format!("job_running_{}", self.job)
+    }
+}
+
+/// The outcome of a single run of all jobs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct JobRunSummary {
+    pub completed: Vec<&'static str>,
+    pub failed: Vec<&'static str>,
+    /// The jobs that were not started because they were still running elsewhere.
+    pub skipped: Vec<&'static str>,
+}
+
+/// What happened to a job that did not fail.
+enum JobOutcome {
+    Completed,
+    AlreadyRunning,
+}
+
+/// Run all `jobs` every `job_interval_min` until a shutdown is requested.
+///
+/// The jobs use the database pool, KV client and event bus of the rest of the bot.
+/// Paused jobs are skipped. When a shutdown is requested during a run, the running jobs get `shutdown_grace_sec` to finish.
+/// The state of a job is only saved when it completed, so a failed or interrupted job is repeated on the next run.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn job_scheduler(
     app_config: &DiscordConfig,
-    jobs: &Vec<Box<dyn Job>>,
+    jobs: &[Arc<dyn Job>],
     shard_key: &uuid::Uuid,
-    discord_client: impl CacheHttp,
+    db_pool: Arc<AnyPool>,
+    kv_client: Arc<KVClient>,
+    discord_sender: Arc<dyn DiscordSender>,
+    event_bus: EventBus,
+    mut shutdown: ShutdownListener,
 ) -> Result<()> {
     let span = debug_span!(
         "fercord.jobs.scheduler",
@@ -91,81 +180,433 @@ pub(crate) async fn job_scheduler<'j>(
         return Ok(());
     }
 
-    event!(Level::DEBUG, "Setting up job scheduler db pool");
-    let db_pool = {
-        let pool = db::setup(app_config.database_url.as_ref())
-            .await
-            .with_context(|| "Error setting up database connection")?;
-
-        Arc::new(pool)
-    };
-
-    event!(Level::DEBUG, "Setting up job scheduler KV client");
-    let kv_client =
-        Arc::new(KVClient::new(app_config).with_context(|| "Error setting up KV client")?);
-
     let interval_dur = std::time::Duration::from_secs((app_config.job_interval_min * 60) as u64);
     let mut job_interval = tokio::time::interval_at(tokio::time::Instant::now(), interval_dur);
     job_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
-    let client_arc = Arc::new(discord_client);
+    let job_timeout = Duration::from_secs(app_config.job_timeout_sec as u64);
+    let job_concurrency = app_config.job_concurrency.get();
+    let shutdown_grace = Duration::from_secs(app_config.shutdown_grace_sec as u64);
 
     loop {
-        // get last run time and compare to interval, sleep for difference or run immediately
-        event!(Level::TRACE, "Retrieving last run state");
-        let last_job_state = get_last_runtime(shard_key, &kv_client).await?;
+        let mut job_runs = Vec::with_capacity(jobs.len());
+        let run_time = Utc::now();
 
-        let last_time_ran = last_job_state.map_or(Utc::now(), |s| s.last_run);
+        for job in jobs {
+            match is_paused(job.name(), &kv_client).await {
+                Ok(true) => {
+                    info!("Job {} is paused. Skipping...", job.name());
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    event!(
+                        Level::WARN,
+                        ?e,
+                        "Could not determine if job {} is paused. Running it anyway",
+                        job.name()
+                    );
+                }
+            }
 
-        let since_last_run = Utc::now() - last_time_ran;
-        event!(
-            Level::INFO,
-            "Time since last run: {:?} s",
-            &since_last_run.num_seconds()
+            let job_args = Arc::new(
+                job_args_for(
+                    job.name(),
+                    shard_key,
+                    run_time,
+                    &kv_client,
+                    &db_pool,
+                    &discord_sender,
+                    &event_bus,
+                    app_config,
+                )
+                .await?,
+            );
+            job_runs.push((job.clone(), job_args));
+        }
+
+        let job_run = run_jobs(job_runs, job_timeout, job_concurrency);
+        tokio::pin!(job_run);
+
+        let summary = tokio::select! {
+            summary = &mut job_run => summary,
+            _ = shutdown.requested() => {
+                info!(
+                    "Shutdown requested. Waiting up to {} s for running jobs to finish",
+                    shutdown_grace.as_secs()
+                );
+
+                match tokio::time::timeout(shutdown_grace, job_run).await {
+                    Ok(summary) => summary,
+                    Err(_) => {
+                        event!(
+                            Level::WARN,
+                            "Running jobs did not finish within the grace period. Not saving job state"
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        info!(
+            "Attempted all jobs in this run. Completed: {} - Failed: {} - Skipped: {}",
+            &summary.completed.len(),
+            &summary.failed.len(),
+            &summary.skipped.len()
         );
 
-        let mut failed_jobs = 0;
-        let mut completed_jobs = 0;
+        for job_name in summary.completed {
+            save_job_state(shard_key, job_name, run_time, &kv_client).await?;
+        }
 
-        let job_args = Arc::new(JobArgs::new(
-            &kv_client,
-            &db_pool,
-            last_time_ran,
-            &client_arc,
-            app_config.clone(),
-        ));
+        if shutdown.is_requested() {
+            info!("Job scheduler stopped");
+            return Ok(());
+        }
 
-        for job in jobs {
-            if let Err(e) = job.run(&job_args).await {
+        info!("Sleeping until next interval");
+        tokio::select! {
+            _ = job_interval.tick() => {},
+            _ = shutdown.requested() => {
+                info!("Job scheduler stopped");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Build the `JobArgs` for a run of a single job that starts at `run_time`, continuing from the last run of that job
+/// that completed on this shard.
+#[allow(clippy::too_many_arguments)]
+async fn job_args_for(
+    job_name: &str,
+    shard_key: &uuid::Uuid,
+    run_time: DateTime<Utc>,
+    kv_client: &Arc<KVClient>,
+    db_pool: &Arc<AnyPool>,
+    discord_sender: &Arc<dyn DiscordSender>,
+    event_bus: &EventBus,
+    app_config: &DiscordConfig,
+) -> Result<JobArgs> {
+    event!(
+        Level::TRACE,
+        "Retrieving last run state for job {}",
+        job_name
+    );
+    let last_job_state = get_last_runtime(shard_key, job_name, kv_client).await?;
+
+    let last_time_ran = last_job_state.map_or(run_time, |s| s.last_run);
+
+    let since_last_run = run_time - last_time_ran;
+    event!(
+        Level::INFO,
+        "Time since last run of {}: {:?} s",
+        job_name,
+        &since_last_run.num_seconds()
+    );
+
+    Ok(JobArgs::new(
+        kv_client,
+        db_pool,
+        last_time_ran,
+        run_time,
+        discord_sender,
+        app_config.clone(),
+        reminder_cipher(app_config)?,
+        event_bus,
+    ))
+}
+
+/// Run every job in its own task, with at most `concurrency` jobs running at the same time.
+///
+/// A job that errors, panics or takes longer than `timeout` is counted as failed. It does not affect the other jobs.
+/// A job that is still running elsewhere, according to its [`JobLock`], is skipped.
+pub(crate) async fn run_jobs(
+    job_runs: Vec<(Arc<dyn Job>, Arc<JobArgs>)>,
+    timeout: Duration,
+    concurrency: usize,
+) -> JobRunSummary {
+    let limiter = Arc::new(Semaphore::new(concurrency));
+    let mut job_set = JoinSet::new();
+    let mut job_names = HashMap::with_capacity(job_runs.len());
+
+    for (job, job_args) in job_runs {
+        let job_name = job.name();
+        let limiter = limiter.clone();
+        let span = debug_span!("fercord.jobs.run", job = job_name);
+
+        let handle = job_set.spawn(
+            async move {
+                let _permit = limiter
+                    .acquire_owned()
+                    .await
+                    .context("Job limiter was closed")?;
+
+                let Some(lock) = lock_job(job_name, timeout, &job_args.kv_client).await? else {
+                    info!("Job {} is already running. Skipping...", job_name);
+                    return Ok(JobOutcome::AlreadyRunning);
+                };
+
+                let result = tokio::time::timeout(timeout, job.run(&job_args))
+                    .await
+                    .map_err(|_| anyhow!("Job timed out after {} s", timeout.as_secs()));
+
+                // A lock that is left behind expires on its own
+                if let Err(e) = unlock_job(&lock, &job_args.kv_client).await {
+                    event!(
+                        Level::WARN,
+                        ?e,
+                        "Error releasing the lock of job {}",
+                        job_name
+                    );
+                }
+
+                result?.map(|()| JobOutcome::Completed)
+            }
+            .instrument(span),
+        );
+        job_names.insert(handle.id(), job_name);
+    }
+
+    let mut summary = JobRunSummary::default();
+
+    while let Some(joined) = job_set.join_next_with_id().await {
+        match joined {
+            Ok((id, Ok(JobOutcome::Completed))) => summary
+                .completed
+                .push(job_names.get(&id).copied().unwrap_or_default()),
+            Ok((id, Ok(JobOutcome::AlreadyRunning))) => summary
+                .skipped
+                .push(job_names.get(&id).copied().unwrap_or_default()),
+            Ok((id, Err(e))) => {
+                let job_name = job_names.get(&id).copied().unwrap_or_default();
                 event!(
                     Level::ERROR,
+                    job = job_name,
                     "Encountered an error during a background job: {:?}",
                     e
                 );
-                failed_jobs += 1;
-            } else {
-                completed_jobs += 1;
+                summary.failed.push(job_name);
+            }
+            Err(join_error) => {
+                let job_name = job_names.get(&join_error.id()).copied().unwrap_or_default();
+                event!(
+                    Level::ERROR,
+                    job = job_name,
+                    "Background job panicked or was cancelled: {}",
+                    join_error
+                );
+                summary.failed.push(job_name);
             }
         }
+    }
 
-        info!(
-            "Attempted all jobs in this run. Completed: {} - Failed: {}",
-            &completed_jobs, &failed_jobs
+    summary
+}
+
+/// Run a single job right away, outside of the schedule. Paused jobs are run as well.
+///
+/// A job that is already running, for example because the scheduler started it, is not started again.
+/// The job state is saved when the job completes, so the scheduler continues from the start of this run.
+pub(crate) async fn trigger_job(
+    job: &Arc<dyn Job>,
+    app_config: &DiscordConfig,
+    kv_client: &KVClient,
+    db_pool: &AnyPool,
+    discord_sender: &Arc<dyn DiscordSender>,
+    event_bus: &EventBus,
+) -> Result<()> {
+    let kv_client = Arc::new(kv_client.clone());
+    let run_time = Utc::now();
+    let job_args = job_args_for(
+        job.name(),
+        &app_config.shard_key,
+        run_time,
+        &kv_client,
+        &Arc::new(db_pool.clone()),
+        discord_sender,
+        event_bus,
+        app_config,
+    )
+    .await?;
+
+    info!("Manually triggering job {}", job.name());
+    let summary = run_jobs(
+        vec![(job.clone(), Arc::new(job_args))],
+        Duration::from_secs(app_config.job_timeout_sec as u64),
+        1,
+    )
+    .await;
+
+    if !summary.skipped.is_empty() {
+        return Err(anyhow!(
+            "Job {} is already running, try again when it finished",
+            job.name()
+        ));
+    }
+
+    if summary.completed.is_empty() {
+        return Err(anyhow!(
+            "Job {} failed, see the logs for details",
+            job.name()
+        ));
+    }
+
+    save_job_state(&app_config.shard_key, job.name(), run_time, &kv_client).await
+}
+
+/// Claim the job with the given name, returns `None` when it is already claimed.
+///
+/// The claim expires after `timeout` and some margin, so a process that dies while running the job does not keep it.
+async fn lock_job(
+    job_name: &str,
+    timeout: Duration,
+    kv_client: &KVClient,
+) -> Result<Option<JobLock>> {
+    let lock = JobLock {
+        job: job_name.to_string(),
+        started_at: Utc::now(),
+        token: uuid::Uuid::new_v4(),
+    };
+
+    let locked = kv_client
+        .save_json_if_absent(lock.clone(), timeout + JOB_LOCK_MARGIN)
+        .await
+        .with_context(|| format!("Error locking job {}", job_name))?;
+
+    Ok(locked.then_some(lock))
+}
+
+/// Release the claim of [`lock_job`], unless it expired and the job was claimed again since.
+async fn unlock_job(lock: &JobLock, kv_client: &KVClient) -> Result<()> {
+    let released = kv_client
+        .delete_json_if_equal(lock)
+        .await
+        .with_context(|| format!("Error unlocking job {}", lock.job))?;
+
+    if !released {
+        event!(
+            Level::WARN,
+            "The lock of job {} expired before the job finished",
+            lock.job
         );
+    }
 
-        save_job_state(shard_key, &kv_client).await?;
+    Ok(())
+}
 
-        info!("Sleeping until next interval");
-        _ = job_interval.tick().await;
+/// Check if the job with the given name is paused.
+pub(crate) async fn is_paused(job_name: &str, kv_client: &KVClient) -> Result<bool> {
+    let pause = kv_client
+        .get_json(&JobPause {
+            job: job_name.to_string(),
+            paused: false,
+        })
+        .await
+        .with_context(|| format!("Error getting pause state for job {}", job_name))?;
+
+    Ok(pause.is_some_and(|p| p.paused))
+}
+
+/// Pause or resume the job with the given name, for every replica.
+pub(crate) async fn set_paused(job_name: &str, paused: bool, kv_client: &KVClient) -> Result<()> {
+    info!("Setting paused={} for job {}", paused, job_name);
+
+    kv_client
+        .save_json(JobPause {
+            job: job_name.to_string(),
+            paused,
+        })
+        .await
+        .with_context(|| format!("Error saving pause state for job {}", job_name))
+}
+
+/// Find a registered job by its name.
+pub(crate) fn find_job<'a>(jobs: &'a [Arc<dyn Job>], job_name: &str) -> Result<&'a Arc<dyn Job>> {
+    jobs.iter()
+        .find(|j| j.name() == job_name)
+        .ok_or_else(|| anyhow!("There is no job called {}", job_name))
+}
+
+/// Handle a `jobs` subcommand from the command line and return the output for the user.
+pub(crate) async fn job_command(
+    app_config: &DiscordConfig,
+    jobs: &[Arc<dyn Job>],
+    command: &JobCommands,
+) -> Result<String> {
+    // The KV store can live in the database
+    let db_pool = db::setup_with(&app_config.database_url, &app_config.database)
+        .await
+        .context("Error setting up database connection")?;
+    let kv_client = KVClient::new(app_config, &db_pool).context("Error building KV client")?;
+
+    match command {
+        JobCommands::List => {
+            let mut output = String::new();
+
+            for job in jobs {
+                let state = if is_paused(job.name(), &kv_client).await? {
+                    "paused"
+                } else {
+                    "active"
+                };
+                output.push_str(&format!("{}: {}\n", job.name(), state));
+            }
+
+            Ok(output)
+        }
+        JobCommands::Run { name } => {
+            let job = find_job(jobs, name)?;
+            let discord_sender: Arc<dyn DiscordSender> =
+                Arc::new(OutboundQueue::from_config(app_config));
+            let event_bus = EventBus::new(app_config).context("Error setting up the event bus")?;
+
+            let result = trigger_job(
+                job,
+                app_config,
+                &kv_client,
+                &db_pool,
+                &discord_sender,
+                &event_bus,
+            )
+            .await;
+            // The process exits right after, taking the events the job queued with it
+            event_bus.flush().await;
+            result?;
+
+            Ok(format!("Job {} completed", name))
+        }
+        JobCommands::Pause { name } => {
+            let job = find_job(jobs, name)?;
+            set_paused(job.name(), true, &kv_client).await?;
+
+            Ok(format!("Paused job {}", name))
+        }
+        JobCommands::Resume { name } => {
+            let job = find_job(jobs, name)?;
+            set_paused(job.name(), false, &kv_client).await?;
+
+            Ok(format!("Resumed job {}", name))
+        }
     }
 }
 
-/// Save the job state for the given shard key and using the given KVClient.
-async fn save_job_state(shard_key: &uuid::Uuid, kv_client: &Arc<KVClient>) -> Result<()> {
-    let state = JobState::new(shard_key, Utc::now());
+/// Save that the run of the job that started at `run_time` completed, for the given shard key.
+///
+/// The next run continues from `run_time`, not from when this run finished, so nothing that happened during a slow
+/// run is missed.
+async fn save_job_state(
+    shard_key: &uuid::Uuid,
+    job_name: &str,
+    run_time: DateTime<Utc>,
+    kv_client: &KVClient,
+) -> Result<()> {
+    let state = JobState::new(shard_key, job_name, run_time);
     event!(
         Level::DEBUG,
-        "Saving completed run at {}",
+        "Saving completed run of {} that started at {}",
+        job_name,
         field::display(&state.last_run)
     );
 
@@ -176,16 +617,335 @@ async fn save_job_state(shard_key: &uuid::Uuid, kv_client: &Arc<KVClient>) -> Re
 ///
 /// ## Parameters
 /// * `job_shard_key`: A `uuid::Uuid` that identifies this job runner.
+/// * `job_name`: The name of the job.
 /// * `kv_client`: The `KVClient` used for the connection to the kv server.
 async fn get_last_runtime(
     job_shard_key: &uuid::Uuid,
-    kv_client: &Arc<KVClient>,
+    job_name: &str,
+    kv_client: &KVClient,
 ) -> Result<Option<JobState>> {
-    let state_ident = JobState::for_identity(job_shard_key);
+    let state_ident = JobState::for_identity(job_shard_key, job_name);
     let state_json = kv_client
         .get_json::<JobState>(&state_ident)
         .await
-        .with_context(|| format!("Error getting job state for shard {}", job_shard_key))?;
+        .with_context(|| {
+            format!(
+                "Error getting job state of {} for shard {}",
+                job_name, job_shard_key
+            )
+        })?;
 
     Ok(state_json)
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::num::NonZeroUsize;
+
+    use tokio::sync::Barrier;
+
+    use super::*;
+    use crate::discord::sender::fake::RecordingSender;
+
+    struct SleepyJob;
+
+    #[async_trait]
+    impl Job for SleepyJob {
+        fn name(&self) -> &'static str {
+            "sleepy"
+        }
+
+        async fn run(&self, _args: &JobArgs) -> JobResult {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            Ok(())
+        }
+    }
+
+    struct PanickingJob;
+
+    #[async_trait]
+    impl Job for PanickingJob {
+        fn name(&self) -> &'static str {
+            "panicking"
+        }
+
+        async fn run(&self, _args: &JobArgs) -> JobResult {
+            panic!("This job always panics");
+        }
+    }
+
+    /// Waits on the barrier, under the given name.
+    struct BarrierJob(&'static str, Arc<Barrier>);
+
+    #[async_trait]
+    impl Job for BarrierJob {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn run(&self, _args: &JobArgs) -> JobResult {
+            self.1.wait().await;
+            Ok(())
+        }
+    }
+
+    /// Records messages like the [`RecordingSender`], but takes `delay` to send each of them.
+    struct SlowSender {
+        delay: Duration,
+        sent: RecordingSender,
+    }
+
+    #[async_trait]
+    impl DiscordSender for SlowSender {
+        async fn send_message(
+            &self,
+            channel: poise::serenity_prelude::ChannelId,
+            content: String,
+        ) -> Result<()> {
+            tokio::time::sleep(self.delay).await;
+            self.sent.send_message(channel, content).await
+        }
+    }
+
+    /// Create `JobArgs` backed by an in-memory sqlite database.
+    ///
+    /// The `KVClient` in the returned arguments keeps everything in memory.
+    pub(crate) async fn test_job_args_with(
+        discord_sender: Arc<dyn DiscordSender>,
+        last_run_time: DateTime<Utc>,
+    ) -> Result<Arc<JobArgs>> {
+        let config = DiscordConfig {
+            discord_token: String::new(),
+            database_url: "sqlite::memory:".into(),
+            redis_url: None,
+            kv_store: Some(KVStoreKind::Memory),
+            kv_prefix: None,
+            job_interval_min: 1,
+            job_timeout_sec: 1,
+            job_concurrency: NonZeroUsize::new(2).unwrap(),
+            shutdown_grace_sec: 1,
+            message_concurrency: NonZeroUsize::new(1).unwrap(),
+            guild_purge_grace_hours: 1,
+            shard_key: uuid::Uuid::new_v4(),
+            session_key: None,
+            session_store: None,
+            client_id: None,
+            client_secret: None,
+            database: DatabaseConfig::default(),
+            encryption: None,
+        };
+
+        let db_pool = Arc::new(db::setup(&config.database_url).await?);
+        let kv_client = Arc::new(KVClient::new(&config, &db_pool)?);
+
+        Ok(Arc::new(JobArgs::new(
+            &kv_client,
+            &db_pool,
+            last_run_time,
+            Utc::now(),
+            &discord_sender,
+            config,
+            None,
+            &EventBus::in_process(),
+        )))
+    }
+
+    async fn test_job_runs(jobs: Vec<Arc<dyn Job>>) -> Result<Vec<(Arc<dyn Job>, Arc<JobArgs>)>> {
+        let job_args = test_job_args_with(Arc::new(RecordingSender::default()), Utc::now()).await?;
+
+        Ok(jobs
+            .into_iter()
+            .map(|job| (job, job_args.clone()))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn timed_out_job_counts_as_failed() -> Result<()> {
+        let job_runs = test_job_runs(vec![Arc::new(SleepyJob)]).await?;
+
+        let summary = run_jobs(job_runs, Duration::from_millis(50), 1).await;
+
+        assert_eq!(
+            JobRunSummary {
+                completed: vec![],
+                failed: vec!["sleepy"],
+                skipped: vec![],
+            },
+            summary
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn panicking_job_does_not_stop_other_jobs() -> Result<()> {
+        let barrier = Arc::new(Barrier::new(1));
+        let job_runs = test_job_runs(vec![
+            Arc::new(PanickingJob),
+            Arc::new(BarrierJob("barrier", barrier)),
+        ])
+        .await?;
+
+        let summary = run_jobs(job_runs, Duration::from_secs(5), 1).await;
+
+        assert_eq!(
+            JobRunSummary {
+                completed: vec!["barrier"],
+                failed: vec!["panicking"],
+                skipped: vec![],
+            },
+            summary
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jobs_run_concurrently() -> Result<()> {
+        // Both jobs can only pass the barrier when they run at the same time.
+        let barrier = Arc::new(Barrier::new(2));
+        let job_runs = test_job_runs(vec![
+            Arc::new(BarrierJob("first", barrier.clone())),
+            Arc::new(BarrierJob("second", barrier)),
+        ])
+        .await?;
+
+        let mut summary = run_jobs(job_runs, Duration::from_secs(5), 2).await;
+        summary.completed.sort();
+
+        assert_eq!(
+            JobRunSummary {
+                completed: vec!["first", "second"],
+                failed: vec![],
+                skipped: vec![],
+            },
+            summary
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn running_job_is_not_started_again() -> Result<()> {
+        let barrier = Arc::new(Barrier::new(1));
+        let job_runs = test_job_runs(vec![Arc::new(BarrierJob("barrier", barrier))]).await?;
+        let kv_client = job_runs[0].1.kv_client.clone();
+
+        // Another replica or a manual trigger is running it
+        let lock = lock_job("barrier", Duration::from_secs(5), &kv_client)
+            .await?
+            .context("Job was already locked")?;
+        let summary = run_jobs(job_runs.clone(), Duration::from_secs(5), 1).await;
+        assert_eq!(vec!["barrier"], summary.skipped);
+        assert!(summary.completed.is_empty());
+
+        unlock_job(&lock, &kv_client).await?;
+        let summary = run_jobs(job_runs.clone(), Duration::from_secs(5), 1).await;
+        assert_eq!(vec!["barrier"], summary.completed);
+
+        // A finished run releases the lock
+        let summary = run_jobs(job_runs, Duration::from_secs(5), 1).await;
+        assert_eq!(vec!["barrier"], summary.completed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reminders_due_during_a_slow_run_are_sent_by_the_next_run() -> Result<()> {
+        use fercord_storage::prelude::model::reminder::*;
+
+        let sender = Arc::new(SlowSender {
+            delay: Duration::from_millis(2100),
+            sent: RecordingSender::default(),
+        });
+        let now = Utc::now();
+        let args = test_job_args_with(sender.clone(), now).await?;
+        let mut config = args.discord_config.clone();
+        config.job_timeout_sec = 10;
+        save_job_state(
+            &config.shard_key,
+            "reminders",
+            now - chrono::TimeDelta::minutes(5),
+            &args.kv_client,
+        )
+        .await?;
+
+        let repo = Reminder::repository(&args.db_pool);
+        for (when, what) in [
+            (now - chrono::TimeDelta::minutes(1), "the due thing"),
+            // Becomes due while the first run is still sending
+            (now + chrono::TimeDelta::seconds(1), "the next thing"),
+        ] {
+            repo.insert(&Reminder {
+                id: 0,
+                who: 1234,
+                when,
+                what: what.into(),
+                server: 42,
+                channel: 4242,
+                what_encrypted: false,
+                undeliverable_since: None,
+            })
+            .await?;
+        }
+
+        let job = crate::discord::jobs::reminders();
+        let discord_sender: Arc<dyn DiscordSender> = sender.clone();
+        for _ in 0..2 {
+            trigger_job(
+                &job,
+                &config,
+                &args.kv_client,
+                &args.db_pool,
+                &discord_sender,
+                &args.event_bus,
+            )
+            .await?;
+        }
+
+        assert_eq!(
+            vec![
+                "<@1234> I was supposed to remind you of the due thing".to_owned(),
+                "<@1234> I was supposed to remind you of the next thing".to_owned(),
+            ],
+            sender
+                .sent
+                .sent()
+                .into_iter()
+                .map(|m| m.content)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_run_does_not_release_a_newer_lock() -> Result<()> {
+        let kv_client = KVClient::in_memory();
+
+        let stale = lock_job("slow", Duration::from_secs(5), &kv_client)
+            .await?
+            .context("Job was already locked")?;
+        // The lock of the stale run expired and another run claimed the job
+        kv_client.delete(&stale).await?;
+        let fresh = lock_job("slow", Duration::from_secs(5), &kv_client)
+            .await?
+            .context("Job was already locked")?;
+
+        unlock_job(&stale, &kv_client).await?;
+        assert!(lock_job("slow", Duration::from_secs(5), &kv_client)
+            .await?
+            .is_none());
+
+        unlock_job(&fresh, &kv_client).await?;
+        assert!(lock_job("slow", Duration::from_secs(5), &kv_client)
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn job_state_is_kept_per_job() {
+        let shard_key = uuid::uuid!("c69b7bb6-0ca4-40da-8bad-26d9d4d2fb50");
+
+        assert_eq!(
+            "jobstate_c69b7bb6-0ca4-40da-8bad-26d9d4d2fb50_reminders",
+            JobState::for_identity(&shard_key, "reminders").kv_key()
+        );
+    }
+}
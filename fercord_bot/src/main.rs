@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use anyhow::Context;
 use discord::commands::register;
 use discord::commands::roll;
@@ -5,21 +7,37 @@ use poise::serenity_prelude::{self as serenity, ActivityData};
 use tracing::*;
 
 use fercord_storage::db;
+use fercord_storage::encryption::FieldCipher;
 use fercord_storage::prelude::*;
 
+use crate::backup::{backup_command, copy_database_command, restore_command};
+use crate::database::db_command;
 use crate::discord::commands::{reminder, timezone};
+use crate::discord::queue::OutboundQueue;
+use crate::discord::sender::DiscordSender;
+use crate::encryption::{reminder_cipher, rotate_key_command};
 use crate::healthchecks::perform_healthchecks;
-use crate::job::{job_scheduler, Job};
+use crate::job::{job_command, job_scheduler, Job};
+use crate::shutdown::{shutdown_channel, termination_signal};
 use fercord_common::{cli, cli::Commands, prelude::*};
 
+mod backup;
+mod database;
 mod discord;
+mod encryption;
 mod healthchecks;
 mod job;
+mod shutdown;
 
 pub struct ServerData {
     pub kv_client: KVClient,
     pub db_pool: AnyPool,
+    pub settings_cache: Arc<GuildSettingsCache>,
+    pub reminder_cipher: Option<Arc<FieldCipher>>,
     pub config: DiscordConfig,
+    pub jobs: Vec<Arc<dyn Job>>,
+    pub discord_sender: Arc<dyn DiscordSender>,
+    pub event_bus: EventBus,
 }
 
 #[tokio::main]
@@ -33,30 +51,130 @@ async fn main() -> anyhow::Result<()> {
     // Load application config
     let config = DiscordConfig::from_env_and_file(&config_file_path)?;
 
-    if let Some(Commands::Healthcheck) = args.command {
-        let checks_output = perform_healthchecks(&config).await?;
-        println!("{}", checks_output);
-        return Ok(());
+    let jobs: Vec<Arc<dyn Job>> = discord::jobs::all();
+
+    match args.command {
+        Some(Commands::Healthcheck) => {
+            let checks_output = perform_healthchecks(&config).await?;
+            println!("{}", checks_output);
+            return Ok(());
+        }
+        Some(Commands::Jobs { command }) => {
+            let jobs_output = job_command(&config, &jobs, &command).await?;
+            println!("{}", jobs_output);
+            return Ok(());
+        }
+        Some(Commands::Backup { path, dry_run }) => {
+            let backup_output = backup_command(&config, &path, dry_run).await?;
+            println!("{}", backup_output);
+            return Ok(());
+        }
+        Some(Commands::Restore { path, dry_run }) => {
+            let restore_output = restore_command(&config, &path, dry_run).await?;
+            println!("{}", restore_output);
+            return Ok(());
+        }
+        Some(Commands::CopyDatabase {
+            source,
+            destination,
+            batch_size,
+        }) => {
+            let copy_output =
+                copy_database_command(&config, &source, &destination, batch_size).await?;
+            println!("{}", copy_output);
+            return Ok(());
+        }
+        Some(Commands::Db { command }) => {
+            let db_output = db_command(&config, &command).await?;
+            println!("{}", db_output);
+            return Ok(());
+        }
+        Some(Commands::RotateEncryptionKey { batch_size }) => {
+            let rotate_output = rotate_key_command(&config, batch_size).await?;
+            println!("{}", rotate_output);
+            return Ok(());
+        }
+        None => {}
     }
 
     // Db Setup
     event!(Level::DEBUG, "Database setup");
 
-    let db_pool = db::setup(config.database_url.as_ref())
+    let db_pool = db::setup_with(&config.database_url, &config.database)
         .await
         .context("Error setting up database connection")?;
 
     // KV Setup
     event!(Level::DEBUG, "Connecting to KV Store");
-    let kv_client = KVClient::new(&config).context("Error building redis client")?;
+    let kv_client = KVClient::new(&config, &db_pool).context("Error building KV client")?;
+
+    // Guild timezones used to live in the KV store only
+    if let Err(e) = migrate_guild_timezones(&db_pool, &kv_client).await {
+        event!(
+            Level::WARN,
+            ?e,
+            "Error migrating guild timezones from the KV store"
+        );
+    }
+
+    // Timezones used to be saved without validation
+    match GuildSettings::repository(&db_pool)
+        .clear_invalid_timezones()
+        .await
+    {
+        Ok(cleared) if !cleared.is_empty() => {
+            event!(Level::INFO, ?cleared, "Cleared invalid guild timezones")
+        }
+        Ok(_) => {}
+        Err(e) => event!(Level::WARN, ?e, "Error clearing invalid guild timezones"),
+    }
+
+    let reminder_cipher = reminder_cipher(&config).context("Error setting up encryption")?;
+
+    // Changes are announced to the API as well
+    let event_bus = EventBus::new(&config).context("Error setting up the event bus")?;
+
+    // Settings saved by the API or another replica are dropped from the cache as soon as they are announced
+    let settings_cache = Arc::new(GuildSettingsCache::default());
+    match event_bus.subscribe().await {
+        Ok(events) => {
+            let settings_cache = settings_cache.clone();
+            tokio::spawn(async move { settings_cache.follow(events).await });
+        }
+        Err(e) => event!(
+            Level::WARN,
+            ?e,
+            "Error subscribing to storage events, changed guild settings show up after the cache checks for them"
+        ),
+    }
 
     // Discord setup
     event!(Level::DEBUG, "Discord client setup");
 
+    // Every unsolicited message goes through the outbound queue.
+    let discord_sender: Arc<dyn DiscordSender> = Arc::new(OutboundQueue::from_config(&config));
+
+    // The commands and the scheduler share the pool and the KV client
+    let scheduler_db_pool = Arc::new(db_pool.clone());
+    let scheduler_kv_client = Arc::new(kv_client.clone());
+
     let discord_config = config.clone();
+    let command_jobs = jobs.clone();
+    let command_sender = discord_sender.clone();
+    let command_event_bus = event_bus.clone();
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![reminder(), timezone(), roll(), register()],
+            commands: vec![
+                reminder(),
+                timezone(),
+                roll(),
+                register(),
+                discord::commands::jobs(),
+                discord::commands::privacy(),
+            ],
+            event_handler: |ctx, event, framework, data| {
+                Box::pin(discord::events::event_handler(ctx, event, framework, data))
+            },
             ..Default::default()
         })
         .setup(|ctx, _ready, framework| {
@@ -71,7 +189,12 @@ async fn main() -> anyhow::Result<()> {
                 Ok(ServerData {
                     kv_client,
                     db_pool,
+                    settings_cache,
+                    reminder_cipher,
                     config: discord_config,
+                    jobs: command_jobs,
+                    discord_sender: command_sender,
+                    event_bus: command_event_bus,
                 })
             })
         })
@@ -79,25 +202,40 @@ async fn main() -> anyhow::Result<()> {
 
     // Set up background scheduling
     event!(Level::INFO, "Setting up background jobs");
-    let shard_key = uuid::Uuid::new_v4();
+    let shard_key = config.shard_key;
     info!(%shard_key);
 
-    let jobs: Vec<Box<dyn Job>> = vec![
-        discord::jobs::reminders(),
-        discord::jobs::reminders_cleanup(),
-    ];
-
     let token = config.discord_token.as_str();
     let intents =
         serenity::GatewayIntents::non_privileged() | serenity::GatewayIntents::MESSAGE_CONTENT;
     let mut discord_client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
         .await?;
-    let http_client = serenity::HttpBuilder::new(token).build();
+
+    // Shutdown handling
+    let (shutdown_sender, shutdown_listener) = shutdown_channel();
+    let shard_manager = discord_client.shard_manager.clone();
+    tokio::spawn(async move {
+        termination_signal().await;
+
+        event!(Level::INFO, "Shutting down");
+        // Stop receiving interactions first, the scheduler gets a grace period to finish its run.
+        _ = shutdown_sender.send(true);
+        shard_manager.shutdown_all().await;
+    });
 
     let (discord_result, scheduler_result) = tokio::join!(
         discord_client.start_autosharded(),
-        job_scheduler(&config, &jobs, &shard_key, &http_client)
+        job_scheduler(
+            &config,
+            &jobs,
+            &shard_key,
+            scheduler_db_pool,
+            scheduler_kv_client,
+            discord_sender,
+            event_bus,
+            shutdown_listener
+        )
     );
 
     if let Err(scheduler_err) = scheduler_result {
@@ -108,5 +246,7 @@ async fn main() -> anyhow::Result<()> {
         event!(Level::ERROR, "{:?}", &discord_error);
     }
 
+    event!(Level::INFO, "Shutdown complete");
+
     Ok(())
 }
@@ -0,0 +1,107 @@
+use tokio::sync::watch;
+use tracing::{event, Level};
+
+/// Create a new shutdown channel.
+///
+/// Sending `true` on the returned sender notifies every (cloned) [`ShutdownListener`].
+pub(crate) fn shutdown_channel() -> (watch::Sender<bool>, ShutdownListener) {
+    let (sender, receiver) = watch::channel(false);
+
+    (sender, ShutdownListener { receiver })
+}
+
+/// Listens for a shutdown request.
+#[derive(Debug, Clone)]
+pub(crate) struct ShutdownListener {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownListener {
+    /// Completes once a shutdown has been requested.
+    ///
+    /// If the sending side is dropped without ever requesting a shutdown, this never completes.
+    pub async fn requested(&mut self) {
+        if self
+            .receiver
+            .wait_for(|requested| *requested)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Check if a shutdown has been requested, without waiting.
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+}
+
+/// Completes when the process receives a termination signal (SIGTERM or Ctrl+C).
+pub(crate) async fn termination_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            event!(Level::ERROR, %e, "Error listening for Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                event!(Level::ERROR, %e, "Error listening for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => event!(Level::INFO, "Received Ctrl+C"),
+        _ = terminate => event!(Level::INFO, "Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn listeners_are_notified() {
+        let (sender, mut listener) = shutdown_channel();
+        let mut cloned = listener.clone();
+
+        assert!(!listener.is_requested());
+
+        sender.send(true).expect("Listeners should still be alive");
+
+        tokio::time::timeout(Duration::from_secs(1), listener.requested())
+            .await
+            .expect("Listener was not notified");
+        tokio::time::timeout(Duration::from_secs(1), cloned.requested())
+            .await
+            .expect("Cloned listener was not notified");
+        assert!(listener.is_requested());
+    }
+
+    #[tokio::test]
+    async fn dropped_sender_does_not_request_shutdown() {
+        let (sender, mut listener) = shutdown_channel();
+        drop(sender);
+
+        let waited = tokio::time::timeout(Duration::from_millis(50), listener.requested()).await;
+
+        assert!(
+            waited.is_err(),
+            "Dropping the sender should not trigger a shutdown"
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use clap::{arg, command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
+
+/// The amount of reminders `copy-database` copies per transaction when none is given.
+pub const DEFAULT_COPY_BATCH_SIZE: u32 = 500;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -18,4 +21,89 @@ pub struct Args {
 pub enum Commands {
     /// Simply performs health checks. Only supported by the bot. Ignored by all the rest
     Healthcheck,
+    /// Manage the background jobs. Only supported by the bot. Ignored by all the rest
+    Jobs {
+        #[command(subcommand)]
+        command: JobCommands,
+    },
+    /// Write all reminders, guild settings and job state to a backup archive. Only supported by the bot. Ignored by all the rest
+    Backup {
+        /// Path of the archive to write
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        path: String,
+        /// Only report what would be backed up, without writing the archive
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Restore a backup archive into a database without reminders. Only supported by the bot. Ignored by all the rest
+    Restore {
+        /// Path of the archive to restore
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        path: String,
+        /// Only check the archive and report what would be restored, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Copy all data to another database, for example from sqlite to Postgres. Only supported by the bot. Ignored by all the rest
+    ///
+    /// Run it again to continue a copy that was interrupted.
+    CopyDatabase {
+        /// Url of the database to copy from
+        source: String,
+        /// Url of the database to copy to, it is migrated before anything is copied
+        destination: String,
+        /// The amount of reminders copied per transaction
+        #[arg(long, default_value_t = DEFAULT_COPY_BATCH_SIZE)]
+        batch_size: u32,
+    },
+    /// Show, apply or revert the database migrations. Only supported by the bot. Ignored by all the rest
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Encrypt all reminders with the current encryption key. Only supported by the bot. Ignored by all the rest
+    ///
+    /// Reminders that were saved before encryption was enabled are encrypted as well.
+    RotateEncryptionKey {
+        /// The amount of reminders encrypted per transaction
+        #[arg(long, default_value_t = 500)]
+        batch_size: u32,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialOrd, PartialEq)]
+pub enum DbCommands {
+    /// List the migrations and whether they are applied
+    Status,
+    /// Apply the pending migrations
+    Migrate,
+    /// Revert the migrations after a version, this drops the tables and columns they added
+    Revert {
+        /// The version to go back to
+        version: i64,
+        /// Only report which migrations would be reverted
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialOrd, PartialEq)]
+pub enum JobCommands {
+    /// List all registered jobs and whether they are paused
+    List,
+    /// Run a job immediately, even when it is paused
+    Run {
+        /// The name of the job
+        name: String,
+    },
+    /// Pause a job on every bot instance
+    Pause {
+        /// The name of the job
+        name: String,
+    },
+    /// Resume a paused job on every bot instance
+    Resume {
+        /// The name of the job
+        name: String,
+    },
 }
@@ -1,14 +1,15 @@
 //! Everything used to handle the application configuration.
 //!
 //! Create a new configuration as follows:
-//! ```rust
+//! ```rust,no_run
 //! use fercord_common::prelude::*;
 //! let config = DiscordConfig::from_env().unwrap();
 //! // or when you want to use a file and only overwrite from env
 //! let config = DiscordConfig::from_env_and_file("../.config/config.toml").unwrap();
 //! ```
 
-use std::num::NonZeroU64;
+use std::collections::BTreeMap;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
 
 use tracing::{event, Level};
 
@@ -22,11 +23,21 @@ use figment::{providers::{Env, Format, Toml}, Figment};
 /// Settings:
 /// * `discord_token`: `String`
 /// * `database_url`: `String`
-/// * `redis_url`: `String`
+/// * `redis_url`: `Option<String>`
+/// * `kv_store`: `KVStoreKind` (default: `redis` when `redis_url` is set, `database` otherwise)
+/// * `kv_prefix`: `Option<String>`
 /// * `job_interval_min`: `u32`
+/// * `job_timeout_sec`: `u32` (default: 60)
+/// * `job_concurrency`: `NonZeroUsize` (default: 2)
+/// * `shutdown_grace_sec`: `u32` (default: 30)
+/// * `message_concurrency`: `NonZeroUsize` (default: 4)
+/// * `guild_purge_grace_hours`: `u32` (default: 168)
 /// * `session_key`: `String`
+/// * `session_store`: `SessionStoreKind` (default: `redis` when `redis_url` is set, `database` otherwise)
 /// * `client_id`: `NonZeroU64`
 /// * `client_secret`: `String`
+/// * `database`: [`DatabaseConfig`], the `[database]` section (default: see [`DatabaseConfig`])
+/// * `encryption`: `Option<EncryptionConfig>`, the `[encryption]` section
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 pub struct DiscordConfig {
     /// The Discord API token.
@@ -36,9 +47,32 @@ pub struct DiscordConfig {
     /// Usual format: `postgres://usernane:pw@server/db`
     pub database_url: String,
     /// Url to indicate the redis instance to use.
-    pub redis_url: String,
+    ///
+    /// Only required when the KV store is redis.
+    pub redis_url: Option<String>,
+    /// Where the KV store lives, see [`DiscordConfig::kv_store_kind`] for the default.
+    #[serde(default)]
+    pub kv_store: Option<KVStoreKind>,
+    /// Prefix for every KV key, so multiple deployments can share a KV store.
+    #[serde(default)]
+    pub kv_prefix: Option<String>,
     /// Job interval in minutes
     pub job_interval_min: u32,
+    /// Maximum time in seconds a single job may run before it is cancelled.
+    #[serde(default = "default_job_timeout_sec")]
+    pub job_timeout_sec: u32,
+    /// Maximum amount of jobs that are allowed to run at the same time.
+    #[serde(default = "default_job_concurrency")]
+    pub job_concurrency: NonZeroUsize,
+    /// Time in seconds that running work gets to finish after a shutdown was requested.
+    #[serde(default = "default_shutdown_grace_sec")]
+    pub shutdown_grace_sec: u32,
+    /// Maximum amount of channels the bot delivers unsolicited messages to at the same time.
+    #[serde(default = "default_message_concurrency")]
+    pub message_concurrency: NonZeroUsize,
+    /// Time in hours the data of a guild is kept after the bot was removed from it, or after a channel was deleted.
+    #[serde(default = "default_guild_purge_grace_hours")]
+    pub guild_purge_grace_hours: u32,
     /// The unique shard key that defines this bot server.
     ///
     /// Used when multiple servers share the same key-value store.
@@ -47,6 +81,9 @@ pub struct DiscordConfig {
     ///
     /// This is used by the API.
     pub session_key: Option<String>,
+    /// Where the API keeps its sessions, see [`DiscordConfig::session_store_kind`] for the default.
+    #[serde(default)]
+    pub session_store: Option<SessionStoreKind>,
     /// Discord OAuth Client Id.
     ///
     /// This is used by the API
@@ -55,19 +92,183 @@ pub struct DiscordConfig {
     ///
     /// This is used by the API.
     pub client_secret: Option<String>,
+    /// Settings for the database connections.
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    /// Encryption of reminder contents. Without it they are stored as plaintext.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+}
+
+/// Keys for the encryption of reminder contents, the `[encryption]` section of the config file.
+///
+/// Every encrypted value is tagged with the id of its key. To take a new key in use, move the current one to
+/// `previous_keys` and run the `rotate-encryption-key` subcommand of the bot.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+pub struct EncryptionConfig {
+    /// The id of the key new values are encrypted with.
+    pub key_id: String,
+    /// Base64 encoded 256-bit key.
+    pub key: String,
+    /// Base64 encoded keys that were used before, by their id. They are only used to decrypt.
+    #[serde(default)]
+    pub previous_keys: BTreeMap<String, String>,
+}
+
+impl std::fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("key_id", &self.key_id)
+            .field("key", &"<redacted>")
+            .field("previous_keys", &self.previous_keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Settings for the database connection pool and migrations, the `[database]` section of the config file.
+///
+/// From the environment they are set with a double underscore, for example `FERCORD_DATABASE__MAX_CONNECTIONS`.
+///
+/// Settings:
+/// * `max_connections`: `NonZeroU32` (default: 5)
+/// * `min_connections`: `u32` (default: 0)
+/// * `acquire_timeout_sec`: `u64` (default: 30)
+/// * `idle_timeout_sec`: `u64` (default: 600, 0 keeps idle connections open)
+/// * `sqlite_journal_mode`: [`SqliteJournalMode`] (default: `wal`)
+/// * `sqlite_busy_timeout_ms`: `u64` (default: 5000)
+/// * `sqlite_synchronous`: [`SqliteSynchronous`] (default: `normal`)
+/// * `log_statements`: [`StatementLogLevel`] (default: `info`)
+/// * `auto_migrate`: `bool` (default: `true`)
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Maximum amount of connections in the pool, shared by everything in the process.
+    pub max_connections: NonZeroU32,
+    /// Amount of connections the pool keeps open, even when they are not used.
+    pub min_connections: u32,
+    /// Maximum time in seconds to wait for a free connection.
+    pub acquire_timeout_sec: u64,
+    /// Time in seconds after which an unused connection is closed. 0 keeps them open.
+    pub idle_timeout_sec: u64,
+    /// Journal mode of sqlite databases. Ignored for other databases.
+    pub sqlite_journal_mode: SqliteJournalMode,
+    /// Time in milliseconds sqlite waits for a locked database. Ignored for other databases.
+    pub sqlite_busy_timeout_ms: u64,
+    /// How often sqlite waits for writes to reach the disk. Ignored for other databases.
+    pub sqlite_synchronous: SqliteSynchronous,
+    /// The level at which every executed statement is logged.
+    pub log_statements: StatementLogLevel,
+    /// Apply pending migrations when connecting. When disabled, they are applied with the `db migrate` subcommand.
+    pub auto_migrate: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: NonZeroU32::new(5).unwrap(),
+            min_connections: 0,
+            acquire_timeout_sec: 30,
+            idle_timeout_sec: 600,
+            sqlite_journal_mode: SqliteJournalMode::Wal,
+            sqlite_busy_timeout_ms: 5000,
+            sqlite_synchronous: SqliteSynchronous::Normal,
+            log_statements: StatementLogLevel::Info,
+            auto_migrate: true,
+        }
+    }
+}
+
+/// The [journal modes](https://www.sqlite.org/pragma.html#pragma_journal_mode) of sqlite.
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteJournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    /// Readers do not block the writer and the other way around.
+    Wal,
+    Off,
+}
+
+/// The [synchronous settings](https://www.sqlite.org/pragma.html#pragma_synchronous) of sqlite.
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteSynchronous {
+    Off,
+    /// Safe in combination with the `wal` journal mode, a power loss can only lose the last transactions.
+    Normal,
+    Full,
+    Extra,
+}
+
+/// Log levels for executed statements.
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementLogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// The kinds of key-value store the application can use.
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum KVStoreKind {
+    /// A redis server, see `redis_url`.
+    Redis,
+    /// The memory of the process. Nothing survives a restart and nothing is shared between the bot and the API.
+    Memory,
+    /// A table in the database of `database_url`.
+    Database,
+}
+
+/// The kinds of session store the API can use.
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    /// A redis server, see `redis_url`.
+    Redis,
+    /// A table in the database of `database_url`. Expired sessions are deleted by the API every `job_interval_min`
+    /// and by the `maintenance` job of the bot. The state is encrypted when `encryption` is configured.
+    Database,
 }
 
 const ENV_PREFIX: &str = "FERCORD_";
 
+fn default_job_timeout_sec() -> u32 {
+    60
+}
+
+fn default_job_concurrency() -> NonZeroUsize {
+    NonZeroUsize::new(2).unwrap()
+}
+
+fn default_shutdown_grace_sec() -> u32 {
+    30
+}
+
+fn default_guild_purge_grace_hours() -> u32 {
+    7 * 24
+}
+
+fn default_message_concurrency() -> NonZeroUsize {
+    NonZeroUsize::new(4).unwrap()
+}
+
 impl DiscordConfig {
     /// Create a configuration just from environment variables.
     ///  
     /// This will read all variables prefixed with `FERCORD_` and try to serialize them into a `DiscordConfig`.
-    #[allow(dead_code)]
+    /// A double underscore separates the section from the setting, as in `FERCORD_DATABASE__MAX_CONNECTIONS`.
+    #[allow(dead_code, clippy::result_large_err)]
     #[tracing::instrument]
     pub fn from_env() -> Result<Self, Error> {
         let figment = Figment::new()
-            .merge(Env::prefixed(ENV_PREFIX));
+            .merge(Env::prefixed(ENV_PREFIX).split("__"));
 
         figment.extract()
     }
@@ -77,7 +278,7 @@ impl DiscordConfig {
     /// The file is prioritised. You can use the environment variables to overwrite certain file values.
     ///
     /// For more info about how the environment variables are read, see [from_env()](#from_env).
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::result_large_err)]
     #[tracing::instrument]
     pub fn from_env_and_file(path: &str) -> Result<Self, Error> {
         event!(
@@ -94,12 +295,31 @@ impl DiscordConfig {
         }
 
         let env_figment = Figment::new()
-            .merge(Env::prefixed(ENV_PREFIX));
+            .merge(Env::prefixed(ENV_PREFIX).split("__"));
 
 
         file_figment.merge(env_figment).extract()
     }
 
+    /// The KV store to use. When none is configured that is redis if there is a `redis_url`, the database otherwise.
+    pub fn kv_store_kind(&self) -> KVStoreKind {
+        match (self.kv_store, &self.redis_url) {
+            (Some(kind), _) => kind,
+            (None, Some(_)) => KVStoreKind::Redis,
+            (None, None) => KVStoreKind::Database,
+        }
+    }
+
+    /// The session store of the API. When none is configured that is redis if there is a `redis_url`, the database
+    /// otherwise.
+    pub fn session_store_kind(&self) -> SessionStoreKind {
+        match (self.session_store, &self.redis_url) {
+            (Some(kind), _) => kind,
+            (None, Some(_)) => SessionStoreKind::Redis,
+            (None, None) => SessionStoreKind::Database,
+        }
+    }
+
     /// Checks if all the required fields are set in the configuration that the API server
     pub fn is_valid_api_config(&self) -> bool {
         let client_secret = self.client_secret.clone().is_some_and(|s| !s.is_empty());
@@ -117,6 +337,7 @@ impl Default for DiscordConfig {
 }
 
 #[cfg(test)]
+#[allow(clippy::result_large_err)]
 mod tests {
     //! Tests are run with the working directory set to the work space, not the directory of the source file.
 
@@ -163,12 +384,22 @@ mod tests {
             let expected = DiscordConfig {
                 discord_token: "111".into(),
                 database_url: "sqlite://:memory:".into(),
-                redis_url: "redis://localhost".into(),
+                redis_url: Some("redis://localhost".into()),
+                kv_store: None,
+                kv_prefix: None,
                 job_interval_min: 1,
+                job_timeout_sec: 60,
+                job_concurrency: NonZeroUsize::new(2).unwrap(),
+                shutdown_grace_sec: 30,
+                message_concurrency: NonZeroUsize::new(4).unwrap(),
+                guild_purge_grace_hours: 168,
                 shard_key: uuid::uuid!("c69b7bb6-0ca4-40da-8bad-26d9d4d2fb50"),
                 session_key: Some("1hYw2n0+t8SDo+gqy+Q3x2SJ4u/Y6e6QPrMHExaQTHETOD8tlUsR2Cq66H0a2QuGBK7L1TIDhAupc3rHCbiehw==".into()),
+                session_store: None,
                 client_secret: None,
                 client_id: Some(NonZeroU64::new(948517362313863198).unwrap()),
+                database: DatabaseConfig::default(),
+                encryption: None,
             };
 
             let config = DiscordConfig::from_env_and_file("config.toml")?;
@@ -190,12 +421,22 @@ mod tests {
             let expected = DiscordConfig {
                 discord_token: "222".into(),
                 database_url: "sqlite://:memory:".into(),
-                redis_url: "redis://localhost".into(),
+                redis_url: Some("redis://localhost".into()),
+                kv_store: None,
+                kv_prefix: None,
                 job_interval_min: 1,
+                job_timeout_sec: 60,
+                job_concurrency: NonZeroUsize::new(2).unwrap(),
+                shutdown_grace_sec: 30,
+                message_concurrency: NonZeroUsize::new(4).unwrap(),
+                guild_purge_grace_hours: 168,
                 shard_key: uuid::uuid!("c69b7bb6-0ca4-40da-8bad-26d9d4d2fb50"),
                 session_key: Some("1hYw2n0+t8SDo+gqy+Q3x2SJ4u/Y6e6QPrMHExaQTHETOD8tlUsR2Cq66H0a2QuGBK7L1TIDhAupc3rHCbiehw==".into()),
+                session_store: None,
                 client_secret: Some("supersecret".into()),
                 client_id: Some(NonZeroU64::new(948517362313863198).unwrap()),
+                database: DatabaseConfig::default(),
+                encryption: None,
             };
 
             let config = DiscordConfig::from_env_and_file("config.toml")?;
@@ -204,4 +445,65 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn kv_store_defaults_to_database_without_redis() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file("config.toml", &TEST_CONFIG.replace(r#"redis_url = "redis://localhost""#, ""))?;
+
+            let config = DiscordConfig::from_env_and_file("config.toml")?;
+            assert_eq!(None, config.redis_url);
+            assert_eq!(KVStoreKind::Database, config.kv_store_kind());
+
+            jail.set_env(format!("{}{}", ENV_PREFIX, "KV_STORE"), "memory");
+            let config = DiscordConfig::from_env_and_file("config.toml")?;
+            assert_eq!(KVStoreKind::Memory, config.kv_store_kind());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn session_store_defaults_to_redis_when_configured() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file("config.toml", TEST_CONFIG)?;
+
+            let config = DiscordConfig::from_env_and_file("config.toml")?;
+            assert_eq!(SessionStoreKind::Redis, config.session_store_kind());
+
+            jail.set_env(format!("{}{}", ENV_PREFIX, "SESSION_STORE"), "database");
+            let config = DiscordConfig::from_env_and_file("config.toml")?;
+            assert_eq!(SessionStoreKind::Database, config.session_store_kind());
+
+            jail.clear_env();
+            jail.create_file("config.toml", &TEST_CONFIG.replace(r#"redis_url = "redis://localhost""#, ""))?;
+            let config = DiscordConfig::from_env_and_file("config.toml")?;
+            assert_eq!(SessionStoreKind::Database, config.session_store_kind());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn database_section_is_read_from_file_and_env() {
+        figment::Jail::expect_with(|jail| {
+            let config_file = format!("{TEST_CONFIG}\n[database]\nmax_connections = 10\nsqlite_journal_mode = \"delete\"\n");
+            jail.create_file("config.toml", &config_file)?;
+            jail.set_env(format!("{}{}", ENV_PREFIX, "DATABASE__LOG_STATEMENTS"), "debug");
+            jail.set_env(format!("{}{}", ENV_PREFIX, "DATABASE__AUTO_MIGRATE"), "false");
+
+            let config = DiscordConfig::from_env_and_file("config.toml")?;
+            let expected = DatabaseConfig {
+                max_connections: NonZeroU32::new(10).unwrap(),
+                sqlite_journal_mode: SqliteJournalMode::Delete,
+                log_statements: StatementLogLevel::Debug,
+                auto_migrate: false,
+                ..Default::default()
+            };
+            assert_eq!(expected, config.database);
+            assert_eq!("sqlite://:memory:", config.database_url);
+
+            Ok(())
+        });
+    }
 }
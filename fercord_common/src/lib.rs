@@ -10,5 +10,6 @@ pub mod prelude {
 
     pub use crate::cli::Args;
     pub use crate::cli::Commands;
-    pub use crate::config::DiscordConfig;
+    pub use crate::cli::JobCommands;
+    pub use crate::config::{DatabaseConfig, DiscordConfig, KVStoreKind, SessionStoreKind};
 }
@@ -0,0 +1 @@
+ALTER TABLE public.reminders DROP CONSTRAINT reminders_pkey;
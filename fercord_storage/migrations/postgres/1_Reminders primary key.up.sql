@@ -0,0 +1,3 @@
+-- Make the reminder id the primary key, like it is in sqlite
+
+ALTER TABLE public.reminders ADD CONSTRAINT reminders_pkey PRIMARY KEY (id);
@@ -0,0 +1,10 @@
+-- Back to text columns and a timestamptz for "when"
+
+DROP INDEX public.reminders_who_idx;
+DROP INDEX public.reminders_server_idx;
+
+ALTER TABLE public.reminders
+    ALTER COLUMN who TYPE varchar USING who::varchar,
+    ALTER COLUMN "when" TYPE timestamptz USING to_timestamp("when"),
+    ALTER COLUMN "server" TYPE varchar USING "server"::varchar,
+    ALTER COLUMN channel TYPE varchar USING channel::varchar;
@@ -0,0 +1,11 @@
+-- Store timestamps and snowflakes as integers, like the sqlite schema. "when" is a unix timestamp in seconds.
+-- A snowflake that is not a number fails the cast, which aborts the migration instead of losing or changing the row.
+
+ALTER TABLE public.reminders
+    ALTER COLUMN who TYPE bigint USING who::bigint,
+    ALTER COLUMN "when" TYPE bigint USING floor(extract(epoch FROM "when"))::bigint,
+    ALTER COLUMN "server" TYPE bigint USING "server"::bigint,
+    ALTER COLUMN channel TYPE bigint USING channel::bigint;
+
+CREATE INDEX reminders_who_idx ON public.reminders (who);
+CREATE INDEX reminders_server_idx ON public.reminders ("server");
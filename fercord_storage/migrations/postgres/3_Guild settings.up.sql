@@ -0,0 +1,6 @@
+-- Per guild settings, these used to be stored in the KV store only
+
+CREATE TABLE public.guild_settings (
+    guild_id bigint NOT NULL PRIMARY KEY,
+    timezone varchar NULL
+);
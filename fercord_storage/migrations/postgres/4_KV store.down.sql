@@ -0,0 +1 @@
+DROP TABLE public.kv_store;
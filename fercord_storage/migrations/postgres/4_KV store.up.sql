@@ -0,0 +1,6 @@
+-- Key-value store for installs that run without redis
+
+CREATE TABLE public.kv_store (
+    kv_key varchar NOT NULL PRIMARY KEY,
+    kv_value varchar NOT NULL
+);
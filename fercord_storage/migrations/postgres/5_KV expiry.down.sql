@@ -0,0 +1 @@
+ALTER TABLE public.kv_store DROP COLUMN expires_at;
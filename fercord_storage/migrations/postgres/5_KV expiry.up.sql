@@ -0,0 +1,3 @@
+-- Keys can expire, expires_at is a unix timestamp in milliseconds
+
+ALTER TABLE public.kv_store ADD COLUMN expires_at bigint NULL;
@@ -0,0 +1,3 @@
+DROP TABLE public.guild_departures;
+
+ALTER TABLE public.reminders DROP COLUMN undeliverable_since;
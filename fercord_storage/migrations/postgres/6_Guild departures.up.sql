@@ -0,0 +1,12 @@
+-- Reminders that can not be delivered anymore, because the bot left the guild or the channel was deleted.
+-- undeliverable_since is a unix timestamp in seconds, those reminders are purged after a grace period.
+
+ALTER TABLE public.reminders ADD COLUMN undeliverable_since bigint NULL;
+
+-- The guilds the bot was removed from, their data is purged after the grace period.
+-- left_at is a unix timestamp in seconds.
+
+CREATE TABLE public.guild_departures (
+    guild_id bigint NOT NULL PRIMARY KEY,
+    left_at bigint NOT NULL
+);
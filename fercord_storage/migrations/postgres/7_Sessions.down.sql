@@ -0,0 +1,3 @@
+DROP INDEX public.sessions_expires_at_idx;
+
+DROP TABLE public.sessions;
@@ -0,0 +1,10 @@
+-- Sessions of the web API, for installs that run without redis.
+-- state is the session data as JSON, expires_at is a unix timestamp in seconds.
+
+CREATE TABLE public.sessions (
+    session_key varchar NOT NULL PRIMARY KEY,
+    state varchar NOT NULL,
+    expires_at bigint NOT NULL
+);
+
+CREATE INDEX sessions_expires_at_idx ON public.sessions (expires_at);
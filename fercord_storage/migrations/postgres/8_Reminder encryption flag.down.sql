@@ -0,0 +1 @@
+ALTER TABLE public.reminders DROP COLUMN what_encrypted;
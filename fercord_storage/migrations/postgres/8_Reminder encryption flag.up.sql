@@ -0,0 +1,5 @@
+-- Whether "what" is encrypted, so plaintext that happens to look like an encrypted value is never decrypted.
+ALTER TABLE public.reminders ADD COLUMN what_encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Until now the prefix was the only marker.
+UPDATE public.reminders SET what_encrypted = TRUE WHERE what LIKE 'enc:v1:%';
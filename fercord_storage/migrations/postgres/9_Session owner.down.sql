@@ -0,0 +1,5 @@
+DROP INDEX public.sessions_user_id_idx;
+
+ALTER TABLE public.sessions DROP COLUMN state_encrypted;
+
+ALTER TABLE public.sessions DROP COLUMN user_id;
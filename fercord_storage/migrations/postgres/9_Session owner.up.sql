@@ -0,0 +1,12 @@
+-- The Discord user that logged in with the session, so the sessions of a user can be exported and deleted.
+-- NULL until the user logged in.
+ALTER TABLE public.sessions ADD COLUMN user_id bigint;
+
+-- The sessions from before do not name their user, so they could not be exported or deleted for them.
+-- Their users have to log in again.
+DELETE FROM public.sessions WHERE user_id IS NULL;
+
+-- Whether state is encrypted with the key of the [encryption] section.
+ALTER TABLE public.sessions ADD COLUMN state_encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX sessions_user_id_idx ON public.sessions (user_id);
@@ -0,0 +1,25 @@
+-- Back to text columns, "when" becomes an RFC 3339 timestamp again
+
+CREATE TABLE reminders_v1 (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	who TEXT(128),
+	"when" TEXT(128),
+	what TEXT(1024),
+	server TEXT(128),
+	channel TEXT(128)
+);
+
+INSERT INTO reminders_v1 (id, who, "when", what, server, channel)
+SELECT id, CAST(who AS TEXT), strftime('%Y-%m-%dT%H:%M:%SZ', "when", 'unixepoch'), what, CAST(server AS TEXT), CAST(channel AS TEXT)
+FROM reminders;
+
+-- The quarantined rows go back as they were
+INSERT INTO reminders_v1 (id, who, "when", what, server, channel)
+SELECT id, who, "when", what, server, channel
+FROM reminders_quarantine;
+
+DROP TABLE reminders_quarantine;
+
+DROP TABLE reminders;
+
+ALTER TABLE reminders_v1 RENAME TO reminders;
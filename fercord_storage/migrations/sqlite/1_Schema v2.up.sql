@@ -0,0 +1,50 @@
+-- Store timestamps and snowflakes as integers, so they can be indexed and compared without conversions.
+-- "when" is a unix timestamp in seconds. Rows that can not be converted are moved to reminders_quarantine as they are,
+-- with the reason, instead of being dropped or turned into zeroes.
+
+CREATE TABLE reminders_v2 (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	who INTEGER NOT NULL,
+	"when" INTEGER NOT NULL,
+	what TEXT NOT NULL,
+	server INTEGER NOT NULL,
+	channel INTEGER NOT NULL
+);
+
+-- A snowflake only converts when it reads the same as an integer, which rules out text, fractions and overflows.
+INSERT INTO reminders_v2 (id, who, "when", what, server, channel)
+SELECT id, CAST(who AS INTEGER), unixepoch("when"), what, CAST(server AS INTEGER), CAST(channel AS INTEGER)
+FROM reminders
+WHERE CAST(CAST(who AS INTEGER) AS TEXT) = who
+	AND unixepoch("when") IS NOT NULL
+	AND what IS NOT NULL
+	AND CAST(CAST(server AS INTEGER) AS TEXT) = server
+	AND CAST(CAST(channel AS INTEGER) AS TEXT) = channel;
+
+CREATE TABLE reminders_quarantine (
+	id INTEGER PRIMARY KEY,
+	who TEXT,
+	"when" TEXT,
+	what TEXT,
+	server TEXT,
+	channel TEXT,
+	reason TEXT NOT NULL
+);
+
+INSERT INTO reminders_quarantine (id, who, "when", what, server, channel, reason)
+SELECT id, who, "when", what, server, channel,
+	CASE
+		WHEN who IS NULL OR "when" IS NULL OR what IS NULL OR server IS NULL OR channel IS NULL THEN 'missing value'
+		WHEN unixepoch("when") IS NULL THEN 'invalid when'
+		ELSE 'invalid snowflake'
+	END
+FROM reminders
+WHERE id NOT IN (SELECT id FROM reminders_v2);
+
+DROP TABLE reminders;
+
+ALTER TABLE reminders_v2 RENAME TO reminders;
+
+CREATE INDEX reminders_when_idx ON reminders ("when");
+CREATE INDEX reminders_who_idx ON reminders (who);
+CREATE INDEX reminders_server_idx ON reminders (server);
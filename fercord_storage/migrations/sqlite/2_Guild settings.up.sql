@@ -0,0 +1,6 @@
+-- Per guild settings, these used to be stored in the KV store only
+
+CREATE TABLE guild_settings (
+	guild_id INTEGER PRIMARY KEY NOT NULL,
+	timezone TEXT NULL
+);
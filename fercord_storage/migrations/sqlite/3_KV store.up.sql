@@ -0,0 +1,6 @@
+-- Key-value store for installs that run without redis
+
+CREATE TABLE kv_store (
+	kv_key TEXT PRIMARY KEY NOT NULL,
+	kv_value TEXT NOT NULL
+);
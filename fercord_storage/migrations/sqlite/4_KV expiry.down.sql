@@ -0,0 +1 @@
+ALTER TABLE kv_store DROP COLUMN expires_at;
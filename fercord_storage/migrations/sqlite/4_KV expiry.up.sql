@@ -0,0 +1,3 @@
+-- Keys can expire, expires_at is a unix timestamp in milliseconds
+
+ALTER TABLE kv_store ADD COLUMN expires_at INTEGER NULL;
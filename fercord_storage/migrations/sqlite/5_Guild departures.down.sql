@@ -0,0 +1,3 @@
+DROP TABLE guild_departures;
+
+ALTER TABLE reminders DROP COLUMN undeliverable_since;
@@ -0,0 +1,12 @@
+-- Reminders that can not be delivered anymore, because the bot left the guild or the channel was deleted.
+-- undeliverable_since is a unix timestamp in seconds, those reminders are purged after a grace period.
+
+ALTER TABLE reminders ADD COLUMN undeliverable_since INTEGER NULL;
+
+-- The guilds the bot was removed from, their data is purged after the grace period.
+-- left_at is a unix timestamp in seconds.
+
+CREATE TABLE guild_departures (
+	guild_id INTEGER PRIMARY KEY NOT NULL,
+	left_at INTEGER NOT NULL
+);
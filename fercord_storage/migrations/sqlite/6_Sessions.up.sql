@@ -0,0 +1,10 @@
+-- Sessions of the web API, for installs that run without redis.
+-- state is the session data as JSON, expires_at is a unix timestamp in seconds.
+
+CREATE TABLE sessions (
+	session_key TEXT PRIMARY KEY NOT NULL,
+	state TEXT NOT NULL,
+	expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
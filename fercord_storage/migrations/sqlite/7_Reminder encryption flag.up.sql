@@ -0,0 +1,5 @@
+-- Whether "what" is encrypted, so plaintext that happens to look like an encrypted value is never decrypted.
+ALTER TABLE reminders ADD COLUMN what_encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Until now the prefix was the only marker. GLOB is case sensitive, LIKE is not.
+UPDATE reminders SET what_encrypted = TRUE WHERE what GLOB 'enc:v1:*';
@@ -0,0 +1,554 @@
+//! Backups of everything fercord stores, as a JSON lines archive.
+//!
+//! The first line of an archive is an [`ArchiveHeader`], every other line is a single [`ArchiveRecord`]. The header
+//! carries the version of the archive format, so newer archives are refused instead of half restored.
+
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx_oldapi::AnyPool;
+use tracing::{event, Level};
+
+use crate::db::Repository;
+use crate::kv::{KVClient, KVIdentity};
+use crate::model::{GuildDeparture, GuildSettings, Reminder, ReminderFilter, ReminderListOptions};
+
+/// Identifies a file as a fercord backup.
+pub const ARCHIVE_FORMAT: &str = "fercord-backup";
+/// The version of the archive format this version of fercord writes. Archives up to this version can be restored.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// The amount of reminders read from the database at a time while backing up.
+const BACKUP_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveHeader {
+    pub format: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ArchiveHeader {
+    fn new() -> Self {
+        Self {
+            format: ARCHIVE_FORMAT.into(),
+            version: ARCHIVE_VERSION,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// A single line of an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    Header(ArchiveHeader),
+    Reminder(Reminder),
+    GuildSettings(GuildSettings),
+    GuildDeparture(GuildDeparture),
+    Kv(KvEntry),
+}
+
+/// A KV store entry, the value is kept exactly as it was stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvEntry {
+    pub key: KVIdentity,
+    pub value: String,
+    /// When the entry expires, `None` if it never does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// What an archive holds, or would hold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    pub reminders: u64,
+    pub guild_settings: u64,
+    pub guild_departures: u64,
+    pub kv_entries: u64,
+}
+
+impl Display for ArchiveSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} reminders, {} guild settings, {} guild departures and {} KV entries",
+            self.reminders, self.guild_settings, self.guild_departures, self.kv_entries
+        )
+    }
+}
+
+/// The contents of an archive, read and checked completely before anything is restored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub header: ArchiveHeader,
+    pub reminders: Vec<Reminder>,
+    pub guild_settings: Vec<GuildSettings>,
+    pub guild_departures: Vec<GuildDeparture>,
+    pub kv_entries: Vec<KvEntry>,
+}
+
+impl Archive {
+    pub fn summary(&self) -> ArchiveSummary {
+        ArchiveSummary {
+            reminders: self.reminders.len() as u64,
+            guild_settings: self.guild_settings.len() as u64,
+            guild_departures: self.guild_departures.len() as u64,
+            kv_entries: self.kv_entries.len() as u64,
+        }
+    }
+}
+
+fn write_record(out: &mut impl Write, record: &ArchiveRecord) -> Result<()> {
+    serde_json::to_writer(&mut *out, record).context("Error writing to the archive")?;
+    out.write_all(b"\n")
+        .context("Error writing to the archive")?;
+
+    Ok(())
+}
+
+/// Write all reminders, all guild settings, all guild departures and the KV entries with keys that match one of `kv_patterns` to `out`.
+///
+/// Pass [`std::io::sink`] to only find out what would be backed up.
+pub async fn write_backup(
+    pool: &AnyPool,
+    kv_client: &KVClient,
+    kv_patterns: &[&str],
+    mut out: impl Write,
+) -> Result<ArchiveSummary> {
+    let mut summary = ArchiveSummary::default();
+    write_record(&mut out, &ArchiveRecord::Header(ArchiveHeader::new()))?;
+
+    let repo = Reminder::repository(pool);
+    let mut options = ReminderListOptions {
+        limit: BACKUP_PAGE_SIZE,
+        ..Default::default()
+    };
+    loop {
+        let page = repo.list(&options).await?;
+        for reminder in page.items {
+            write_record(&mut out, &ArchiveRecord::Reminder(reminder))?;
+            summary.reminders += 1;
+        }
+
+        match page.next {
+            Some(next) => options.after = Some(next),
+            None => break,
+        }
+    }
+
+    for settings in GuildSettings::repository(pool).all_guild_settings().await? {
+        write_record(&mut out, &ArchiveRecord::GuildSettings(settings))?;
+        summary.guild_settings += 1;
+    }
+
+    for departure in GuildDeparture::repository(pool)
+        .all_guild_departures()
+        .await?
+    {
+        write_record(&mut out, &ArchiveRecord::GuildDeparture(departure))?;
+        summary.guild_departures += 1;
+    }
+
+    let mut keys = Vec::new();
+    for pattern in kv_patterns {
+        keys.extend(kv_client.keys(pattern).await?);
+    }
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        // Keys can expire while we are busy
+        let Some((value, ttl)) = kv_client.get_raw_with_ttl(&key).await? else {
+            continue;
+        };
+        let expires_at = ttl.map(|ttl| {
+            TimeDelta::from_std(ttl)
+                .ok()
+                .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        });
+
+        write_record(
+            &mut out,
+            &ArchiveRecord::Kv(KvEntry {
+                key,
+                value,
+                expires_at,
+            }),
+        )?;
+        summary.kv_entries += 1;
+    }
+
+    out.flush().context("Error writing to the archive")?;
+    event!(Level::INFO, %summary, "Wrote backup");
+
+    Ok(summary)
+}
+
+/// Read an archive and check that this version of fercord can restore it.
+pub fn read_archive(input: impl BufRead) -> Result<Archive> {
+    let mut header = None;
+    let mut reminders = Vec::new();
+    let mut guild_settings = Vec::new();
+    let mut guild_departures = Vec::new();
+    let mut kv_entries = Vec::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.context("Error reading the archive")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: ArchiveRecord = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid record on line {} of the archive", line_number))?;
+
+        match (record, &header) {
+            (ArchiveRecord::Header(found), None) => {
+                check_header(&found)?;
+                header = Some(found);
+            }
+            (ArchiveRecord::Header(_), Some(_)) => {
+                bail!(
+                    "Unexpected second header on line {} of the archive",
+                    line_number
+                )
+            }
+            (_, None) => bail!("The archive does not start with a header"),
+            (ArchiveRecord::Reminder(reminder), Some(_)) => reminders.push(reminder),
+            (ArchiveRecord::GuildSettings(settings), Some(_)) => guild_settings.push(settings),
+            (ArchiveRecord::GuildDeparture(departure), Some(_)) => guild_departures.push(departure),
+            (ArchiveRecord::Kv(entry), Some(_)) => kv_entries.push(entry),
+        }
+    }
+
+    let Some(header) = header else {
+        bail!("The archive is empty");
+    };
+
+    Ok(Archive {
+        header,
+        reminders,
+        guild_settings,
+        guild_departures,
+        kv_entries,
+    })
+}
+
+fn check_header(header: &ArchiveHeader) -> Result<()> {
+    if header.format != ARCHIVE_FORMAT {
+        bail!(
+            "This is not a fercord backup, its format is {}",
+            header.format
+        );
+    }
+    if header.version == 0 || header.version > ARCHIVE_VERSION {
+        bail!(
+            "The archive has version {}, this version of fercord can restore up to version {}",
+            header.version,
+            ARCHIVE_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+/// Restore an archive. Reminders keep their ids, so the database may not have any reminders yet.
+///
+/// The reminders, guild settings and guild departures are restored in a single transaction, so a failed restore leaves the database as
+/// it was and can simply be retried. Guild settings and KV entries replace the ones that exist, KV entries keep the
+/// moment they expire and the ones that expired since the backup are skipped. With `dry_run` everything is checked,
+/// but nothing is written.
+pub async fn restore_backup(
+    pool: &AnyPool,
+    kv_client: &KVClient,
+    archive: &Archive,
+    dry_run: bool,
+) -> Result<ArchiveSummary> {
+    let repo = Reminder::repository(pool);
+    let existing = repo.count(&ReminderFilter::default()).await?;
+    if existing > 0 {
+        bail!(
+            "The database already has {} reminders, backups can only be restored into a database without reminders",
+            existing
+        );
+    }
+
+    let summary = archive.summary();
+    if dry_run {
+        event!(Level::INFO, %summary, "Dry run, not restoring the backup");
+        return Ok(summary);
+    }
+
+    let settings_repo = GuildSettings::repository(pool);
+    let mut trans = pool.begin().await?;
+    repo.insert_with_ids_in(&mut trans, &archive.reminders)
+        .await?;
+    for settings in &archive.guild_settings {
+        settings_repo
+            .save_guild_settings_in(&mut trans, settings)
+            .await?;
+    }
+    let departures_repo = GuildDeparture::repository(pool);
+    for departure in &archive.guild_departures {
+        departures_repo
+            .save_guild_departure_in(&mut trans, departure)
+            .await?;
+    }
+    trans
+        .commit()
+        .await
+        .context("Error committing the restored backup")?;
+
+    // The KV store can not be part of the transaction, but saving the same entries again is harmless
+    for settings in &archive.guild_settings {
+        kv_client
+            .delete(settings)
+            .await
+            .context("Error clearing the cached guild settings")?;
+    }
+    let now = Utc::now();
+    for entry in &archive.kv_entries {
+        let ttl = match entry.expires_at {
+            Some(expires_at) => match (expires_at - now).to_std() {
+                Ok(ttl) if !ttl.is_zero() => Some(ttl),
+                _ => continue,
+            },
+            None => None,
+        };
+
+        kv_client
+            .save_raw(&entry.key, entry.value.clone(), ttl)
+            .await?;
+    }
+
+    event!(Level::INFO, %summary, "Restored backup");
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    //! Restoring needs a database without reminders, so these only run against fresh in-memory sqlite databases.
+
+    use std::time::Duration;
+
+    use chrono_tz::Tz;
+
+    use super::*;
+    use crate::db;
+
+    async fn fresh_database() -> Result<AnyPool> {
+        db::setup("sqlite::memory:").await
+    }
+
+    async fn filled_database(kv_client: &KVClient) -> Result<AnyPool> {
+        let pool = fresh_database().await?;
+        let repo = Reminder::repository(&pool);
+        for (what, channel) in [("first", 3), ("second", 3), ("deleted channel", 4)] {
+            repo.insert(&Reminder {
+                id: 0,
+                who: 1,
+                when: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                what: what.into(),
+                server: 2,
+                channel,
+                what_encrypted: false,
+                undeliverable_since: None,
+            })
+            .await?;
+        }
+        repo.mark_undeliverable(
+            &ReminderFilter {
+                channel: Some(4),
+                ..Default::default()
+            },
+            &DateTime::from_timestamp(1_700_000_100, 0).unwrap(),
+        )
+        .await?;
+        GuildDeparture::repository(&pool)
+            .save_guild_departure(&GuildDeparture {
+                guild_id: 5,
+                left_at: DateTime::from_timestamp(1_700_000_200, 0).unwrap(),
+            })
+            .await?;
+        GuildSettings::repository(&pool)
+            .save_guild_settings(&GuildSettings {
+                guild_id: 2,
+                timezone: Some(Tz::Europe__Brussels),
+            })
+            .await?;
+        kv_client
+            .save_raw("job_paused_reminders", "true".into(), None)
+            .await?;
+        kv_client
+            .save_raw(
+                "job_paused_expiring",
+                "true".into(),
+                Some(Duration::from_secs(3600)),
+            )
+            .await?;
+        kv_client
+            .save_raw("unrelated", "false".into(), None)
+            .await?;
+
+        Ok(pool)
+    }
+
+    #[tokio::test]
+    async fn restored_backup_matches_original() -> Result<()> {
+        let kv_client = KVClient::in_memory();
+        let pool = filled_database(&kv_client).await?;
+        let mut archive = Vec::new();
+
+        let written = write_backup(&pool, &kv_client, &["job_paused_*"], &mut archive).await?;
+
+        let restored_kv_client = KVClient::in_memory();
+        let restored_pool = fresh_database().await?;
+        let archive = read_archive(archive.as_slice())?;
+        let restored = restore_backup(&restored_pool, &restored_kv_client, &archive, false).await?;
+
+        assert_eq!(
+            ArchiveSummary {
+                reminders: 3,
+                guild_settings: 1,
+                guild_departures: 1,
+                kv_entries: 2
+            },
+            written
+        );
+        assert_eq!(written, restored);
+        let options = ReminderListOptions::default();
+        assert_eq!(
+            Reminder::repository(&pool).list(&options).await?,
+            Reminder::repository(&restored_pool).list(&options).await?
+        );
+        assert_eq!(
+            GuildSettings::repository(&pool)
+                .all_guild_settings()
+                .await?,
+            GuildSettings::repository(&restored_pool)
+                .all_guild_settings()
+                .await?
+        );
+        assert_eq!(
+            GuildDeparture::repository(&pool)
+                .all_guild_departures()
+                .await?,
+            GuildDeparture::repository(&restored_pool)
+                .all_guild_departures()
+                .await?
+        );
+        assert_eq!(
+            Some("true".to_owned()),
+            restored_kv_client.get_raw("job_paused_reminders").await?
+        );
+        assert_eq!(None, restored_kv_client.get_raw("unrelated").await?);
+        let Some((_, Some(ttl))) = restored_kv_client
+            .get_raw_with_ttl("job_paused_expiring")
+            .await?
+        else {
+            panic!("The expiring KV entry lost its ttl");
+        };
+        assert!(ttl > Duration::from_secs(3500));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_restore_can_be_retried() -> Result<()> {
+        let kv_client = KVClient::in_memory();
+        let pool = filled_database(&kv_client).await?;
+        let mut archive = Vec::new();
+        write_backup(&pool, &kv_client, &[], &mut archive).await?;
+        let archive = read_archive(archive.as_slice())?;
+
+        // The second reminder clashes with the first, after the first one was inserted
+        let mut broken = archive.clone();
+        broken.reminders[1].id = broken.reminders[0].id;
+        let restored_pool = fresh_database().await?;
+        assert!(restore_backup(&restored_pool, &kv_client, &broken, false)
+            .await
+            .is_err());
+        assert_eq!(
+            0,
+            Reminder::repository(&restored_pool)
+                .count(&ReminderFilter::default())
+                .await?
+        );
+        assert!(GuildSettings::repository(&restored_pool)
+            .all_guild_settings()
+            .await?
+            .is_empty());
+
+        let restored = restore_backup(&restored_pool, &kv_client, &archive, false).await?;
+        assert_eq!(archive.summary(), restored);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dry_run_restores_nothing() -> Result<()> {
+        let kv_client = KVClient::in_memory();
+        let pool = filled_database(&kv_client).await?;
+        let mut archive = Vec::new();
+        write_backup(&pool, &kv_client, &["job_paused_*"], &mut archive).await?;
+
+        let restored_kv_client = KVClient::in_memory();
+        let restored_pool = fresh_database().await?;
+        let archive = read_archive(archive.as_slice())?;
+        let summary = restore_backup(&restored_pool, &restored_kv_client, &archive, true).await?;
+
+        assert_eq!(3, summary.reminders);
+        assert_eq!(
+            0,
+            Reminder::repository(&restored_pool)
+                .count(&ReminderFilter::default())
+                .await?
+        );
+        assert_eq!(
+            None,
+            restored_kv_client.get_raw("job_paused_reminders").await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restore_refuses_database_with_reminders() -> Result<()> {
+        let kv_client = KVClient::in_memory();
+        let pool = filled_database(&kv_client).await?;
+        let mut archive = Vec::new();
+        write_backup(&pool, &kv_client, &[], &mut archive).await?;
+
+        let archive = read_archive(archive.as_slice())?;
+
+        assert!(restore_backup(&pool, &kv_client, &archive, true)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn archives_from_newer_versions_are_refused() {
+        let newer = format!(
+            r#"{{"kind":"header","format":"{}","version":{},"created_at":"2024-01-01T00:00:00Z"}}"#,
+            ARCHIVE_FORMAT,
+            ARCHIVE_VERSION + 1
+        );
+
+        assert!(read_archive(newer.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn archives_need_a_header() {
+        let headless = r#"{"kind":"kv","key":"a","value":"b"}"#;
+
+        assert!(read_archive(headless.as_bytes()).is_err());
+        assert!(read_archive("".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// A small in-process cache where entries expire after a fixed time and the least recently used entry makes room
+/// when the cache is full.
+///
+/// Eviction scans all entries, so this is meant for caches of a few thousand entries at most.
+#[derive(Debug)]
+pub struct Cache<K, V> {
+    inner: Mutex<Inner<K, V>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Goes up with every access, entries remember when they were last used.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Create a cache that holds at most `capacity` entries for `ttl` each.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                entries: HashMap::with_capacity(capacity),
+                clock: 0,
+            }),
+            ttl,
+            capacity,
+        }
+    }
+
+    /// A poisoned lock only means a panic happened while the cache was in use, the entries are still consistent.
+    fn inner(&self) -> MutexGuard<'_, Inner<K, V>> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The cached value, `None` when there is none or it expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        match inner.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.last_used = clock;
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                inner.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache the value, making room by dropping expired entries or else the least recently used one.
+    pub fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner();
+        inner.clock += 1;
+        let now = Instant::now();
+
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            inner.entries.retain(|_, entry| entry.expires_at > now);
+        }
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let least_recently_used = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+
+            if let Some(least_recently_used) = least_recently_used {
+                inner.entries.remove(&least_recently_used);
+            }
+        }
+
+        let entry = Entry {
+            value,
+            expires_at: now + self.ttl,
+            last_used: inner.clock,
+        };
+        inner.entries.insert(key, entry);
+    }
+
+    /// Forget the value of a single key.
+    pub fn invalidate(&self, key: &K) {
+        self.inner().entries.remove(key);
+    }
+
+    /// Forget everything.
+    pub fn clear(&self) {
+        self.inner().entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_values_expire() {
+        let cache = Cache::new(10, Duration::from_millis(50));
+        cache.insert(1, "one");
+
+        assert_eq!(Some("one"), cache.get(&1));
+
+        std::thread::sleep(Duration::from_millis(80));
+
+        assert_eq!(None, cache.get(&1));
+    }
+
+    #[test]
+    fn least_recently_used_value_makes_room() {
+        let cache = Cache::new(2, Duration::from_secs(60));
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        cache.get(&1);
+
+        cache.insert(3, "three");
+
+        assert_eq!(Some("one"), cache.get(&1));
+        assert_eq!(None, cache.get(&2));
+        assert_eq!(Some("three"), cache.get(&3));
+    }
+
+    #[test]
+    fn replacing_a_value_does_not_evict() {
+        let cache = Cache::new(2, Duration::from_secs(60));
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+
+        cache.insert(2, "deux");
+
+        assert_eq!(Some("one"), cache.get(&1));
+        assert_eq!(Some("deux"), cache.get(&2));
+    }
+
+    #[test]
+    fn invalidated_values_are_gone() {
+        let cache = Cache::new(10, Duration::from_secs(60));
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+
+        cache.invalidate(&1);
+        assert_eq!(None, cache.get(&1));
+        assert_eq!(Some("two"), cache.get(&2));
+
+        cache.clear();
+        assert_eq!(None, cache.get(&2));
+    }
+}
@@ -1,30 +1,214 @@
-use anyhow::{Context, Result};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use fercord_common::config::{DatabaseConfig, StatementLogLevel};
+#[cfg(feature = "sqlite")]
+use fercord_common::config::{SqliteJournalMode, SqliteSynchronous};
+use log::LevelFilter;
 use poise::async_trait;
-use sqlx_oldapi::{any::AnyPoolOptions, AnyPool};
+use sqlx_oldapi::any::{AnyConnectOptions, AnyKind, AnyPoolOptions};
+use sqlx_oldapi::migrate::{Migrate, Migrator};
 #[cfg(feature = "sqlite")]
-use sqlx_oldapi::{migrate::MigrateDatabase, Sqlite};
+use sqlx_oldapi::{migrate::MigrateDatabase, sqlite, Sqlite};
+use sqlx_oldapi::{AnyPool, ConnectOptions};
 use tracing::{event, Level};
 
+use crate::encryption::FieldCipher;
+use crate::events::{EventBus, StorageEvent};
+
 pub type Pool = AnyPool;
 
-/// Create a database connection and run any pending migrations
-#[cfg(not(feature = "sqlite"))]
+/// The database backends we support. Which one is used is decided by the scheme of the database url.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    /// Determine the backend from the scheme of a database url (`sqlite:` or `postgres:`/`postgresql:`).
+    pub fn from_url(url: &str) -> Result<Self> {
+        let Some((scheme, _)) = url.split_once(':') else {
+            bail!("The database url has no scheme, expected sqlite:// or postgres://");
+        };
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "sqlite" => Ok(Self::Sqlite),
+            "postgres" | "postgresql" => Ok(Self::Postgres),
+            other => {
+                bail!("Unsupported database scheme {other}, expected sqlite:// or postgres://")
+            }
+        }
+    }
+
+    /// The backend the pool is connected to.
+    pub fn of(pool: &AnyPool) -> Self {
+        match pool.any_kind() {
+            #[cfg(feature = "sqlite")]
+            AnyKind::Sqlite => Self::Sqlite,
+            #[cfg(feature = "postgres")]
+            AnyKind::Postgres => Self::Postgres,
+            #[allow(unreachable_patterns)]
+            other => unreachable!("Pools for {other:?} can not be created through db::setup"),
+        }
+    }
+
+    /// Whether support for this backend was compiled in.
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Self::Sqlite => cfg!(feature = "sqlite"),
+            Self::Postgres => cfg!(feature = "postgres"),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sqlite => write!(f, "sqlite"),
+            Self::Postgres => write!(f, "postgres"),
+        }
+    }
+}
+
+/// Create a database connection pool with the default [`DatabaseConfig`] and run any pending migrations.
+///
+/// The backend is picked from the scheme of `url`. A sqlite database is created when it does not exist yet.
 pub async fn setup(url: &str) -> Result<AnyPool> {
-    event!(Level::DEBUG, "Connecting to the database");
+    setup_with(url, &DatabaseConfig::default()).await
+}
+
+/// Create a database connection pool and make sure the schema is up to date.
+///
+/// Pending migrations are applied when `auto_migrate` is enabled, otherwise they make this fail. A schema that is newer
+/// than the migrations of this build always fails.
+///
+/// Create one pool per process and share it, its size is the limit for the whole process.
+#[tracing::instrument(skip(config))]
+pub async fn setup_with(url: &str, config: &DatabaseConfig) -> Result<AnyPool> {
+    let pool = connect(url, config).await?;
+
+    if config.auto_migrate {
+        run_migrations(&pool).await?;
+    } else {
+        check_schema(&migration_status(&pool).await?, false)?;
+    }
+
+    Ok(pool)
+}
+
+/// Create a database connection pool without looking at the schema.
+///
+/// Use it for tools that manage or inspect the schema, everything else should use [`setup_with`].
+#[tracing::instrument(skip(config))]
+pub async fn connect(url: &str, config: &DatabaseConfig) -> Result<AnyPool> {
+    open(url, config, true).await
+}
+
+/// Create a database connection pool like [`connect`], but fail when the sqlite database does not exist yet.
+///
+/// Use it to look at a database without leaving an empty one behind, like in health checks.
+#[tracing::instrument(skip(config))]
+pub async fn connect_existing(url: &str, config: &DatabaseConfig) -> Result<AnyPool> {
+    open(url, config, false).await
+}
+
+/// Create the pool, `create_sqlite` decides between creating and rejecting a missing sqlite database.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+async fn open(url: &str, config: &DatabaseConfig, create_sqlite: bool) -> Result<AnyPool> {
+    let backend = Backend::from_url(url)?;
+    if !backend.is_enabled() {
+        bail!("Support for {backend} databases was not enabled when building fercord_storage");
+    }
+
+    #[cfg(feature = "sqlite")]
+    if backend == Backend::Sqlite {
+        if create_sqlite {
+            create_sqlite_database(url).await?;
+        } else if !Sqlite::database_exists(url).await? {
+            bail!("Sqlite database {} does not exist", url);
+        }
+    }
+
+    if config.min_connections > config.max_connections.get() {
+        bail!(
+            "database.min_connections ({}) can not be larger than database.max_connections ({})",
+            config.min_connections,
+            config.max_connections
+        );
+    }
+
+    event!(Level::DEBUG, %backend, ?config, "Connecting to the database");
+    let idle_timeout = match config.idle_timeout_sec {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
     let pool = AnyPoolOptions::new()
-        .max_connections(2)
-        .connect(url)
+        .max_connections(config.max_connections.get())
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_sec))
+        .idle_timeout(idle_timeout)
+        .connect_with(connect_options(url, config)?)
         .await
         .with_context(|| "Error connecting to database")?;
 
-    run_migrations(&pool).await?;
-
     Ok(pool)
 }
 
+/// The options every connection of the pool is opened with.
+fn connect_options(url: &str, config: &DatabaseConfig) -> Result<AnyConnectOptions> {
+    let mut options = AnyConnectOptions::from_str(url).context("Invalid database url")?;
+    options.log_statements(level_filter(config.log_statements));
+
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_options) = options.as_sqlite_mut() {
+        *sqlite_options = sqlite_options
+            .clone()
+            .journal_mode(journal_mode(config.sqlite_journal_mode))
+            .synchronous(synchronous(config.sqlite_synchronous))
+            .busy_timeout(Duration::from_millis(config.sqlite_busy_timeout_ms));
+    }
+
+    Ok(options)
+}
+
+fn level_filter(level: StatementLogLevel) -> LevelFilter {
+    match level {
+        StatementLogLevel::Off => LevelFilter::Off,
+        StatementLogLevel::Error => LevelFilter::Error,
+        StatementLogLevel::Warn => LevelFilter::Warn,
+        StatementLogLevel::Info => LevelFilter::Info,
+        StatementLogLevel::Debug => LevelFilter::Debug,
+        StatementLogLevel::Trace => LevelFilter::Trace,
+    }
+}
+
 #[cfg(feature = "sqlite")]
-#[tracing::instrument]
-pub async fn setup(url: &str) -> Result<AnyPool> {
+fn journal_mode(mode: SqliteJournalMode) -> sqlite::SqliteJournalMode {
+    match mode {
+        SqliteJournalMode::Delete => sqlite::SqliteJournalMode::Delete,
+        SqliteJournalMode::Truncate => sqlite::SqliteJournalMode::Truncate,
+        SqliteJournalMode::Persist => sqlite::SqliteJournalMode::Persist,
+        SqliteJournalMode::Memory => sqlite::SqliteJournalMode::Memory,
+        SqliteJournalMode::Wal => sqlite::SqliteJournalMode::Wal,
+        SqliteJournalMode::Off => sqlite::SqliteJournalMode::Off,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn synchronous(synchronous: SqliteSynchronous) -> sqlite::SqliteSynchronous {
+    match synchronous {
+        SqliteSynchronous::Off => sqlite::SqliteSynchronous::Off,
+        SqliteSynchronous::Normal => sqlite::SqliteSynchronous::Normal,
+        SqliteSynchronous::Full => sqlite::SqliteSynchronous::Full,
+        SqliteSynchronous::Extra => sqlite::SqliteSynchronous::Extra,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+async fn create_sqlite_database(url: &str) -> Result<()> {
     event!(Level::DEBUG, "Checking if database exists");
 
     if !Sqlite::database_exists(url).await? {
@@ -36,46 +220,330 @@ pub async fn setup(url: &str) -> Result<AnyPool> {
         event!(Level::DEBUG, "Database {} already exists", url);
     }
 
-    event!(Level::DEBUG, "Connecting to the database");
-    let pool = AnyPoolOptions::new()
-        .max_connections(2)
-        .connect(url)
-        .await
-        .with_context(|| "Error connecting to database")?;
-
-    run_migrations(&pool).await?;
+    Ok(())
+}
 
-    Ok(pool)
+/// The embedded migrations for the backend.
+pub(crate) fn migrator(backend: Backend) -> Result<Migrator> {
+    match backend {
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => Ok(sqlx_oldapi::migrate!("migrations/sqlite")),
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => Ok(sqlx_oldapi::migrate!("migrations/postgres")),
+        #[allow(unreachable_patterns)]
+        backend => {
+            bail!("Support for {backend} databases was not enabled when building fercord_storage")
+        }
+    }
 }
 
-#[cfg(feature = "sqlite")]
-async fn run_migrations(pool: &AnyPool) -> Result<()> {
-    let migrations = sqlx_oldapi::migrate!("migrations/sqlite");
+/// Run the pending migrations that belong to the backend of the pool.
+///
+/// Returns the versions that were applied.
+pub async fn run_migrations(pool: &AnyPool) -> Result<Vec<i64>> {
+    let status = migration_status(pool).await?;
+    check_schema(&status, true)?;
 
     event!(Level::DEBUG, "Running any pending migrations");
-    migrations
+    migrator(Backend::of(pool))?
         .run(pool)
         .await
         .with_context(|| "Error applying migrations")?;
 
-    Ok(())
+    Ok(status
+        .iter()
+        .filter(|m| m.state == MigrationState::Pending)
+        .map(|m| m.version)
+        .collect())
 }
 
-#[cfg(feature = "postgres")]
-async fn run_migrations(pool: &AnyPool) -> Result<()> {
-    let migrations = sqlx_oldapi::migrate!("migrations/postgres");
+/// Revert the applied migrations with a version after `target`, newest first.
+///
+/// Returns the versions that were reverted. Reverting drops the tables and columns those migrations added, with the
+/// data in them.
+pub async fn revert_migrations(pool: &AnyPool, target: i64) -> Result<Vec<i64>> {
+    let status = migration_status(pool).await?;
+    check_schema(&status, true)?;
 
-    event!(Level::DEBUG, "Running any pending migrations");
-    migrations
-        .run(pool)
+    event!(Level::INFO, target, "Reverting migrations");
+    migrator(Backend::of(pool))?
+        .undo(pool, target)
         .await
-        .with_context(|| "Error applying migrations")?;
+        .with_context(|| format!("Error reverting migrations to version {target}"))?;
+
+    Ok(status
+        .iter()
+        .rev()
+        .filter(|m| m.state == MigrationState::Applied && m.version > target)
+        .map(|m| m.version)
+        .collect())
+}
+
+/// Where a migration stands in a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// It was applied, but its SQL differs from the migration in this build.
+    Modified,
+    /// It failed halfway and has to be cleaned up by hand.
+    Failed,
+    /// It was applied by a newer build, this one does not know it.
+    Unknown,
+}
+
+impl Display for MigrationState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Applied => write!(f, "applied"),
+            Self::Pending => write!(f, "pending"),
+            Self::Modified => write!(f, "modified"),
+            Self::Failed => write!(f, "failed"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A migration and its state in a database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    /// Empty for [`MigrationState::Unknown`] migrations.
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// The state of every migration of this build and of every unknown migration in the database, ordered by version.
+pub async fn migration_status(pool: &AnyPool) -> Result<Vec<MigrationStatus>> {
+    let migrator = migrator(Backend::of(pool))?;
+
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table()
+        .await
+        .context("Error creating the migrations table")?;
+    let failed = conn
+        .dirty_version()
+        .await
+        .context("Error reading the applied migrations")?;
+    let mut applied = conn
+        .list_applied_migrations()
+        .await
+        .context("Error reading the applied migrations")?;
+
+    let mut status = Vec::new();
+    for migration in migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+    {
+        // Whatever is left in `applied` afterwards is unknown to this build
+        let state = match applied.iter().position(|a| a.version == migration.version) {
+            None => MigrationState::Pending,
+            Some(i) => {
+                let applied = applied.remove(i);
+                if failed == Some(migration.version) {
+                    MigrationState::Failed
+                } else if applied.checksum != migration.checksum {
+                    MigrationState::Modified
+                } else {
+                    MigrationState::Applied
+                }
+            }
+        };
+
+        status.push(MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            state,
+        });
+    }
+    status.extend(applied.into_iter().map(|a| MigrationStatus {
+        version: a.version,
+        description: String::new(),
+        state: MigrationState::Unknown,
+    }));
+    status.sort_by_key(|m| m.version);
+
+    Ok(status)
+}
+
+/// Fail with an explanation when the schema can not be used by this build.
+///
+/// Pending migrations are only a problem when they are not going to be applied.
+fn check_schema(status: &[MigrationStatus], migrating: bool) -> Result<()> {
+    let with_state = |state| {
+        status
+            .iter()
+            .filter(move |m| m.state == state)
+            .map(|m| m.version)
+    };
+
+    if let Some(newest) = with_state(MigrationState::Unknown).max() {
+        let known = status
+            .iter()
+            .filter(|m| m.state != MigrationState::Unknown)
+            .map(|m| m.version)
+            .max()
+            .unwrap_or_default();
+        bail!(
+            "The database schema is at version {newest}, which is newer than this build of fercord supports \
+            (version {known}). Upgrade fercord, or revert the migrations with `db revert` from the newer build"
+        );
+    }
+    if let Some(version) = with_state(MigrationState::Failed).next() {
+        bail!(
+            "Migration {version} failed earlier, clean up the database by hand before continuing"
+        );
+    }
+    if let Some(version) = with_state(MigrationState::Modified).next() {
+        bail!("Migration {version} was changed after it was applied to the database");
+    }
+    if !migrating {
+        let pending: Vec<i64> = with_state(MigrationState::Pending).collect();
+        if !pending.is_empty() {
+            bail!(
+                "The database schema is missing migrations {pending:?} and database.auto_migrate is disabled. \
+                Apply them with `db migrate`"
+            );
+        }
+    }
 
     Ok(())
 }
 
 pub struct Repo<'r> {
     pub(crate) pool: &'r AnyPool,
+    pub(crate) backend: Backend,
+    /// Encrypts and decrypts the fields of the model that hold personal data.
+    pub(crate) cipher: Option<&'r FieldCipher>,
+    /// Where the changes made through the repository are announced.
+    pub(crate) events: Option<&'r EventBus>,
+}
+
+impl<'r> Repo<'r> {
+    /// Publish a [`StorageEvent`] on the bus for every change made through this repository.
+    ///
+    /// Without a bus nothing is published, like for backups and copies.
+    pub fn with_events(mut self, events: Option<&'r EventBus>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Publish the event, after the change was committed.
+    ///
+    /// The event is queued with [`EventBus::send`], so the write does not wait for it. The change already happened, so
+    /// failing to announce it is only logged.
+    pub(crate) fn publish(&self, event: StorageEvent) {
+        if let Some(events) = self.events {
+            events.send(event);
+        }
+    }
+}
+
+/// Discord snowflakes fit in 63 bits, so they can be stored in a signed 64-bit column.
+pub(crate) fn to_db_snowflake(snowflake: u64) -> Result<i64> {
+    i64::try_from(snowflake).with_context(|| format!("Snowflake {} is too large", snowflake))
+}
+
+pub(crate) fn from_db_snowflake(snowflake: i64) -> Result<u64> {
+    u64::try_from(snowflake).with_context(|| format!("Invalid snowflake {}", snowflake))
+}
+
+/// Page size of a listing when none is given.
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+
+/// Sort direction of a listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+impl Order {
+    pub(crate) fn sql(&self) -> &'static str {
+        match self {
+            Self::Ascending => "ASC",
+            Self::Descending => "DESC",
+        }
+    }
+
+    /// The comparison that selects the records that come after a cursor.
+    pub(crate) fn after(&self) -> &'static str {
+        match self {
+            Self::Ascending => ">",
+            Self::Descending => "<",
+        }
+    }
+}
+
+/// Which records to list, in what order and where to continue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOptions<F, S, C> {
+    pub filter: F,
+    pub sort: S,
+    pub order: Order,
+    /// The maximum amount of records on a page.
+    pub limit: u32,
+    /// Continue after this position, use [`Page::next`] of the previous page.
+    pub after: Option<C>,
+}
+
+impl<F: Default, S: Default, C> Default for ListOptions<F, S, C> {
+    fn default() -> Self {
+        Self {
+            filter: F::default(),
+            sort: S::default(),
+            order: Order::default(),
+            limit: DEFAULT_PAGE_SIZE,
+            after: None,
+        }
+    }
+}
+
+/// A single page of a listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<E, C> {
+    pub items: Vec<E>,
+    /// The cursor for the next page, `None` when this is the last page.
+    pub next: Option<C>,
+}
+
+/// Puts SQL together at runtime, using the parameter placeholders of the backend.
+///
+/// The values still have to be bound in the same order as the placeholders were added.
+pub(crate) struct SqlBuilder {
+    backend: Backend,
+    sql: String,
+    params: usize,
+}
+
+impl SqlBuilder {
+    pub fn new(backend: Backend, sql: impl Into<String>) -> Self {
+        Self {
+            backend,
+            sql: sql.into(),
+            params: 0,
+        }
+    }
+
+    pub fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    /// Reserve the next placeholder and return it, without adding it to the SQL.
+    pub fn placeholder(&mut self) -> String {
+        self.params += 1;
+
+        match self.backend {
+            Backend::Sqlite => "?".into(),
+            Backend::Postgres => format!("${}", self.params),
+        }
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
 }
 
 #[async_trait]
@@ -84,7 +552,275 @@ pub trait Repository<E, I>
 where
     I: Sized,
 {
+    /// Criteria that select the records for [`Repository::list`] and [`Repository::count`].
+    type Filter: Send + Sync;
+    /// The fields a listing can be sorted on.
+    type Sort: Send + Sync;
+    /// A position in a sorted listing.
+    type Cursor: Send + Sync;
+
     async fn insert(&self, entity: &E) -> Result<I>;
+    /// Insert all entities in a single transaction and return their ids, in the same order.
+    async fn insert_many(&self, entities: &[E]) -> Result<Vec<I>>;
+    /// Update an existing record. Returns `false` when there is no record with the id of the entity.
+    async fn update(&self, entity: &E) -> Result<bool>;
     async fn delete(&self, entity: E) -> Result<()>;
+    /// Delete the records with the given ids in a single transaction and return how many were deleted.
+    async fn delete_many(&self, ids: &[I]) -> Result<u64>;
     async fn get(&self, id: I) -> Result<Option<E>>;
+    /// Get a page of the records that match the filter.
+    async fn list(
+        &self,
+        options: &ListOptions<Self::Filter, Self::Sort, Self::Cursor>,
+    ) -> Result<Page<E, Self::Cursor>>;
+    /// Count the records that match the filter.
+    async fn count(&self, filter: &Self::Filter) -> Result<u64>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_is_picked_from_url_scheme() {
+        assert_eq!(
+            Backend::Sqlite,
+            Backend::from_url("sqlite://fercord.db").unwrap()
+        );
+        assert_eq!(
+            Backend::Sqlite,
+            Backend::from_url("sqlite::memory:").unwrap()
+        );
+        assert_eq!(
+            Backend::Postgres,
+            Backend::from_url("postgres://fercord@localhost/fercord").unwrap()
+        );
+        assert_eq!(
+            Backend::Postgres,
+            Backend::from_url("postgresql://localhost").unwrap()
+        );
+    }
+
+    #[test]
+    fn placeholders_follow_the_backend() {
+        let mut sqlite = SqlBuilder::new(Backend::Sqlite, "");
+        let mut postgres = SqlBuilder::new(Backend::Postgres, "");
+
+        assert_eq!(
+            vec!["?", "?"],
+            vec![sqlite.placeholder(), sqlite.placeholder()]
+        );
+        assert_eq!(
+            vec!["$1", "$2"],
+            vec![postgres.placeholder(), postgres.placeholder()]
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_settings_are_applied() -> Result<()> {
+        let (dir, url) = temp_sqlite_url()?;
+        let config = DatabaseConfig {
+            sqlite_busy_timeout_ms: 1234,
+            ..Default::default()
+        };
+
+        let pool = setup_with(&url, &config).await?;
+        let journal_mode: String = sqlx_oldapi::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&pool)
+            .await?;
+        let synchronous: i32 = sqlx_oldapi::query_scalar("PRAGMA synchronous")
+            .fetch_one(&pool)
+            .await?;
+        let busy_timeout: i32 = sqlx_oldapi::query_scalar("PRAGMA busy_timeout")
+            .fetch_one(&pool)
+            .await?;
+        pool.close().await;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!("wal", journal_mode);
+        // NORMAL
+        assert_eq!(1, synchronous);
+        assert_eq!(1234, busy_timeout);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn more_minimum_than_maximum_connections_is_rejected() {
+        let config = DatabaseConfig {
+            min_connections: 6,
+            ..Default::default()
+        };
+
+        assert!(setup_with("sqlite::memory:", &config).await.is_err());
+    }
+
+    /// A sqlite database in a file of its own, so every connection of the pool sees the same data.
+    #[cfg(feature = "sqlite")]
+    fn temp_sqlite_url() -> Result<(std::path::PathBuf, String)> {
+        let dir = std::env::temp_dir().join(format!("fercord_db_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let url = format!("sqlite://{}", dir.join("fercord.db").display());
+
+        Ok((dir, url))
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn connecting_to_an_existing_database_does_not_create_one() -> Result<()> {
+        let (dir, url) = temp_sqlite_url()?;
+
+        assert!(connect_existing(&url, &DatabaseConfig::default())
+            .await
+            .is_err());
+        assert!(!dir.join("fercord.db").exists());
+
+        connect(&url, &DatabaseConfig::default())
+            .await?
+            .close()
+            .await;
+        connect_existing(&url, &DatabaseConfig::default())
+            .await?
+            .close()
+            .await;
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sessions_without_a_user_are_dropped_by_the_session_owner_migration() -> Result<()> {
+        let (dir, url) = temp_sqlite_url()?;
+        let pool = setup(&url).await?;
+        revert_migrations(&pool, 7).await?;
+        sqlx_oldapi::query(
+            "INSERT INTO sessions (session_key, state, expires_at) VALUES ('old', '{}', 4102444800)",
+        )
+        .execute(&pool)
+        .await?;
+
+        run_migrations(&pool).await?;
+
+        let sessions: i64 = sqlx_oldapi::query_scalar("SELECT COUNT(*) FROM sessions")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(0, sessions);
+        pool.close().await;
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn migrations_can_be_reverted_and_applied_again() -> Result<()> {
+        let (dir, url) = temp_sqlite_url()?;
+        let pool = setup(&url).await?;
+
+        let status = migration_status(&pool).await?;
+        assert_eq!(
+            vec![0, 1, 2, 3, 4, 5, 6, 7, 8],
+            status.iter().map(|m| m.version).collect::<Vec<_>>()
+        );
+        assert!(status.iter().all(|m| m.state == MigrationState::Applied));
+        assert_eq!("Guild settings", status[2].description);
+
+        assert_eq!(
+            vec![8, 7, 6, 5, 4, 3, 2],
+            revert_migrations(&pool, 1).await?
+        );
+        let states: Vec<MigrationState> = migration_status(&pool)
+            .await?
+            .iter()
+            .map(|m| m.state)
+            .collect();
+        assert_eq!(
+            vec![
+                MigrationState::Applied,
+                MigrationState::Applied,
+                MigrationState::Pending,
+                MigrationState::Pending,
+                MigrationState::Pending,
+                MigrationState::Pending,
+                MigrationState::Pending,
+                MigrationState::Pending,
+                MigrationState::Pending
+            ],
+            states
+        );
+        assert!(sqlx_oldapi::query("SELECT * FROM guild_settings")
+            .fetch_all(&pool)
+            .await
+            .is_err());
+
+        assert_eq!(vec![2, 3, 4, 5, 6, 7, 8], run_migrations(&pool).await?);
+        assert!(run_migrations(&pool).await?.is_empty());
+        pool.close().await;
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn pending_migrations_fail_without_auto_migrate() -> Result<()> {
+        let (dir, url) = temp_sqlite_url()?;
+        let config = DatabaseConfig {
+            auto_migrate: false,
+            ..Default::default()
+        };
+
+        let error = setup_with(&url, &config).await.unwrap_err();
+        assert!(error.to_string().contains("db migrate"), "{error}");
+
+        let pool = connect(&url, &config).await?;
+        run_migrations(&pool).await?;
+        pool.close().await;
+        setup_with(&url, &config).await?.close().await;
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn newer_schemas_are_refused() -> Result<()> {
+        let (dir, url) = temp_sqlite_url()?;
+        let pool = setup(&url).await?;
+        // What a newer build leaves behind
+        sqlx_oldapi::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+            VALUES (99, 'From the future', true, x'00', 0)",
+        )
+        .execute(&pool)
+        .await?;
+
+        let status = migration_status(&pool).await?;
+        assert_eq!(
+            Some(&MigrationStatus {
+                version: 99,
+                description: String::new(),
+                state: MigrationState::Unknown,
+            }),
+            status.last()
+        );
+        pool.close().await;
+
+        let error = setup(&url).await.unwrap_err();
+        assert!(
+            error.to_string().contains("version 99, which is newer"),
+            "{error}"
+        );
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_schemes_are_rejected() {
+        assert!(Backend::from_url("mysql://localhost").is_err());
+        assert!(Backend::from_url("fercord.db").is_err());
+    }
 }
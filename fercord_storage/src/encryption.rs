@@ -0,0 +1,264 @@
+//! Encryption of single fields, like the contents of reminders.
+//!
+//! Encrypted values are stored as `enc:v1:{key id}:{base64 of the nonce and the ciphertext}`, using AES-256-GCM with
+//! the key id as associated data. The key id tells which key decrypts a value, so a new key can be taken in use while
+//! older values are still encrypted with the previous one.
+//!
+//! The format does not tell plaintext apart from encrypted values, any text can look like one. Whoever stores the
+//! values keeps track of which ones are encrypted, like the `what_encrypted` column of the reminders.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use fercord_common::config::EncryptionConfig;
+
+/// The version of the format, followed by the key id.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+/// Length of an AES-GCM nonce in bytes.
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts values with the current key and decrypts them with any of the known keys.
+pub struct FieldCipher {
+    key_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl Debug for FieldCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut key_ids: Vec<&String> = self.keys.keys().collect();
+        key_ids.sort();
+
+        f.debug_struct("FieldCipher")
+            .field("key_id", &self.key_id)
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+impl FieldCipher {
+    /// Create a cipher that encrypts with `key`, a 256-bit key.
+    pub fn new(key_id: &str, key: &[u8]) -> Result<Self> {
+        let mut cipher = Self {
+            key_id: key_id.to_string(),
+            keys: HashMap::new(),
+        };
+        cipher.add_key(key_id, key)?;
+
+        Ok(cipher)
+    }
+
+    /// Also decrypt values that were encrypted with an older key.
+    pub fn with_previous_key(mut self, key_id: &str, key: &[u8]) -> Result<Self> {
+        ensure!(
+            key_id != self.key_id,
+            "Previous key {key_id} has the same id as the current key"
+        );
+        self.add_key(key_id, key)?;
+
+        Ok(self)
+    }
+
+    /// Create the cipher from the base64 encoded keys in the configuration.
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self> {
+        let decode = |key_id: &str, key: &str| {
+            STANDARD
+                .decode(key.trim())
+                .with_context(|| format!("Encryption key {key_id} is not valid base64"))
+        };
+
+        let mut cipher = Self::new(&config.key_id, &decode(&config.key_id, &config.key)?)?;
+        for (key_id, key) in &config.previous_keys {
+            cipher = cipher.with_previous_key(key_id, &decode(key_id, key)?)?;
+        }
+
+        Ok(cipher)
+    }
+
+    fn add_key(&mut self, key_id: &str, key: &[u8]) -> Result<()> {
+        ensure!(
+            !key_id.is_empty()
+                && key_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')),
+            "Invalid key id {key_id:?}, use letters, digits, '-', '_' and '.'"
+        );
+        let key = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| anyhow!("Encryption key {key_id} has to be 32 bytes long"))?;
+        self.keys.insert(key_id.to_string(), key);
+
+        Ok(())
+    }
+
+    /// The id of the key new values are encrypted with.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Encrypt the value with the current key.
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let key = &self.keys[&self.key_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: self.key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Error encrypting value"))?;
+
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+
+        Ok(format!(
+            "{ENCRYPTED_PREFIX}{}:{}",
+            self.key_id,
+            STANDARD.encode(data)
+        ))
+    }
+
+    /// Decrypt a value returned by [`FieldCipher::encrypt`].
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let Some((key_id, data)) = split_encrypted(value) else {
+            bail!("Value is not in the encrypted format");
+        };
+
+        let Some(key) = self.keys.get(key_id) else {
+            bail!("Value was encrypted with unknown key {key_id}");
+        };
+        let data = STANDARD
+            .decode(data)
+            .context("Encrypted value is not valid base64")?;
+        ensure!(data.len() > NONCE_LENGTH, "Encrypted value is too short");
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        let plaintext = key
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Error decrypting value with key {key_id}"))?;
+
+        String::from_utf8(plaintext).context("Decrypted value is not valid UTF-8")
+    }
+
+    /// Whether the encrypted value uses the current key, otherwise it uses an older key.
+    pub fn is_current(&self, value: &str) -> bool {
+        split_encrypted(value).is_some_and(|(key_id, _)| key_id == self.key_id)
+    }
+}
+
+/// The key id and the data of an encrypted value.
+fn split_encrypted(value: &str) -> Option<(&str, &str)> {
+    value.strip_prefix(ENCRYPTED_PREFIX)?.split_once(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+    const OTHER_KEY: [u8; 32] = [8; 32];
+
+    #[test]
+    fn encrypted_values_can_be_decrypted() -> Result<()> {
+        let cipher = FieldCipher::new("2024", &KEY)?;
+
+        let encrypted = cipher.encrypt("buy milk")?;
+
+        assert!(encrypted.starts_with("enc:v1:2024:"));
+        assert!(!encrypted.contains("buy milk"));
+        assert!(cipher.is_current(&encrypted));
+        assert_ne!(
+            encrypted,
+            cipher.encrypt("buy milk")?,
+            "Every value gets its own nonce"
+        );
+        assert_eq!("buy milk", cipher.decrypt(&encrypted)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn previous_keys_only_decrypt() -> Result<()> {
+        let old = FieldCipher::new("2023", &OTHER_KEY)?;
+        let encrypted = old.encrypt("buy milk")?;
+
+        let cipher = FieldCipher::new("2024", &KEY)?.with_previous_key("2023", &OTHER_KEY)?;
+
+        assert_eq!("buy milk", cipher.decrypt(&encrypted)?);
+        assert!(!cipher.is_current(&encrypted));
+        assert!(cipher.encrypt("buy milk")?.starts_with("enc:v1:2024:"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn plaintext_is_not_decrypted() -> Result<()> {
+        let cipher = FieldCipher::new("2024", &KEY)?;
+
+        assert!(cipher.decrypt("buy milk").is_err());
+        assert!(!cipher.is_current("buy milk"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_keys_and_tampering_are_detected() -> Result<()> {
+        let cipher = FieldCipher::new("2024", &KEY)?;
+        let other = FieldCipher::new("2023", &OTHER_KEY)?;
+        let encrypted = cipher.encrypt("buy milk")?;
+
+        assert!(other.decrypt(&encrypted).is_err());
+        // The key id is authenticated, another id with the same key does not decrypt
+        let relabeled = FieldCipher::new("2025", &KEY)?.with_previous_key("2024", &OTHER_KEY)?;
+        assert!(relabeled
+            .decrypt(&encrypted.replace(":2024:", ":2025:"))
+            .is_err());
+
+        let mut tampered = encrypted.clone().into_bytes();
+        let last = tampered.len() - 2;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        assert!(cipher.decrypt(&String::from_utf8(tampered)?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!(FieldCipher::new("2024", &[1; 16]).is_err());
+        assert!(FieldCipher::new("", &KEY).is_err());
+        assert!(FieldCipher::new("20:24", &KEY).is_err());
+        assert!(FieldCipher::new("2024", &KEY)
+            .and_then(|cipher| cipher.with_previous_key("2024", &OTHER_KEY))
+            .is_err());
+    }
+
+    #[test]
+    fn keys_are_read_from_the_config() -> Result<()> {
+        let config = EncryptionConfig {
+            key_id: "2024".into(),
+            key: STANDARD.encode(KEY),
+            previous_keys: [("2023".to_string(), STANDARD.encode(OTHER_KEY))].into(),
+        };
+
+        let cipher = FieldCipher::from_config(&config)?;
+        let old = FieldCipher::new("2023", &OTHER_KEY)?;
+
+        assert_eq!("2024", cipher.key_id());
+        assert_eq!("buy milk", cipher.decrypt(&old.encrypt("buy milk")?)?);
+        assert!(!format!("{cipher:?}").contains(&config.key));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use poise::async_trait;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+
+use super::{EventTransport, MessageStream};
+
+/// The amount of messages a subscriber can fall behind before it misses messages.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Delivers the events to the subscribers in the same process.
+///
+/// Nothing reaches other processes, so this only suits tests and installs where the bot runs without the API.
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    channels: Mutex<HashMap<String, broadcast::Sender<String>>>,
+}
+
+impl MemoryTransport {
+    /// The sender of the channel, created when nobody used the channel before.
+    fn sender(&self, channel: &str) -> Result<broadcast::Sender<String>> {
+        let mut channels = self
+            .channels
+            .lock()
+            .map_err(|_| anyhow!("The in-process event transport was poisoned"))?;
+
+        Ok(channels
+            .entry(channel.to_owned())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .clone())
+    }
+}
+
+#[async_trait]
+impl EventTransport for MemoryTransport {
+    async fn publish(&self, channel: &str, message: String) -> Result<()> {
+        // Sending only fails when nobody is subscribed, which is fine for events
+        let _ = self.sender(channel)?.send(message);
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<MessageStream> {
+        let receiver = self.sender(channel)?.subscribe();
+
+        Ok(Box::pin(BroadcastStream::new(receiver).map(|message| {
+            message.map_err(|BroadcastStreamRecvError::Lagged(missed)| {
+                anyhow!("Missed {} storage events", missed)
+            })
+        })))
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use poise::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::{Stream, StreamExt};
+use tracing::*;
+
+use fercord_common::config::DiscordConfig;
+
+pub use self::memory::MemoryTransport;
+pub use self::redis::RedisTransport;
+
+mod memory;
+mod redis;
+
+#[cfg(test)]
+mod tests;
+
+/// The channel the events are published on, behind the prefix of the bus.
+const EVENT_CHANNEL: &str = "storage_events";
+
+/// How long publishing a single event may take before it is given up.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The amount of events [`EventBus::send`] holds while they are published. Events sent to a full queue are dropped.
+const SEND_QUEUE_CAPACITY: usize = 1024;
+
+/// A change that was made to the stored data.
+///
+/// Events are only published after the change was committed, but they are not guaranteed to arrive: a subscriber that
+/// was not connected misses them. Use them to react sooner, not as the only way to learn about changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StorageEvent {
+    ReminderInserted {
+        id: i64,
+        when: DateTime<Utc>,
+    },
+    ReminderUpdated {
+        id: i64,
+        when: DateTime<Utc>,
+    },
+    RemindersDeleted {
+        ids: Vec<i64>,
+    },
+    /// Reminders were changed or deleted in bulk, like all reminders of a guild. Reload the reminders you depend on.
+    RemindersChanged,
+    GuildSettingsSaved {
+        guild_id: u64,
+    },
+    GuildSettingsDeleted {
+        guild_id: u64,
+    },
+}
+
+/// An entry in the queue of [`EventBus::send`].
+#[derive(Debug)]
+enum Queued {
+    Event(StorageEvent),
+    /// Answered once every entry before it was handled, see [`EventBus::flush`].
+    Flush(oneshot::Sender<()>),
+}
+
+/// The events of a subscription. An error means events were missed, the stream ends when the subscription does.
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<StorageEvent>> + Send>>;
+
+/// The stream of raw messages of a [`EventTransport`] subscription.
+pub type MessageStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// Moves messages from the processes that publish them to every process that subscribed to the channel.
+///
+/// [`EventBus`] builds the typed events on top of this, so a new transport only has to move strings around.
+#[async_trait]
+pub trait EventTransport: Debug + Send + Sync {
+    /// Send the message to every current subscriber of the channel.
+    async fn publish(&self, channel: &str, message: String) -> Result<()>;
+    /// Receive the messages that are published on the channel from now on.
+    async fn subscribe(&self, channel: &str) -> Result<MessageStream>;
+}
+
+/// Publishes and subscribes to [`StorageEvent`]s, so the bot and the API learn about the changes the other one made.
+///
+/// ## Creation
+/// Create a new bus by calling `EventBus::new`, which uses redis pub/sub when `redis_url` is configured.
+/// Use `EventBus::in_process` when only the current process has to receive the events, like in tests.
+///
+/// ## Prefix
+/// The events go over a channel under the `kv_prefix`, so deployments that share a redis server do not receive each
+/// other's events.
+///
+/// ## Sending
+/// [`EventBus::send`] queues the event and returns right away, a background task publishes the queued events in order.
+/// Repositories send their events this way, so a slow or unreachable transport does not hold up their writes.
+/// A process that exits soon after sending, like a CLI command, calls [`EventBus::flush`] first.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    transport: Arc<dyn EventTransport>,
+    channel: String,
+    /// The queue of [`EventBus::send`], its task is started by the first event.
+    queue: Arc<OnceLock<mpsc::Sender<Queued>>>,
+}
+
+impl EventBus {
+    /// Create a new `EventBus` from a `&DiscordConfig`.
+    ///
+    /// Without `redis_url` the events do not leave the process.
+    #[instrument(level = "trace")]
+    pub fn new(config: &DiscordConfig) -> Result<Self> {
+        let transport: Arc<dyn EventTransport> = match &config.redis_url {
+            Some(url) => Arc::new(RedisTransport::new(url)?),
+            None => {
+                event!(
+                    Level::DEBUG,
+                    "No redis_url configured, storage events stay in this process"
+                );
+                Arc::new(MemoryTransport::default())
+            }
+        };
+
+        let bus = Self::from_transport(transport);
+
+        Ok(match &config.kv_prefix {
+            Some(prefix) => bus.with_prefix(prefix),
+            None => bus,
+        })
+    }
+
+    /// Create an `EventBus` that only delivers events within the process. Clones of the bus share the subscribers.
+    pub fn in_process() -> Self {
+        Self::from_transport(Arc::new(MemoryTransport::default()))
+    }
+
+    /// Create an `EventBus` on top of any transport.
+    pub fn from_transport(transport: Arc<dyn EventTransport>) -> Self {
+        Self {
+            transport,
+            channel: EVENT_CHANNEL.to_owned(),
+            queue: Default::default(),
+        }
+    }
+
+    /// Publish the events of this bus under `prefix`.
+    pub fn with_prefix(self, prefix: &str) -> Self {
+        Self {
+            channel: format!("{}:{}", prefix, EVENT_CHANNEL),
+            // The queue publishes on the channel it was started with
+            queue: Default::default(),
+            ..self
+        }
+    }
+
+    /// Send the event to every subscriber, giving up after [`PUBLISH_TIMEOUT`].
+    pub async fn publish(&self, event: &StorageEvent) -> Result<()> {
+        event!(Level::TRACE, ?event, "Publishing storage event");
+
+        let message = serde_json::to_string(event).context("Error serializing storage event")?;
+        tokio::time::timeout(
+            PUBLISH_TIMEOUT,
+            self.transport.publish(&self.channel, message),
+        )
+        .await
+        .context("Timed out publishing storage event")?
+    }
+
+    /// Queue the event to be published in the background, after the events that were sent before it.
+    ///
+    /// Events that can not be published, or that do not fit in the queue, are only logged. Must be called from within
+    /// a tokio runtime.
+    pub fn send(&self, event: StorageEvent) {
+        let queue = self.queue.get_or_init(|| {
+            let (sender, mut receiver) = mpsc::channel::<Queued>(SEND_QUEUE_CAPACITY);
+            let bus = Self {
+                queue: Default::default(),
+                ..self.clone()
+            };
+            // Ends when every clone of the bus was dropped
+            tokio::spawn(async move {
+                while let Some(queued) = receiver.recv().await {
+                    match queued {
+                        Queued::Event(event) => {
+                            if let Err(e) = bus.publish(&event).await {
+                                event!(Level::WARN, ?e, ?event, "Error publishing storage event");
+                            }
+                        }
+                        // The one waiting may have given up already
+                        Queued::Flush(done) => _ = done.send(()),
+                    }
+                }
+            });
+
+            sender
+        });
+
+        if let Err(e) = queue.try_send(Queued::Event(event)) {
+            event!(Level::WARN, ?e, "Error queueing storage event, dropping it");
+        }
+    }
+
+    /// Wait until every event that was sent before is published, or given up after [`PUBLISH_TIMEOUT`].
+    ///
+    /// The queue only lives as long as the process, so call this before a short-lived process exits.
+    pub async fn flush(&self) {
+        let Some(queue) = self.queue.get() else {
+            return;
+        };
+
+        let (done, flushed) = oneshot::channel();
+        if queue.send(Queued::Flush(done)).await.is_ok() {
+            _ = flushed.await;
+        }
+    }
+
+    /// Receive the events that are published from now on, by any process that shares the transport.
+    pub async fn subscribe(&self) -> Result<EventStream> {
+        event!(Level::TRACE, channel = %self.channel, "Subscribing to storage events");
+
+        let messages = self.transport.subscribe(&self.channel).await?;
+
+        Ok(Box::pin(messages.map(|message| {
+            let message = message?;
+            serde_json::from_str(&message)
+                .with_context(|| format!("Error reading storage event {}", message))
+        })))
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use poise::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client};
+use tokio::sync::OnceCell;
+use tokio_stream::StreamExt;
+
+use super::{EventTransport, MessageStream};
+
+/// How long setting up the shared connection may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long publishing waits after a failed connection attempt before it tries to connect again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Sends the events over redis pub/sub, so every process connected to the same redis server receives them.
+///
+/// Publishing shares one [`ConnectionManager`], which is set up by the first event and reconnects by itself from then
+/// on. When setting it up fails, events fail right away until [`RECONNECT_DELAY`] passed. Every subscription has a
+/// connection of its own, as redis does not allow other commands on a subscribed connection.
+#[derive(Clone)]
+pub struct RedisTransport {
+    client: Client,
+    connection: Arc<OnceCell<ConnectionManager>>,
+    /// When setting up the shared connection failed the last time.
+    failed_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl std::fmt::Debug for RedisTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisTransport")
+            .field("client", &self.client)
+            .field("connected", &self.connection.initialized())
+            .finish()
+    }
+}
+
+impl RedisTransport {
+    /// Create a transport for the redis server at `url`. This does not connect yet.
+    pub fn new(url: &str) -> Result<Self> {
+        let client = Client::open(url)?;
+
+        Ok(Self {
+            client,
+            connection: Default::default(),
+            failed_at: Default::default(),
+        })
+    }
+
+    /// The shared connection. Clones of a `ConnectionManager` all use the same underlying connection.
+    async fn connection(&self) -> Result<ConnectionManager> {
+        if let Some(connection) = self.connection.get() {
+            return Ok(connection.clone());
+        }
+
+        let failed_at = *self
+            .failed_at
+            .lock()
+            .map_err(|_| anyhow!("The redis event transport was poisoned"))?;
+        if failed_at.is_some_and(|failed_at| failed_at.elapsed() < RECONNECT_DELAY) {
+            bail!(
+                "Not connected to redis, the last attempt failed less than {RECONNECT_DELAY:?} ago"
+            );
+        }
+
+        let connect = || async {
+            tokio::time::timeout(CONNECT_TIMEOUT, self.client.get_connection_manager())
+                .await
+                .context("Timed out connecting to redis")?
+                .context("Error connecting to redis")
+        };
+        match self.connection.get_or_try_init(connect).await {
+            Ok(connection) => Ok(connection.clone()),
+            Err(e) => {
+                if let Ok(mut failed_at) = self.failed_at.lock() {
+                    *failed_at = Some(Instant::now());
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventTransport for RedisTransport {
+    async fn publish(&self, channel: &str, message: String) -> Result<()> {
+        let mut con = self.connection().await?;
+        let _: () = con.publish(channel, message).await?;
+
+        Ok(())
+    }
+
+    /// The stream ends when the connection to redis drops, subscribe again to continue.
+    async fn subscribe(&self, channel: &str) -> Result<MessageStream> {
+        let mut pubsub = self
+            .client
+            .get_async_pubsub()
+            .await
+            .context("Error connecting to redis")?;
+        pubsub
+            .subscribe(channel)
+            .await
+            .with_context(|| format!("Error subscribing to {}", channel))?;
+
+        // The subscription lasts as long as its sink
+        let (sink, messages) = pubsub.split();
+
+        Ok(Box::pin(messages.map(move |message| {
+            let _ = &sink;
+            message
+                .get_payload::<String>()
+                .context("Error reading a message from redis")
+        })))
+    }
+}
@@ -0,0 +1,214 @@
+//! Every event transport has to behave the same, so the same cases run against each of them.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::SubsecRound;
+
+use super::*;
+
+/// How long a case waits for an event before it gives up.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Generate a test per transport for every case. A case is an `async fn(EventBus) -> Result<()>`.
+///
+/// The redis cases run against the server in `FERCORD_TEST_REDIS_URL` and are skipped when that variable is not set.
+macro_rules! event_transport_suite {
+    ($($case:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $case() -> anyhow::Result<()> {
+                    super::$case(super::EventBus::in_process()).await
+                }
+            )*
+        }
+
+        mod redis {
+            $(
+                #[tokio::test]
+                async fn $case() -> anyhow::Result<()> {
+                    let Ok(url) = std::env::var("FERCORD_TEST_REDIS_URL") else {
+                        eprintln!("FERCORD_TEST_REDIS_URL is not set, skipping");
+                        return Ok(());
+                    };
+                    let transport = super::RedisTransport::new(&url)?;
+                    // Cases of different runs must not see each other's events
+                    let bus = super::EventBus::from_transport(std::sync::Arc::new(transport))
+                        .with_prefix(&uuid::Uuid::new_v4().to_string());
+
+                    super::$case(bus).await
+                }
+            )*
+        }
+    };
+}
+
+event_transport_suite!(
+    subscribers_receive_published_events,
+    every_subscriber_receives_the_event,
+    earlier_events_are_not_received,
+    prefixes_keep_deployments_apart,
+    sent_events_arrive_in_order,
+);
+
+async fn next_event(events: &mut EventStream) -> Result<StorageEvent> {
+    tokio::time::timeout(RECEIVE_TIMEOUT, events.next())
+        .await
+        .context("Timed out waiting for an event")?
+        .context("The subscription ended")?
+}
+
+async fn subscribers_receive_published_events(bus: EventBus) -> Result<()> {
+    let mut events = bus.subscribe().await?;
+    let inserted = StorageEvent::ReminderInserted {
+        id: 1,
+        when: Utc::now().trunc_subsecs(0),
+    };
+    let saved = StorageEvent::GuildSettingsSaved { guild_id: 42 };
+
+    bus.publish(&inserted).await?;
+    bus.publish(&saved).await?;
+
+    assert_eq!(inserted, next_event(&mut events).await?);
+    assert_eq!(saved, next_event(&mut events).await?);
+
+    Ok(())
+}
+
+async fn every_subscriber_receives_the_event(bus: EventBus) -> Result<()> {
+    let mut first = bus.subscribe().await?;
+    let mut second = bus.clone().subscribe().await?;
+    let deleted = StorageEvent::RemindersDeleted { ids: vec![1, 2] };
+
+    bus.publish(&deleted).await?;
+
+    assert_eq!(deleted, next_event(&mut first).await?);
+    assert_eq!(deleted, next_event(&mut second).await?);
+
+    Ok(())
+}
+
+async fn earlier_events_are_not_received(bus: EventBus) -> Result<()> {
+    bus.publish(&StorageEvent::RemindersChanged).await?;
+    let mut events = bus.subscribe().await?;
+    let saved = StorageEvent::GuildSettingsDeleted { guild_id: 42 };
+
+    bus.publish(&saved).await?;
+
+    assert_eq!(saved, next_event(&mut events).await?);
+
+    Ok(())
+}
+
+async fn prefixes_keep_deployments_apart(bus: EventBus) -> Result<()> {
+    let other = bus.clone().with_prefix("other");
+    let mut other_events = other.subscribe().await?;
+    let mut events = bus.subscribe().await?;
+
+    bus.publish(&StorageEvent::RemindersChanged).await?;
+    other
+        .publish(&StorageEvent::GuildSettingsSaved { guild_id: 42 })
+        .await?;
+
+    assert_eq!(
+        StorageEvent::RemindersChanged,
+        next_event(&mut events).await?
+    );
+    assert_eq!(
+        StorageEvent::GuildSettingsSaved { guild_id: 42 },
+        next_event(&mut other_events).await?
+    );
+
+    Ok(())
+}
+
+async fn sent_events_arrive_in_order(bus: EventBus) -> Result<()> {
+    let mut events = bus.subscribe().await?;
+    let sent: Vec<_> = (0..10)
+        .map(|guild_id| StorageEvent::GuildSettingsSaved { guild_id })
+        .collect();
+
+    for event in &sent {
+        bus.send(event.clone());
+    }
+
+    for event in sent {
+        assert_eq!(event, next_event(&mut events).await?);
+    }
+
+    Ok(())
+}
+
+/// A transport that never finishes publishing, like a redis server that stopped answering.
+#[derive(Debug)]
+struct StuckTransport;
+
+#[async_trait]
+impl EventTransport for StuckTransport {
+    async fn publish(&self, _channel: &str, _message: String) -> Result<()> {
+        std::future::pending().await
+    }
+
+    async fn subscribe(&self, _channel: &str) -> Result<MessageStream> {
+        Ok(Box::pin(tokio_stream::pending()))
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishing_to_a_stuck_transport_times_out() {
+    let bus = EventBus::from_transport(Arc::new(StuckTransport));
+
+    // Sending does not wait for the transport at all
+    bus.send(StorageEvent::RemindersChanged);
+
+    assert!(bus.publish(&StorageEvent::RemindersChanged).await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn flushing_gives_up_on_a_stuck_transport() {
+    let bus = EventBus::from_transport(Arc::new(StuckTransport));
+
+    bus.send(StorageEvent::RemindersChanged);
+    bus.send(StorageEvent::RemindersChanged);
+
+    tokio::time::timeout(PUBLISH_TIMEOUT * 3, bus.flush())
+        .await
+        .expect("Flushing should give up on the events");
+}
+
+/// A transport that takes a while to publish and remembers what it published.
+#[derive(Debug, Default)]
+struct SlowTransport {
+    published: std::sync::Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl EventTransport for SlowTransport {
+    async fn publish(&self, _channel: &str, message: String) -> Result<()> {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        self.published.lock().unwrap().push(message);
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, _channel: &str) -> Result<MessageStream> {
+        Ok(Box::pin(tokio_stream::pending()))
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn flushing_waits_for_sent_events() {
+    let transport = Arc::new(SlowTransport::default());
+    let bus = EventBus::from_transport(transport.clone());
+
+    // Nothing was sent yet
+    bus.flush().await;
+
+    for guild_id in 0..3 {
+        bus.send(StorageEvent::GuildSettingsSaved { guild_id });
+    }
+    bus.flush().await;
+
+    assert_eq!(3, transport.published.lock().unwrap().len());
+}
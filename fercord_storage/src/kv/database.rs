@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use poise::async_trait;
+use sqlx_oldapi::AnyPool;
+
+use crate::db::Backend;
+
+use super::{KVIdentity, KVStore};
+
+/// A KV store in the `kv_store` table of the database, for installs that run without redis.
+///
+/// Expired keys are hidden from every read and are deleted by [`crate::maintenance`].
+#[derive(Debug, Clone)]
+pub struct DatabaseStore {
+    pool: AnyPool,
+    backend: Backend,
+}
+
+/// The SQL of every KV store query, for a single backend.
+struct KVQueries {
+    set: &'static str,
+    set_if_absent: &'static str,
+    get: &'static str,
+    get_with_expiry: &'static str,
+    delete: &'static str,
+    delete_if_equal: &'static str,
+    delete_expired: &'static str,
+    keys: &'static str,
+}
+
+const SQLITE_QUERIES: KVQueries = KVQueries {
+    set: r#"INSERT INTO kv_store (kv_key, kv_value, expires_at)
+VALUES (?, ?, ?)
+ON CONFLICT (kv_key) DO UPDATE SET kv_value = excluded.kv_value, expires_at = excluded.expires_at;"#,
+    // An expired key counts as absent, but it can still be in the table
+    set_if_absent: r#"INSERT INTO kv_store (kv_key, kv_value, expires_at)
+VALUES (?, ?, ?)
+ON CONFLICT (kv_key) DO UPDATE SET kv_value = excluded.kv_value, expires_at = excluded.expires_at
+WHERE kv_store.expires_at <= ?;"#,
+    get:
+        "SELECT kv_value FROM kv_store WHERE kv_key = ? AND (expires_at IS NULL OR expires_at > ?)",
+    get_with_expiry: r#"SELECT kv_value, expires_at FROM kv_store
+WHERE kv_key = ? AND (expires_at IS NULL OR expires_at > ?)"#,
+    delete: "DELETE FROM kv_store WHERE kv_key = ?",
+    delete_if_equal: r#"DELETE FROM kv_store
+WHERE kv_key = ? AND kv_value = ? AND (expires_at IS NULL OR expires_at > ?)"#,
+    delete_expired: "DELETE FROM kv_store WHERE expires_at <= ?",
+    // GLOB is case sensitive and uses the same wildcards as redis, LIKE is neither.
+    keys:
+        "SELECT kv_key FROM kv_store WHERE kv_key GLOB ? AND (expires_at IS NULL OR expires_at > ?)",
+};
+
+const POSTGRES_QUERIES: KVQueries = KVQueries {
+    set: r#"INSERT INTO public.kv_store (kv_key, kv_value, expires_at)
+VALUES ($1, $2, $3)
+ON CONFLICT (kv_key) DO UPDATE SET kv_value = excluded.kv_value, expires_at = excluded.expires_at;"#,
+    set_if_absent: r#"INSERT INTO public.kv_store (kv_key, kv_value, expires_at)
+VALUES ($1, $2, $3)
+ON CONFLICT (kv_key) DO UPDATE SET kv_value = excluded.kv_value, expires_at = excluded.expires_at
+WHERE kv_store.expires_at <= $4;"#,
+    get: "SELECT kv_value FROM public.kv_store WHERE kv_key = $1 AND (expires_at IS NULL OR expires_at > $2)",
+    get_with_expiry: r#"SELECT kv_value, expires_at FROM public.kv_store
+WHERE kv_key = $1 AND (expires_at IS NULL OR expires_at > $2)"#,
+    delete: "DELETE FROM public.kv_store WHERE kv_key = $1",
+    delete_if_equal: r#"DELETE FROM public.kv_store
+WHERE kv_key = $1 AND kv_value = $2 AND (expires_at IS NULL OR expires_at > $3)"#,
+    delete_expired: "DELETE FROM public.kv_store WHERE expires_at <= $1",
+    keys: r#"SELECT kv_key FROM public.kv_store
+WHERE kv_key LIKE $1 ESCAPE '\' AND (expires_at IS NULL OR expires_at > $2)"#,
+};
+
+impl DatabaseStore {
+    pub fn new(pool: AnyPool) -> Self {
+        let backend = Backend::of(&pool);
+
+        Self { pool, backend }
+    }
+
+    fn queries(&self) -> &'static KVQueries {
+        match self.backend {
+            Backend::Sqlite => &SQLITE_QUERIES,
+            Backend::Postgres => &POSTGRES_QUERIES,
+        }
+    }
+
+    /// Delete the keys that expired at or before `now`, returns how many were deleted.
+    pub async fn delete_expired(&self, now: &DateTime<Utc>) -> Result<u64> {
+        let deleted = sqlx_oldapi::query(self.queries().delete_expired)
+            .bind(now.timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .context("Error removing expired KV keys")?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// Turn a glob pattern into what the `keys` query of the backend expects.
+    fn key_pattern(&self, pattern: &str) -> String {
+        match self.backend {
+            Backend::Sqlite => pattern.to_owned(),
+            Backend::Postgres => glob_to_like(pattern),
+        }
+    }
+}
+
+/// Translate the `*` and `?` wildcards to their LIKE counterparts, escaping what LIKE would otherwise interpret.
+fn glob_to_like(pattern: &str) -> String {
+    let mut like = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        match c {
+            '*' => like.push('%'),
+            '?' => like.push('_'),
+            '%' | '_' | '\\' => {
+                like.push('\\');
+                like.push(c);
+            }
+            c => like.push(c),
+        }
+    }
+
+    like
+}
+
+/// Expiry moments are stored as unix timestamps in milliseconds.
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// The moment a key saved `now` with `ttl` expires.
+fn expires_at(key: &str, now: i64, ttl: Option<Duration>) -> Result<Option<i64>> {
+    ttl.map(|ttl| i64::try_from(ttl.as_millis()).map(|ttl| now.saturating_add(ttl)))
+        .transpose()
+        .with_context(|| format!("The expiry of KV key {} is too far away", key))
+}
+
+#[async_trait]
+impl KVStore for DatabaseStore {
+    async fn set(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<()> {
+        let now = now_millis();
+        let expires_at = expires_at(key, now, ttl)?;
+
+        sqlx_oldapi::query(self.queries().set)
+            .bind(key)
+            .bind(value)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Error saving KV key {}", key))?;
+
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<bool> {
+        let now = now_millis();
+        let expires_at = expires_at(key, now, ttl)?;
+
+        let saved = sqlx_oldapi::query(self.queries().set_if_absent)
+            .bind(key)
+            .bind(value)
+            .bind(expires_at)
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Error saving KV key {}", key))?
+            .rows_affected();
+
+        Ok(saved > 0)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        sqlx_oldapi::query_scalar(self.queries().get)
+            .bind(key)
+            .bind(now_millis())
+            .fetch_optional(&self.pool)
+            .await
+            .with_context(|| format!("Error getting KV key {}", key))
+    }
+
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, Option<Duration>)>> {
+        let now = now_millis();
+        let row: Option<(String, Option<i64>)> =
+            sqlx_oldapi::query_as(self.queries().get_with_expiry)
+                .bind(key)
+                .bind(now)
+                .fetch_optional(&self.pool)
+                .await
+                .with_context(|| format!("Error getting KV key {}", key))?;
+
+        Ok(row.map(|(value, expires_at)| {
+            let ttl = expires_at.map(|expires_at| {
+                Duration::from_millis(expires_at.saturating_sub(now).max(0) as u64)
+            });
+
+            (value, ttl)
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        sqlx_oldapi::query(self.queries().delete)
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Error deleting KV key {}", key))?;
+
+        Ok(())
+    }
+
+    async fn delete_if_equal(&self, key: &str, value: &str) -> Result<bool> {
+        let deleted = sqlx_oldapi::query(self.queries().delete_if_equal)
+            .bind(key)
+            .bind(value)
+            .bind(now_millis())
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Error deleting KV key {}", key))?
+            .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
+    async fn keys(&self, pattern: &str) -> Result<Vec<KVIdentity>> {
+        sqlx_oldapi::query_scalar(self.queries().keys)
+            .bind(self.key_pattern(pattern))
+            .bind(now_millis())
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Error looking for KV keys matching {}", pattern))
+    }
+
+    async fn connection_check(&self) -> Result<()> {
+        self.pool
+            .acquire()
+            .await
+            .context("Could not get a database connection")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_patterns_escape_like_wildcards() {
+        assert_eq!("guild\\_timezone\\_%", glob_to_like("guild_timezone_*"));
+        assert_eq!("100\\%_", glob_to_like("100%?"));
+    }
+}
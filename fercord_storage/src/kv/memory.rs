@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use poise::async_trait;
+
+use super::{KVIdentity, KVStore};
+
+/// A KV store that lives in the memory of the process.
+///
+/// Nothing is persisted and nothing is shared with other processes, so this only suits tests and single process
+/// installs that can live with losing the KV data on a restart.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<KVIdentity, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+impl MemoryStore {
+    /// The entries, without the ones that expired.
+    fn entries(&self) -> Result<std::sync::MutexGuard<'_, HashMap<KVIdentity, Entry>>> {
+        let mut entries = self
+            .entries
+            .lock()
+            .map_err(|_| anyhow!("The in-memory KV store was poisoned"))?;
+
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.is_live(now));
+
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl KVStore for MemoryStore {
+    async fn set(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<()> {
+        let entry = Entry {
+            value,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        };
+        self.entries()?.insert(key.to_owned(), entry);
+
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<bool> {
+        let mut entries = self.entries()?;
+        if entries.contains_key(key) {
+            return Ok(false);
+        }
+
+        let entry = Entry {
+            value,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        };
+        entries.insert(key.to_owned(), entry);
+
+        Ok(true)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.entries()?.get(key).map(|entry| entry.value.clone()))
+    }
+
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, Option<Duration>)>> {
+        let now = Instant::now();
+
+        Ok(self.entries()?.get(key).map(|entry| {
+            let ttl = entry
+                .expires_at
+                .map(|expires_at| expires_at.saturating_duration_since(now));
+
+            (entry.value.clone(), ttl)
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.entries()?.remove(key);
+
+        Ok(())
+    }
+
+    async fn delete_if_equal(&self, key: &str, value: &str) -> Result<bool> {
+        let mut entries = self.entries()?;
+        if entries.get(key).is_none_or(|entry| entry.value != value) {
+            return Ok(false);
+        }
+        entries.remove(key);
+
+        Ok(true)
+    }
+
+    async fn keys(&self, pattern: &str) -> Result<Vec<KVIdentity>> {
+        let pattern: Vec<char> = pattern.chars().collect();
+
+        Ok(self
+            .entries()?
+            .keys()
+            .filter(|key| glob_matches(&pattern, &key.chars().collect::<Vec<_>>()))
+            .cloned()
+            .collect())
+    }
+
+    async fn connection_check(&self) -> Result<()> {
+        self.entries().map(|_| ())
+    }
+}
+
+/// Match `text` against a glob pattern with `*` and `?` wildcards.
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was seen in the pattern and the text position it currently matches up to.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and try again.
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        glob_matches(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn glob_wildcards_match() {
+        assert!(matches("guild_timezone_*", "guild_timezone_123"));
+        assert!(matches("guild_timezone_*", "guild_timezone_"));
+        assert!(matches("*_settings_?", "guild_settings_1"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("exact", "exact"));
+
+        assert!(!matches("guild_timezone_*", "guild_settings_123"));
+        assert!(!matches("*_settings_?", "guild_settings_12"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(!matches("exact", "exactly"));
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{
+    fmt::Debug,
+    marker::{Send, Sync},
+};
+
+use anyhow::{ensure, Context, Result};
+use poise::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx_oldapi::AnyPool;
+use tracing::*;
+
+use fercord_common::config::{DiscordConfig, KVStoreKind};
+
+pub use self::database::DatabaseStore;
+pub use self::memory::MemoryStore;
+pub use self::redis::RedisStore;
+
+mod database;
+mod memory;
+mod redis;
+
+#[cfg(test)]
+mod tests;
+
+pub type KVIdentity = String;
+
+/// A key-value store that holds string values.
+///
+/// [`KVClient`] builds the typed operations on top of this, so a new kind of store only has to move strings around.
+#[async_trait]
+pub trait KVStore: Debug + Send + Sync {
+    /// Save the value under the key, replacing any value that was already there.
+    ///
+    /// With a `ttl` the key is removed once that much time has passed, without one it never expires.
+    async fn set(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<()>;
+    /// Save the value under the key, unless the key already exists. Returns whether the value was saved.
+    ///
+    /// Checking and saving is a single step, so of all callers racing for the same key only one gets `true`.
+    async fn set_if_absent(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<bool>;
+    /// Get the value of the key, `None` if the key does not exist.
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+    /// Get the value of the key together with the time it has left, which is `None` when it never expires.
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, Option<Duration>)>>;
+    /// Get the values of all keys, in the same order as the keys.
+    async fn get_many(&self, keys: &[KVIdentity]) -> Result<Vec<Option<String>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+
+        Ok(values)
+    }
+    /// Remove the key, if it exists.
+    async fn delete(&self, key: &str) -> Result<()>;
+    /// Remove the key, but only while its value is `value`. Returns whether the key was removed.
+    ///
+    /// Checking and removing is a single step, so a key that was replaced in the meantime is kept.
+    async fn delete_if_equal(&self, key: &str, value: &str) -> Result<bool>;
+    /// Find all keys that match the glob-style pattern. `*` matches any amount of characters, `?` a single one.
+    async fn keys(&self, pattern: &str) -> Result<Vec<KVIdentity>>;
+    /// Check that the store can be reached.
+    async fn connection_check(&self) -> Result<()>;
+}
+
+/// A generic KV store client.
+///
+/// ## Creation
+/// Create a new client by calling `KVClient::new`, which picks the store from the configuration.
+/// Use `KVClient::in_memory` when the data does not have to outlive the process, like in tests.
+///
+/// ## Prefix
+/// With a prefix every key is stored as `{prefix}:{key}`, so deployments can share a store without seeing each other's
+/// keys. The prefix is invisible to the callers of the client.
+#[derive(Debug, Clone)]
+pub struct KVClient {
+    store: Arc<dyn KVStore>,
+    prefix: Option<String>,
+}
+
+/// Marks an object as identifyable to the KV Client.
+pub trait Identifiable {
+    fn kv_key(&self) -> KVIdentity;
+}
+
+impl KVClient {
+    /// Create a new `KVClient` from a `&DiscordConfig`.
+    ///
+    /// The database pool is only used when the configuration puts the KV store in the database.
+    #[instrument(level = "trace", skip(db_pool))]
+    pub fn new(config: &DiscordConfig, db_pool: &AnyPool) -> Result<Self> {
+        let kind = config.kv_store_kind();
+        event!(Level::DEBUG, ?kind, "Setting up the KV store");
+
+        let store: Arc<dyn KVStore> = match kind {
+            KVStoreKind::Redis => {
+                let url = config
+                    .redis_url
+                    .as_deref()
+                    .context("redis_url is required when the KV store is redis")?;
+
+                Arc::new(RedisStore::new(url)?)
+            }
+            KVStoreKind::Memory => Arc::new(MemoryStore::default()),
+            KVStoreKind::Database => Arc::new(DatabaseStore::new(db_pool.clone())),
+        };
+
+        let client = Self::from_store(store);
+
+        Ok(match &config.kv_prefix {
+            Some(prefix) => client.with_prefix(prefix),
+            None => client,
+        })
+    }
+
+    /// Create a `KVClient` that keeps everything in memory. Clones of the client share the data.
+    pub fn in_memory() -> Self {
+        Self::from_store(Arc::new(MemoryStore::default()))
+    }
+
+    /// Create a `KVClient` on top of any store.
+    pub fn from_store(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            prefix: None,
+        }
+    }
+
+    /// Put every key of this client under `prefix`.
+    pub fn with_prefix(self, prefix: &str) -> Self {
+        Self {
+            prefix: Some(prefix.to_owned()),
+            ..self
+        }
+    }
+
+    /// The key in the store for the key of a record.
+    fn store_key(&self, key: &str) -> KVIdentity {
+        match &self.prefix {
+            Some(prefix) => format!("{prefix}:{key}"),
+            None => key.to_owned(),
+        }
+    }
+
+    /// Save a value to the KV store.
+    pub async fn save<T>(&self, record: T) -> Result<()>
+    where
+        T: Identifiable + Display + Send + Sync + Debug,
+    {
+        let span = trace_span!(
+            "storage.kv_client",
+            record = field::Empty,
+            save_key = field::Empty
+        );
+        let _enter = span.enter();
+        span.record("record", field::debug(&record));
+        event!(
+            parent: &span,
+            Level::TRACE,
+            "Saving a record to the KV store"
+        );
+
+        let save_key = &self.store_key(&record.kv_key());
+        span.record("save_key", field::debug(&save_key));
+
+        self.store
+            .set(save_key, record.to_string(), None)
+            .await
+            .inspect_err(|e| error!(?e, "Error saving value to kv store"))
+    }
+
+    /// Save complex objects as json in the KV store.
+    pub async fn save_json<T>(&self, record: T) -> Result<()>
+    where
+        T: Identifiable + Serialize + Send + Sync + Debug,
+    {
+        self.save_json_expiring(record, None).await
+    }
+
+    /// Save complex objects as json in the KV store, they are removed after `ttl`.
+    pub async fn save_json_with_ttl<T>(&self, record: T, ttl: Duration) -> Result<()>
+    where
+        T: Identifiable + Serialize + Send + Sync + Debug,
+    {
+        ensure!(
+            !ttl.is_zero(),
+            "The time to live of a KV record can not be zero"
+        );
+
+        self.save_json_expiring(record, Some(ttl)).await
+    }
+
+    /// Save complex objects as json in the KV store, unless their key already exists. They are removed after `ttl`.
+    ///
+    /// Returns whether the record was saved, use this to claim something that only one process may have at a time.
+    pub async fn save_json_if_absent<T>(&self, record: T, ttl: Duration) -> Result<bool>
+    where
+        T: Identifiable + Serialize + Send + Sync + Debug,
+    {
+        ensure!(
+            !ttl.is_zero(),
+            "The time to live of a KV record can not be zero"
+        );
+
+        let save_key = self.store_key(&record.kv_key());
+        event!(Level::TRACE, %save_key, "Saving a record to the KV store unless it exists");
+
+        let json = serde_json::to_string(&record)?;
+        self.store
+            .set_if_absent(&save_key, json, Some(ttl))
+            .await
+            .inspect_err(|e| error!(?e, "Error saving value to kv store"))
+    }
+
+    async fn save_json_expiring<T>(&self, record: T, ttl: Option<Duration>) -> Result<()>
+    where
+        T: Identifiable + Serialize + Send + Sync + Debug,
+    {
+        let span = trace_span!(
+            "storage.kv_client",
+            record = field::Empty,
+            save_key = field::Empty,
+            ttl = field::Empty
+        );
+        let _enter = span.enter();
+        span.record("record", field::debug(&record));
+        if let Some(ttl) = ttl {
+            span.record("ttl", field::debug(ttl));
+        }
+        event!(Level::TRACE, "Saving a record to the KV store in json mode");
+
+        let json = serde_json::to_string(&record)?;
+
+        let save_key = &self.store_key(&record.kv_key());
+        span.record("save_key", field::debug(&save_key));
+
+        self.store
+            .set(save_key, json, ttl)
+            .await
+            .inspect_err(|e| error!(?e, "Error saving value to kv store"))
+    }
+
+    /// Retrieve a complex record for the given key.
+    pub async fn get_json<T>(&self, record: &T) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + Debug + Identifiable,
+    {
+        let span = trace_span!(
+            "storage.kv_client",
+            record = field::Empty,
+            object_key = field::Empty
+        );
+        let _enter = span.enter();
+
+        let key = self.store_key(&record.kv_key());
+        span.record("object_key", &key);
+
+        event!(Level::TRACE, "Retrieving a record from the kv store");
+
+        let Some(json) = self.store.get(&key).await? else {
+            return Ok(None);
+        };
+
+        let record = serde_json::from_str(json.as_str())?;
+
+        span.record("record", field::debug(&record));
+
+        Ok(Some(record))
+    }
+
+    /// Retrieve the complex records for the keys of all given records, in one round trip when the store allows it.
+    ///
+    /// The result has an entry for every given record, in the same order, which is `None` when the key does not exist.
+    pub async fn get_many_json<T>(&self, records: &[T]) -> Result<Vec<Option<T>>>
+    where
+        T: DeserializeOwned + Send + Sync + Debug + Identifiable,
+    {
+        let keys: Vec<KVIdentity> = records
+            .iter()
+            .map(|record| self.store_key(&record.kv_key()))
+            .collect();
+        event!(Level::TRACE, ?keys, "Retrieving records from the kv store");
+
+        self.store
+            .get_many(&keys)
+            .await?
+            .into_iter()
+            .zip(&keys)
+            .map(|(json, key)| {
+                json.map(|json| serde_json::from_str(&json))
+                    .transpose()
+                    .with_context(|| format!("Error reading KV record {}", key))
+            })
+            .collect()
+    }
+
+    /// Retrieve the value of a key as it is stored, for tools that copy values without knowing their type.
+    pub async fn get_raw(&self, key: &str) -> Result<Option<String>> {
+        event!(Level::TRACE, %key, "Retrieving a raw value from the kv store");
+
+        self.store.get(&self.store_key(key)).await
+    }
+
+    /// Retrieve the value of a key as it is stored and the time it has left, see [`KVClient::get_raw`].
+    pub async fn get_raw_with_ttl(&self, key: &str) -> Result<Option<(String, Option<Duration>)>> {
+        event!(Level::TRACE, %key, "Retrieving a raw value and its ttl from the kv store");
+
+        self.store.get_with_ttl(&self.store_key(key)).await
+    }
+
+    /// Save a value under a key as it is, the counterpart of [`KVClient::get_raw`]. With a `ttl` the key expires.
+    pub async fn save_raw(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<()> {
+        event!(Level::TRACE, %key, ?ttl, "Saving a raw value to the kv store");
+        ensure!(
+            ttl.is_none_or(|ttl| !ttl.is_zero()),
+            "The time to live of a KV record can not be zero"
+        );
+
+        self.store.set(&self.store_key(key), value, ttl).await
+    }
+
+    /// Remove the record from the KV store, if it exists.
+    pub async fn delete<T>(&self, record: &T) -> Result<()>
+    where
+        T: Identifiable,
+    {
+        let key = self.store_key(&record.kv_key());
+        event!(Level::TRACE, %key, "Deleting a record from the kv store");
+
+        self.store.delete(&key).await
+    }
+
+    /// Remove the record from the KV store, but only while it is stored exactly as given. Returns whether it was removed.
+    ///
+    /// Use this to give up a claim of [`KVClient::save_json_if_absent`] without removing a claim made by someone else.
+    pub async fn delete_json_if_equal<T>(&self, record: &T) -> Result<bool>
+    where
+        T: Identifiable + Serialize + Send + Sync + Debug,
+    {
+        let key = self.store_key(&record.kv_key());
+        event!(Level::TRACE, %key, "Deleting a record from the kv store if it is unchanged");
+
+        let json = serde_json::to_string(record)?;
+        self.store.delete_if_equal(&key, &json).await
+    }
+
+    /// Remove a key as it is, the counterpart of [`KVClient::get_raw`].
+    pub async fn delete_raw(&self, key: &str) -> Result<()> {
+        event!(Level::TRACE, %key, "Deleting a raw value from the kv store");
+
+        self.store.delete(&self.store_key(key)).await
+    }
+
+    /// Find all keys that match the (glob-style) pattern.
+    pub async fn keys(&self, pattern: &str) -> Result<Vec<KVIdentity>> {
+        event!(Level::TRACE, %pattern, "Scanning the kv store for keys");
+
+        let keys = self.store.keys(&self.store_key(pattern)).await?;
+
+        Ok(match &self.prefix {
+            Some(prefix) => keys
+                .into_iter()
+                .filter_map(|key| Some(key.strip_prefix(prefix)?.strip_prefix(':')?.to_owned()))
+                .collect(),
+            None => keys,
+        })
+    }
+
+    /// Perform a connection check.
+    pub async fn connection_check(&self) -> Result<()> {
+        self.store.connection_check().await
+    }
+}
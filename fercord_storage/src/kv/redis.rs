@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use poise::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client};
+use tokio::sync::OnceCell;
+
+use super::{KVIdentity, KVStore};
+
+/// How long [`RedisStore::connection_check`] waits for redis to answer.
+const CONNECTION_CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A KV store on a redis server.
+///
+/// All calls share one [`ConnectionManager`], which multiplexes the commands over a single connection and reconnects
+/// when that connection drops. It is set up by the first call, so creating the store does not need redis to be up.
+#[derive(Clone)]
+pub struct RedisStore {
+    client: Client,
+    connection: std::sync::Arc<OnceCell<ConnectionManager>>,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("client", &self.client)
+            .field("connected", &self.connection.initialized())
+            .finish()
+    }
+}
+
+impl RedisStore {
+    /// Create a store for the redis server at `url`. This does not connect yet.
+    pub fn new(url: &str) -> Result<Self> {
+        let client = Client::open(url)?;
+
+        Ok(Self {
+            client,
+            connection: Default::default(),
+        })
+    }
+
+    /// The shared connection. Clones of a `ConnectionManager` all use the same underlying connection.
+    async fn connection(&self) -> Result<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| self.client.get_connection_manager())
+            .await
+            .context("Error connecting to redis")?;
+
+        Ok(connection.clone())
+    }
+}
+
+#[async_trait]
+impl KVStore for RedisStore {
+    async fn set(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<()> {
+        let mut con = self.connection().await?;
+        let _: () = match ttl {
+            Some(ttl) => con.pset_ex(key, value, ttl.as_millis() as u64).await?,
+            None => con.set(key, value).await?,
+        };
+
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<bool> {
+        let mut con = self.connection().await?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(key).arg(value).arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis() as u64);
+        }
+
+        // SET with NX answers nil when the key already exists
+        let saved: Option<String> = cmd.query_async(&mut con).await?;
+
+        Ok(saved.is_some())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut con = self.connection().await?;
+
+        Ok(con.get(key).await?)
+    }
+
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, Option<Duration>)>> {
+        let mut con = self.connection().await?;
+        let (value, ttl): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(key)
+            .pttl(key)
+            .query_async(&mut con)
+            .await?;
+
+        // PTTL answers -1 for a key without expiry
+        let ttl = u64::try_from(ttl).ok().map(Duration::from_millis);
+
+        Ok(value.map(|value| (value, ttl)))
+    }
+
+    async fn get_many(&self, keys: &[KVIdentity]) -> Result<Vec<Option<String>>> {
+        // MGET needs at least one key
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut con = self.connection().await?;
+
+        Ok(con.mget(keys).await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let mut con = self.connection().await?;
+        let _: () = con.del(key).await?;
+
+        Ok(())
+    }
+
+    async fn delete_if_equal(&self, key: &str, value: &str) -> Result<bool> {
+        let mut con = self.connection().await?;
+        // A script runs atomically, so the key can not change between GET and DEL
+        let deleted: i64 = redis::Script::new(
+            r#"if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end"#,
+        )
+        .key(key)
+        .arg(value)
+        .invoke_async(&mut con)
+        .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn keys(&self, pattern: &str) -> Result<Vec<KVIdentity>> {
+        let mut con = self.connection().await?;
+        let mut iter = con.scan_match::<_, KVIdentity>(pattern).await?;
+
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key?);
+        }
+
+        Ok(keys)
+    }
+
+    /// If redis answers a `PING` in 15 seconds, we return `Ok()`.
+    async fn connection_check(&self) -> Result<()> {
+        let ping = async {
+            let mut con = self.connection().await?;
+            let _: String = redis::cmd("PING").query_async(&mut con).await?;
+
+            anyhow::Ok(())
+        };
+
+        tokio::time::timeout(CONNECTION_CHECK_TIMEOUT, ping)
+            .await
+            .context("Timed out waiting for redis")?
+    }
+}
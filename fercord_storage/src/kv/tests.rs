@@ -0,0 +1,280 @@
+//! Every KV store has to behave the same, so the same cases run against each of them.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Generate a test per store for every case. A case is an `async fn(KVClient) -> Result<()>`.
+///
+/// The redis cases run against the server in `FERCORD_TEST_REDIS_URL` and are skipped when that variable is not set.
+macro_rules! kv_store_suite {
+    ($($case:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $case() -> anyhow::Result<()> {
+                    super::$case(super::KVClient::in_memory()).await
+                }
+            )*
+        }
+
+        mod database {
+            use sqlx_oldapi::AnyPool;
+
+            use crate::testing::conformance_suite;
+
+            conformance_suite!($($case),*);
+
+            $(
+                async fn $case(pool: &AnyPool) -> anyhow::Result<()> {
+                    let store = super::DatabaseStore::new(pool.clone());
+
+                    super::$case(super::KVClient::from_store(std::sync::Arc::new(store))).await
+                }
+            )*
+        }
+
+        mod redis {
+            $(
+                #[tokio::test]
+                async fn $case() -> anyhow::Result<()> {
+                    let Ok(url) = std::env::var("FERCORD_TEST_REDIS_URL") else {
+                        eprintln!("FERCORD_TEST_REDIS_URL is not set, skipping");
+                        return Ok(());
+                    };
+                    let store = super::RedisStore::new(&url)?;
+
+                    super::$case(super::KVClient::from_store(std::sync::Arc::new(store))).await
+                }
+            )*
+        }
+    };
+}
+
+kv_store_suite!(
+    saved_record_can_be_retrieved,
+    unknown_key_returns_none,
+    saving_replaces_record,
+    deleted_record_is_gone,
+    keys_match_glob_pattern,
+    connection_check_succeeds,
+    record_with_ttl_expires,
+    get_many_returns_records_in_order,
+    prefixes_keep_deployments_apart,
+    saving_if_absent_keeps_the_existing_record,
+    deleting_if_equal_keeps_a_changed_record,
+    raw_values_keep_their_ttl,
+);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Note {
+    key: String,
+    text: String,
+}
+
+impl Note {
+    fn new(key: impl Into<String>, text: &str) -> Self {
+        Self {
+            key: key.into(),
+            text: text.into(),
+        }
+    }
+}
+
+impl Identifiable for Note {
+    fn kv_key(&self) -> KVIdentity {
+        self.key.clone()
+    }
+}
+
+/// A key prefix no other test case uses, so cases can share a store.
+fn unique_prefix() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+async fn saved_record_can_be_retrieved(kv: KVClient) -> Result<()> {
+    let note = Note::new(unique_prefix(), "hello");
+
+    kv.save_json(note.clone()).await?;
+
+    assert_eq!(Some(note.clone()), kv.get_json(&note).await?);
+
+    Ok(())
+}
+
+async fn unknown_key_returns_none(kv: KVClient) -> Result<()> {
+    assert_eq!(None, kv.get_json(&Note::new(unique_prefix(), "")).await?);
+
+    Ok(())
+}
+
+async fn saving_replaces_record(kv: KVClient) -> Result<()> {
+    let key = unique_prefix();
+    kv.save_json(Note::new(&key, "first")).await?;
+
+    let second = Note::new(&key, "second");
+    kv.save_json(second.clone()).await?;
+
+    assert_eq!(Some(second.clone()), kv.get_json(&second).await?);
+
+    Ok(())
+}
+
+async fn deleted_record_is_gone(kv: KVClient) -> Result<()> {
+    let note = Note::new(unique_prefix(), "bye");
+    kv.save_json(note.clone()).await?;
+
+    kv.delete(&note).await?;
+
+    assert_eq!(None, kv.get_json(&note).await?);
+
+    Ok(())
+}
+
+async fn keys_match_glob_pattern(kv: KVClient) -> Result<()> {
+    let prefix = unique_prefix();
+    for key in ["_a1", "_a22", "_b1", "Xa1"] {
+        kv.save_json(Note::new(format!("{prefix}{key}"), ""))
+            .await?;
+    }
+
+    let mut keys = kv.keys(&format!("{prefix}_a*")).await?;
+    keys.sort();
+    assert_eq!(vec![format!("{prefix}_a1"), format!("{prefix}_a22")], keys);
+
+    let keys = kv.keys(&format!("{prefix}_?1")).await?.len();
+    assert_eq!(2, keys);
+
+    Ok(())
+}
+
+async fn connection_check_succeeds(kv: KVClient) -> Result<()> {
+    kv.connection_check().await
+}
+
+async fn record_with_ttl_expires(kv: KVClient) -> Result<()> {
+    let short = Note::new(unique_prefix(), "short");
+    let long = Note::new(unique_prefix(), "long");
+
+    kv.save_json_with_ttl(short.clone(), Duration::from_millis(500))
+        .await?;
+    kv.save_json_with_ttl(long.clone(), Duration::from_secs(60))
+        .await?;
+    assert_eq!(Some(short.clone()), kv.get_json(&short).await?);
+
+    tokio::time::sleep(Duration::from_millis(700)).await;
+
+    assert_eq!(None, kv.get_json(&short).await?);
+    assert_eq!(Some(long.clone()), kv.get_json(&long).await?);
+
+    Ok(())
+}
+
+async fn get_many_returns_records_in_order(kv: KVClient) -> Result<()> {
+    let first = Note::new(unique_prefix(), "first");
+    let missing = Note::new(unique_prefix(), "missing");
+    let second = Note::new(unique_prefix(), "second");
+    kv.save_json(first.clone()).await?;
+    kv.save_json(second.clone()).await?;
+
+    let notes = kv
+        .get_many_json(&[second.clone(), missing.clone(), first.clone()])
+        .await?;
+
+    assert_eq!(vec![Some(second), None, Some(first)], notes);
+    assert!(kv.get_many_json::<Note>(&[]).await?.is_empty());
+
+    Ok(())
+}
+
+async fn prefixes_keep_deployments_apart(kv: KVClient) -> Result<()> {
+    let ours = kv.clone().with_prefix(&unique_prefix());
+    let theirs = kv.with_prefix(&unique_prefix());
+    let note = Note::new("shared_key", "ours");
+
+    ours.save_json(note.clone()).await?;
+
+    assert_eq!(Some(note.clone()), ours.get_json(&note).await?);
+    assert_eq!(None, theirs.get_json(&note).await?);
+    assert_eq!(vec!["shared_key".to_owned()], ours.keys("shared_*").await?);
+    assert!(theirs.keys("shared_*").await?.is_empty());
+
+    Ok(())
+}
+
+async fn saving_if_absent_keeps_the_existing_record(kv: KVClient) -> Result<()> {
+    let key = unique_prefix();
+    let first = Note::new(key.clone(), "first");
+    let second = Note::new(key.clone(), "second");
+
+    assert!(
+        kv.save_json_if_absent(first.clone(), Duration::from_millis(500))
+            .await?
+    );
+    assert!(
+        !kv.save_json_if_absent(second.clone(), Duration::from_secs(60))
+            .await?
+    );
+    assert_eq!(Some(first), kv.get_json(&second).await?);
+
+    // An expired record no longer counts
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert!(
+        kv.save_json_if_absent(second.clone(), Duration::from_secs(60))
+            .await?
+    );
+    assert_eq!(Some(second.clone()), kv.get_json(&second).await?);
+
+    Ok(())
+}
+
+async fn deleting_if_equal_keeps_a_changed_record(kv: KVClient) -> Result<()> {
+    let key = unique_prefix();
+    let first = Note::new(key.clone(), "first");
+    let second = Note::new(key.clone(), "second");
+
+    kv.save_json(second.clone()).await?;
+    assert!(!kv.delete_json_if_equal(&first).await?);
+    assert_eq!(Some(second.clone()), kv.get_json(&second).await?);
+
+    assert!(kv.delete_json_if_equal(&second).await?);
+    assert_eq!(None, kv.get_json(&second).await?);
+    assert!(!kv.delete_json_if_equal(&second).await?);
+
+    Ok(())
+}
+
+async fn raw_values_keep_their_ttl(kv: KVClient) -> Result<()> {
+    let expiring = unique_prefix();
+    let forever = unique_prefix();
+
+    kv.save_raw(&expiring, "1".into(), Some(Duration::from_secs(60)))
+        .await?;
+    kv.save_raw(&forever, "2".into(), None).await?;
+
+    let Some((value, Some(ttl))) = kv.get_raw_with_ttl(&expiring).await? else {
+        panic!("The expiring value lost its ttl");
+    };
+    assert_eq!("1", value);
+    assert!(ttl > Duration::from_secs(50) && ttl <= Duration::from_secs(60));
+    assert_eq!(
+        Some(("2".to_owned(), None)),
+        kv.get_raw_with_ttl(&forever).await?
+    );
+    assert_eq!(None, kv.get_raw_with_ttl(&unique_prefix()).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn zero_ttl_is_rejected() {
+    let kv = KVClient::in_memory();
+
+    assert!(kv
+        .save_json_with_ttl(Note::new(unique_prefix(), ""), Duration::ZERO)
+        .await
+        .is_err());
+}
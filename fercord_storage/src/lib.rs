@@ -11,11 +11,36 @@ pub mod model;
 /// Database access
 pub mod db;
 
+/// In-process caches
+pub mod cache;
+
+/// Encryption of stored values
+pub mod encryption;
+
+/// Backups of all data
+pub mod backup;
+
+/// Copying data between databases
+pub mod transfer;
+
+/// Personal data of users
+pub mod privacy;
+
+/// Notifications about changes to the stored data
+pub mod events;
+
+/// Housekeeping of the stored data
+pub mod maintenance;
+
+#[cfg(test)]
+mod testing;
+
 /// fercord_storage prelude
 pub mod prelude {
     pub use sqlx_oldapi::any::AnyPool;
 
     pub use crate::db::{self, *};
-    pub use crate::kv::{Identifiable, KVClient, KVIdentity};
+    pub use crate::events::{EventBus, StorageEvent};
+    pub use crate::kv::{Identifiable, KVClient, KVIdentity, KVStore};
     pub use crate::model::{self, *};
 }
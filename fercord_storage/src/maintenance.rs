@@ -0,0 +1,108 @@
+//! Housekeeping of the stored data, run regularly by the `maintenance` job of the bot.
+
+use std::fmt::{Display, Formatter};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx_oldapi::AnyPool;
+use tracing::{event, Level};
+
+use crate::kv::DatabaseStore;
+use crate::model::Session;
+
+/// What a maintenance run cleaned up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceSummary {
+    pub expired_sessions: u64,
+    pub expired_kv_keys: u64,
+}
+
+impl Display for MaintenanceSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} expired sessions, {} expired KV keys",
+            self.expired_sessions, self.expired_kv_keys
+        )
+    }
+}
+
+/// Delete the data that expired at or before `now`.
+pub async fn run_maintenance(pool: &AnyPool, now: &DateTime<Utc>) -> Result<MaintenanceSummary> {
+    let summary = MaintenanceSummary {
+        expired_sessions: Session::repository(pool)
+            .delete_sessions_expired_before(now)
+            .await?,
+        expired_kv_keys: DatabaseStore::new(pool.clone()).delete_expired(now).await?,
+    };
+
+    event!(Level::DEBUG, %summary, "Finished storage maintenance");
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::db;
+    use crate::kv::KVStore;
+
+    #[tokio::test]
+    async fn maintenance_deletes_expired_sessions() -> Result<()> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let repo = Session::repository(&pool);
+        let now = Utc::now();
+        for (key, expires_at) in [
+            ("expired", now - TimeDelta::minutes(1)),
+            ("live", now + TimeDelta::minutes(1)),
+        ] {
+            repo.insert_session(&Session {
+                key: key.into(),
+                user_id: None,
+                state: "{}".into(),
+                expires_at,
+            })
+            .await?;
+        }
+
+        let summary = run_maintenance(&pool, &now).await?;
+
+        assert_eq!(
+            MaintenanceSummary {
+                expired_sessions: 1,
+                expired_kv_keys: 0,
+            },
+            summary
+        );
+        assert!(repo.get_session("live").await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn maintenance_deletes_expired_kv_keys() -> Result<()> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let store = DatabaseStore::new(pool.clone());
+        store
+            .set("expired", "1".into(), Some(Duration::from_millis(1)))
+            .await?;
+        store.set("forever", "1".into(), None).await?;
+        store
+            .set("live", "1".into(), Some(Duration::from_secs(60)))
+            .await?;
+
+        let summary = run_maintenance(&pool, &(Utc::now() + TimeDelta::seconds(1))).await?;
+
+        assert_eq!(1, summary.expired_kv_keys);
+        let remaining: i64 = sqlx_oldapi::query_scalar("SELECT COUNT(*) FROM kv_store")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(2, remaining);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx_oldapi::{Any, AnyPool, FromRow, Transaction};
+use tracing::{event, Level};
+
+use crate::db::{from_db_snowflake, to_db_snowflake, Backend, Repo};
+use crate::events::EventBus;
+use crate::kv::KVClient;
+use crate::model::guild_settings::GuildSettings;
+use crate::model::guild_timezone::GuildTimezone;
+use crate::model::reminder::{Reminder, ReminderFilter};
+
+mod sqlite;
+
+mod postgres;
+
+#[cfg(test)]
+mod tests;
+
+/// A guild the bot was removed from.
+///
+/// The data of the guild is kept for a grace period, in case the bot is added again. Use [`leave_guild`],
+/// [`rejoin_guild`] and [`purge_guild`] to keep the reminders and settings of the guild in sync with its departure.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GuildDeparture {
+    pub guild_id: u64,
+    pub left_at: DateTime<Utc>,
+}
+
+impl GuildDeparture {
+    /// Create a `GuildDeparture` repository that connects to the database with the borrowed pool.
+    pub fn repository(pool: &AnyPool) -> GuildDepartureRepo<'_> {
+        Repo {
+            pool,
+            backend: Backend::of(pool),
+            cipher: None,
+            events: None,
+        }
+    }
+}
+
+pub type GuildDepartureRepo<'r> = Repo<'r>;
+
+/// The SQL of every guild departure query, for a single backend.
+pub(crate) struct GuildDepartureQueries {
+    pub get: &'static str,
+    pub save: &'static str,
+    pub delete: &'static str,
+    pub list_before: &'static str,
+}
+
+impl<'r> GuildDepartureRepo<'r> {
+    fn guild_departure_queries(&self) -> &'static GuildDepartureQueries {
+        match self.backend {
+            Backend::Sqlite => &sqlite::QUERIES,
+            Backend::Postgres => &postgres::QUERIES,
+        }
+    }
+
+    /// Get the departure from a guild, `None` if the bot did not leave it.
+    pub async fn get_guild_departure(&self, guild_id: u64) -> Result<Option<GuildDeparture>> {
+        event!(Level::TRACE, guild_id, "Retrieving guild departure");
+
+        let entity =
+            sqlx_oldapi::query_as::<Any, GuildDepartureEntity>(self.guild_departure_queries().get)
+                .bind(to_db_snowflake(guild_id)?)
+                .fetch_optional(self.pool)
+                .await
+                .with_context(|| format!("Error getting the departure from guild {}", guild_id))?;
+
+        entity.map(GuildDeparture::try_from).transpose()
+    }
+
+    /// Save the departure from a guild. When the bot already left the guild, the first departure is kept.
+    pub async fn save_guild_departure(&self, departure: &GuildDeparture) -> Result<()> {
+        event!(Level::TRACE, ?departure, "Saving guild departure");
+
+        sqlx_oldapi::query(self.guild_departure_queries().save)
+            .bind(to_db_snowflake(departure.guild_id)?)
+            .bind(departure.left_at.timestamp())
+            .execute(self.pool)
+            .await
+            .with_context(|| {
+                format!(
+                    "Error saving the departure from guild {}",
+                    departure.guild_id
+                )
+            })?;
+
+        Ok(())
+    }
+
+    /// Save the departure from a guild as part of `trans`, see [`GuildDepartureRepo::save_guild_departure`].
+    pub(crate) async fn save_guild_departure_in(
+        &self,
+        trans: &mut Transaction<'_, Any>,
+        departure: &GuildDeparture,
+    ) -> Result<()> {
+        sqlx_oldapi::query(self.guild_departure_queries().save)
+            .bind(to_db_snowflake(departure.guild_id)?)
+            .bind(departure.left_at.timestamp())
+            .execute(&mut **trans)
+            .await
+            .with_context(|| {
+                format!(
+                    "Error saving the departure from guild {}",
+                    departure.guild_id
+                )
+            })?;
+
+        Ok(())
+    }
+
+    /// Delete the departure from a guild.
+    pub async fn delete_guild_departure(&self, guild_id: u64) -> Result<()> {
+        event!(Level::TRACE, guild_id, "Deleting guild departure");
+
+        sqlx_oldapi::query(self.guild_departure_queries().delete)
+            .bind(to_db_snowflake(guild_id)?)
+            .execute(self.pool)
+            .await
+            .with_context(|| format!("Error deleting the departure from guild {}", guild_id))?;
+
+        Ok(())
+    }
+
+    /// The departures before the given moment, the oldest first.
+    pub async fn guild_departures_before(
+        &self,
+        moment: &DateTime<Utc>,
+    ) -> Result<Vec<GuildDeparture>> {
+        event!(
+            Level::TRACE,
+            "Retrieving guild departures before {}",
+            &moment
+        );
+
+        sqlx_oldapi::query_as::<Any, GuildDepartureEntity>(
+            self.guild_departure_queries().list_before,
+        )
+        .bind(moment.timestamp())
+        .fetch_all(self.pool)
+        .await
+        .context("Error listing guild departures")?
+        .into_iter()
+        .map(GuildDeparture::try_from)
+        .collect()
+    }
+
+    /// Every departure, the oldest first.
+    pub async fn all_guild_departures(&self) -> Result<Vec<GuildDeparture>> {
+        self.guild_departures_before(&DateTime::<Utc>::MAX_UTC)
+            .await
+    }
+}
+
+/// Record that the bot was removed from a guild and mark the reminders of the guild undeliverable.
+///
+/// Returns the amount of reminders that were marked. The change to the reminders is published on `events`, when given.
+pub async fn leave_guild(
+    pool: &AnyPool,
+    guild_id: u64,
+    left_at: &DateTime<Utc>,
+    events: Option<&EventBus>,
+) -> Result<u64> {
+    GuildDeparture::repository(pool)
+        .save_guild_departure(&GuildDeparture {
+            guild_id,
+            left_at: *left_at,
+        })
+        .await?;
+
+    Reminder::repository(pool)
+        .with_events(events)
+        .mark_undeliverable(
+            &ReminderFilter {
+                server: Some(guild_id),
+                ..Default::default()
+            },
+            left_at,
+        )
+        .await
+}
+
+/// Forget the departure from a guild the bot was added to again, before its data was purged.
+///
+/// The reminders that were marked undeliverable when the bot left are delivered again. Returns the departure, `None`
+/// when the bot did not leave the guild.
+pub async fn rejoin_guild(
+    pool: &AnyPool,
+    guild_id: u64,
+    events: Option<&EventBus>,
+) -> Result<Option<GuildDeparture>> {
+    let repo = GuildDeparture::repository(pool);
+    let Some(departure) = repo.get_guild_departure(guild_id).await? else {
+        return Ok(None);
+    };
+
+    // Reminders of channels that were deleted before the bot left stay undeliverable
+    Reminder::repository(pool)
+        .with_events(events)
+        .mark_deliverable(
+            &ReminderFilter {
+                server: Some(guild_id),
+                ..Default::default()
+            },
+            &departure.left_at,
+        )
+        .await?;
+    repo.delete_guild_departure(guild_id).await?;
+
+    Ok(Some(departure))
+}
+
+/// Delete everything that is stored for a guild: its reminders, its settings and their KV keys.
+///
+/// The departure is deleted last, so a purge that fails halfway is done again the next time. Returns the amount of
+/// deleted reminders.
+pub async fn purge_guild(
+    pool: &AnyPool,
+    kv_client: &KVClient,
+    guild_id: u64,
+    events: Option<&EventBus>,
+) -> Result<u64> {
+    let reminders = Reminder::repository(pool)
+        .with_events(events)
+        .delete_reminders_matching(&ReminderFilter {
+            server: Some(guild_id),
+            ..Default::default()
+        })
+        .await?;
+
+    GuildSettings::repository(pool)
+        .with_events(events)
+        .delete_guild_settings(guild_id)
+        .await?;
+    kv_client
+        .delete(&GuildSettings::new(guild_id))
+        .await
+        .with_context(|| format!("Error deleting the cached settings of guild {}", guild_id))?;
+    kv_client
+        .delete(&GuildTimezone {
+            guild_id,
+            ..Default::default()
+        })
+        .await
+        .with_context(|| format!("Error deleting the timezone key of guild {}", guild_id))?;
+
+    GuildDeparture::repository(pool)
+        .delete_guild_departure(guild_id)
+        .await?;
+
+    Ok(reminders)
+}
+
+/// Because a lot of our types are not supported by databases
+#[derive(Debug, FromRow)]
+struct GuildDepartureEntity {
+    pub guild_id: i64,
+    pub left_at: i64,
+}
+
+impl TryFrom<GuildDepartureEntity> for GuildDeparture {
+    type Error = anyhow::Error;
+
+    fn try_from(value: GuildDepartureEntity) -> Result<Self> {
+        let guild_id = from_db_snowflake(value.guild_id)?;
+        let left_at = DateTime::from_timestamp(value.left_at, 0).ok_or_else(|| {
+            anyhow!(
+                "Invalid departure time {} for guild {guild_id}",
+                value.left_at
+            )
+        })?;
+
+        Ok(Self { guild_id, left_at })
+    }
+}
@@ -0,0 +1,17 @@
+pub(super) const GET_QUERY: &str = "SELECT * FROM public.guild_departures WHERE guild_id = $1";
+
+pub(super) const SAVE_QUERY: &str = r#"INSERT INTO public.guild_departures (guild_id, left_at)
+VALUES ($1, $2)
+ON CONFLICT (guild_id) DO NOTHING;"#;
+
+pub(super) const DELETE_QUERY: &str = "DELETE FROM public.guild_departures WHERE guild_id = $1";
+
+pub(super) const LIST_BEFORE_QUERY: &str =
+    "SELECT * FROM public.guild_departures WHERE left_at < $1 ORDER BY left_at, guild_id";
+
+pub(crate) const QUERIES: super::GuildDepartureQueries = super::GuildDepartureQueries {
+    get: GET_QUERY,
+    save: SAVE_QUERY,
+    delete: DELETE_QUERY,
+    list_before: LIST_BEFORE_QUERY,
+};
@@ -0,0 +1,17 @@
+pub(super) const GET_QUERY: &str = "SELECT * FROM guild_departures WHERE guild_id = ?";
+
+pub(super) const SAVE_QUERY: &str = r#"INSERT INTO guild_departures (guild_id, left_at)
+VALUES (?, ?)
+ON CONFLICT (guild_id) DO NOTHING;"#;
+
+pub(super) const DELETE_QUERY: &str = "DELETE FROM guild_departures WHERE guild_id = ?";
+
+pub(super) const LIST_BEFORE_QUERY: &str =
+    "SELECT * FROM guild_departures WHERE left_at < ? ORDER BY left_at, guild_id";
+
+pub(crate) const QUERIES: super::GuildDepartureQueries = super::GuildDepartureQueries {
+    get: GET_QUERY,
+    save: SAVE_QUERY,
+    delete: DELETE_QUERY,
+    list_before: LIST_BEFORE_QUERY,
+};
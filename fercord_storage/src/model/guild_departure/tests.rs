@@ -0,0 +1,208 @@
+//! Conformance suite for the `GuildDepartureRepo`, see [`conformance_suite`].
+
+use anyhow::Result;
+use chrono::{Duration, SubsecRound};
+use chrono_tz::Tz;
+use tokio_stream::StreamExt;
+
+use super::*;
+use crate::db::Repository;
+use crate::testing::{conformance_suite, unique_snowflake};
+
+conformance_suite!(
+    saved_departures_can_be_retrieved,
+    the_first_departure_is_kept,
+    departures_before_only_returns_older_departures,
+    leaving_marks_reminders_undeliverable,
+    rejoining_restores_reminders,
+    purging_deletes_everything_of_the_guild,
+);
+
+fn reminder(server: u64, channel: u64) -> Reminder {
+    Reminder {
+        id: 0,
+        who: 1,
+        when: (Utc::now() + Duration::hours(1)).trunc_subsecs(0),
+        what: "departure".into(),
+        server,
+        channel,
+        what_encrypted: false,
+        undeliverable_since: None,
+    }
+}
+
+/// Whether the reminder is streamed when it is due.
+async fn is_deliverable(pool: &AnyPool, reminder: &Reminder) -> Result<bool> {
+    // Other cases leave reminders behind that can not be read
+    let due: Vec<Reminder> = Reminder::repository(pool)
+        .stream_reminders_before(&(reminder.when + Duration::seconds(1)))
+        .filter_map(|r| r.ok())
+        .collect()
+        .await;
+
+    Ok(due.iter().any(|r| r.id == reminder.id))
+}
+
+async fn saved_departures_can_be_retrieved(pool: &AnyPool) -> Result<()> {
+    let repo = GuildDeparture::repository(pool);
+    let departure = GuildDeparture {
+        guild_id: unique_snowflake(),
+        left_at: Utc::now().trunc_subsecs(0),
+    };
+
+    assert_eq!(None, repo.get_guild_departure(departure.guild_id).await?);
+    repo.save_guild_departure(&departure).await?;
+
+    assert_eq!(
+        Some(departure.clone()),
+        repo.get_guild_departure(departure.guild_id).await?
+    );
+
+    repo.delete_guild_departure(departure.guild_id).await?;
+    assert_eq!(None, repo.get_guild_departure(departure.guild_id).await?);
+
+    Ok(())
+}
+
+async fn the_first_departure_is_kept(pool: &AnyPool) -> Result<()> {
+    let repo = GuildDeparture::repository(pool);
+    let first = GuildDeparture {
+        guild_id: unique_snowflake(),
+        left_at: Utc::now().trunc_subsecs(0) - Duration::days(1),
+    };
+    repo.save_guild_departure(&first).await?;
+
+    repo.save_guild_departure(&GuildDeparture {
+        left_at: Utc::now(),
+        ..first.clone()
+    })
+    .await?;
+
+    assert_eq!(
+        Some(first.clone()),
+        repo.get_guild_departure(first.guild_id).await?
+    );
+
+    Ok(())
+}
+
+async fn departures_before_only_returns_older_departures(pool: &AnyPool) -> Result<()> {
+    let repo = GuildDeparture::repository(pool);
+    // Well before the departures of the other cases
+    let cutoff =
+        Utc::now().trunc_subsecs(0) - Duration::days(3650 + (unique_snowflake() % 1000) as i64);
+    let old = GuildDeparture {
+        guild_id: unique_snowflake(),
+        left_at: cutoff - Duration::hours(1),
+    };
+    let recent = GuildDeparture {
+        guild_id: unique_snowflake(),
+        left_at: cutoff + Duration::hours(1),
+    };
+    repo.save_guild_departure(&old).await?;
+    repo.save_guild_departure(&recent).await?;
+
+    let before = repo.guild_departures_before(&cutoff).await?;
+
+    assert!(before.contains(&old));
+    assert!(!before.contains(&recent));
+
+    Ok(())
+}
+
+async fn leaving_marks_reminders_undeliverable(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let guild_id = unique_snowflake();
+    let mut left_behind = reminder(guild_id, unique_snowflake());
+    left_behind.id = repo.insert(&left_behind).await?;
+    let mut elsewhere = reminder(unique_snowflake(), unique_snowflake());
+    elsewhere.id = repo.insert(&elsewhere).await?;
+
+    assert_eq!(1, leave_guild(pool, guild_id, &Utc::now(), None).await?);
+
+    assert!(!is_deliverable(pool, &left_behind).await?);
+    assert!(is_deliverable(pool, &elsewhere).await?);
+    // They are still there until the guild is purged
+    assert!(repo.get(left_behind.id).await?.is_some());
+    assert!(GuildDeparture::repository(pool)
+        .get_guild_departure(guild_id)
+        .await?
+        .is_some());
+
+    Ok(())
+}
+
+async fn rejoining_restores_reminders(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let guild_id = unique_snowflake();
+    let mut deleted_channel = reminder(guild_id, unique_snowflake());
+    deleted_channel.id = repo.insert(&deleted_channel).await?;
+    let mut kept_channel = reminder(guild_id, unique_snowflake());
+    kept_channel.id = repo.insert(&kept_channel).await?;
+    repo.mark_undeliverable(
+        &ReminderFilter {
+            channel: Some(deleted_channel.channel),
+            ..Default::default()
+        },
+        &(Utc::now() - Duration::hours(1)),
+    )
+    .await?;
+    leave_guild(pool, guild_id, &Utc::now(), None).await?;
+
+    let departure = rejoin_guild(pool, guild_id, None).await?;
+
+    assert_eq!(Some(guild_id), departure.map(|d| d.guild_id));
+    assert!(is_deliverable(pool, &kept_channel).await?);
+    assert!(!is_deliverable(pool, &deleted_channel).await?);
+    assert_eq!(None, rejoin_guild(pool, guild_id, None).await?);
+
+    Ok(())
+}
+
+async fn purging_deletes_everything_of_the_guild(pool: &AnyPool) -> Result<()> {
+    let kv_client = KVClient::in_memory();
+    let repo = Reminder::repository(pool);
+    let guild_id = unique_snowflake();
+    let left_behind = repo.insert(&reminder(guild_id, unique_snowflake())).await?;
+    let elsewhere = repo
+        .insert(&reminder(unique_snowflake(), unique_snowflake()))
+        .await?;
+    let settings = GuildSettings {
+        guild_id,
+        timezone: Some(Tz::Europe__Brussels),
+    };
+    GuildSettings::repository(pool)
+        .save_guild_settings(&settings)
+        .await?;
+    kv_client.save_json(settings.clone()).await?;
+    let old_timezone = GuildTimezone {
+        guild_id,
+        timezone: "Europe/Brussels".into(),
+    };
+    kv_client.save_json(old_timezone).await?;
+    leave_guild(pool, guild_id, &Utc::now(), None).await?;
+
+    assert_eq!(1, purge_guild(pool, &kv_client, guild_id, None).await?);
+
+    assert_eq!(None, repo.get(left_behind).await?);
+    assert!(repo.get(elsewhere).await?.is_some());
+    assert_eq!(
+        None,
+        GuildSettings::repository(pool)
+            .get_guild_settings(guild_id)
+            .await?
+    );
+    assert_eq!(None, kv_client.get_json(&settings).await?);
+    assert!(kv_client
+        .keys(&format!("guild_timezone_{guild_id}"))
+        .await?
+        .is_empty());
+    assert_eq!(
+        None,
+        GuildDeparture::repository(pool)
+            .get_guild_departure(guild_id)
+            .await?
+    );
+
+    Ok(())
+}
@@ -0,0 +1,543 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx_oldapi::{Any, AnyPool, FromRow, Transaction};
+use tokio_stream::StreamExt;
+use tracing::{event, Level};
+
+use crate::cache::Cache;
+use crate::db::{from_db_snowflake, to_db_snowflake, Backend, Repo};
+use crate::events::{EventBus, EventStream, StorageEvent};
+use crate::kv::{Identifiable, KVClient, KVIdentity};
+use crate::model::guild_timezone::GuildTimezone;
+
+mod sqlite;
+
+mod postgres;
+
+#[cfg(test)]
+mod tests;
+
+/// Settings for a single guild.
+///
+/// The database is the source of truth, the KV store only caches them. Use [`get_guild_settings`] and
+/// [`save_guild_settings`] to keep both in sync.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct GuildSettings {
+    pub guild_id: u64,
+    /// The timezone of the guild, stored by its IANA name.
+    pub timezone: Option<Tz>,
+}
+
+impl GuildSettings {
+    /// Empty settings for the given guild.
+    pub fn new(guild_id: u64) -> Self {
+        Self {
+            guild_id,
+            ..Default::default()
+        }
+    }
+
+    /// Create a `GuildSettings` repository that connects to the database with the borrowed pool.
+    pub fn repository(pool: &AnyPool) -> GuildSettingsRepo<'_> {
+        Repo {
+            pool,
+            backend: Backend::of(pool),
+            cipher: None,
+            events: None,
+        }
+    }
+}
+
+impl Identifiable for GuildSettings {
+    fn kv_key(&self) -> KVIdentity {
+        format!("guild_settings_{}", &self.guild_id)
+    }
+}
+
+pub type GuildSettingsRepo<'r> = Repo<'r>;
+
+/// The SQL of every guild settings query, for a single backend.
+pub(crate) struct GuildSettingsQueries {
+    pub get: &'static str,
+    pub save: &'static str,
+    pub delete: &'static str,
+    pub list: &'static str,
+    pub list_timezones: &'static str,
+    pub clear_timezone: &'static str,
+}
+
+impl<'r> GuildSettingsRepo<'r> {
+    fn guild_settings_queries(&self) -> &'static GuildSettingsQueries {
+        match self.backend {
+            Backend::Sqlite => &sqlite::QUERIES,
+            Backend::Postgres => &postgres::QUERIES,
+        }
+    }
+
+    /// Get the settings of a guild, `None` if nothing was saved for it yet.
+    pub async fn get_guild_settings(&self, guild_id: u64) -> Result<Option<GuildSettings>> {
+        event!(Level::TRACE, guild_id, "Retrieving guild settings");
+
+        let entity =
+            sqlx_oldapi::query_as::<Any, GuildSettingsEntity>(self.guild_settings_queries().get)
+                .bind(to_db_snowflake(guild_id)?)
+                .fetch_optional(self.pool)
+                .await
+                .with_context(|| format!("Error getting the settings of guild {}", guild_id))?;
+
+        entity.map(GuildSettings::try_from).transpose()
+    }
+
+    /// Insert or replace the settings of a guild.
+    pub async fn save_guild_settings(&self, settings: &GuildSettings) -> Result<()> {
+        event!(Level::TRACE, ?settings, "Saving guild settings");
+
+        sqlx_oldapi::query(self.guild_settings_queries().save)
+            .bind(to_db_snowflake(settings.guild_id)?)
+            .bind(settings.timezone.map(|timezone| timezone.name()))
+            .execute(self.pool)
+            .await
+            .with_context(|| format!("Error saving the settings of guild {}", settings.guild_id))?;
+
+        self.publish(StorageEvent::GuildSettingsSaved {
+            guild_id: settings.guild_id,
+        });
+
+        Ok(())
+    }
+
+    /// Insert or replace the settings of a guild as part of `trans`. Nothing is published, the caller announces the change.
+    pub(crate) async fn save_guild_settings_in(
+        &self,
+        trans: &mut Transaction<'_, Any>,
+        settings: &GuildSettings,
+    ) -> Result<()> {
+        sqlx_oldapi::query(self.guild_settings_queries().save)
+            .bind(to_db_snowflake(settings.guild_id)?)
+            .bind(settings.timezone.map(|timezone| timezone.name()))
+            .execute(&mut **trans)
+            .await
+            .with_context(|| format!("Error saving the settings of guild {}", settings.guild_id))?;
+
+        Ok(())
+    }
+
+    /// The settings of every guild, ordered by guild id.
+    pub async fn all_guild_settings(&self) -> Result<Vec<GuildSettings>> {
+        event!(Level::TRACE, "Retrieving the settings of all guilds");
+
+        sqlx_oldapi::query_as::<Any, GuildSettingsEntity>(self.guild_settings_queries().list)
+            .fetch_all(self.pool)
+            .await
+            .context("Error listing guild settings")?
+            .into_iter()
+            .map(GuildSettings::try_from)
+            .collect()
+    }
+
+    /// Clear the timezones that are not valid IANA timezone names and return the guilds they belonged to.
+    ///
+    /// Timezones used to be saved without validation. Settings with such a timezone can not be read until it is
+    /// cleared.
+    pub async fn clear_invalid_timezones(&self) -> Result<Vec<u64>> {
+        event!(Level::TRACE, "Clearing invalid guild timezones");
+        let queries = self.guild_settings_queries();
+
+        let mut trans = self.pool.begin().await?;
+        let entities = sqlx_oldapi::query_as::<Any, GuildSettingsEntity>(queries.list_timezones)
+            .fetch_all(&mut *trans)
+            .await
+            .context("Error listing guild timezones")?;
+
+        let mut cleared = Vec::new();
+        for entity in entities {
+            let Some(timezone) = &entity.timezone else {
+                continue;
+            };
+            if timezone.parse::<Tz>().is_ok() {
+                continue;
+            }
+
+            event!(
+                Level::WARN,
+                guild_id = entity.guild_id,
+                %timezone,
+                "Clearing invalid guild timezone"
+            );
+            sqlx_oldapi::query(queries.clear_timezone)
+                .bind(entity.guild_id)
+                .execute(&mut *trans)
+                .await
+                .with_context(|| {
+                    format!("Error clearing the timezone of guild {}", entity.guild_id)
+                })?;
+            cleared.push(from_db_snowflake(entity.guild_id)?);
+        }
+
+        trans.commit().await?;
+
+        for guild_id in &cleared {
+            self.publish(StorageEvent::GuildSettingsSaved {
+                guild_id: *guild_id,
+            });
+        }
+
+        Ok(cleared)
+    }
+
+    /// Delete the settings of a guild.
+    pub async fn delete_guild_settings(&self, guild_id: u64) -> Result<()> {
+        event!(Level::TRACE, guild_id, "Deleting guild settings");
+
+        sqlx_oldapi::query(self.guild_settings_queries().delete)
+            .bind(to_db_snowflake(guild_id)?)
+            .execute(self.pool)
+            .await
+            .with_context(|| format!("Error deleting the settings of guild {}", guild_id))?;
+
+        self.publish(StorageEvent::GuildSettingsDeleted { guild_id });
+
+        Ok(())
+    }
+}
+
+/// Get the settings of a guild, reading through the KV store cache.
+///
+/// The KV store is only a cache: when it can not be reached the settings are read from the database.
+pub async fn get_guild_settings(
+    pool: &AnyPool,
+    kv_client: &KVClient,
+    guild_id: u64,
+) -> Result<Option<GuildSettings>> {
+    let key = GuildSettings::new(guild_id);
+
+    match kv_client.get_json(&key).await {
+        Ok(Some(settings)) => return Ok(Some(settings)),
+        Ok(None) => {}
+        Err(e) => event!(
+            Level::WARN,
+            ?e,
+            guild_id,
+            "Error reading cached guild settings"
+        ),
+    }
+
+    let settings = GuildSettings::repository(pool)
+        .get_guild_settings(guild_id)
+        .await?;
+
+    if let Some(settings) = &settings {
+        if let Err(e) = kv_client.save_json(settings.clone()).await {
+            event!(Level::WARN, ?e, guild_id, "Error caching guild settings");
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Save the settings of a guild to the database and refresh the KV store cache.
+///
+/// The change is published on `events`, when given.
+pub async fn save_guild_settings(
+    pool: &AnyPool,
+    kv_client: &KVClient,
+    settings: &GuildSettings,
+    events: Option<&EventBus>,
+) -> Result<()> {
+    GuildSettings::repository(pool)
+        .with_events(events)
+        .save_guild_settings(settings)
+        .await?;
+
+    // A stale cache entry would hide the new settings, so we get rid of it if it can not be replaced.
+    if let Err(e) = kv_client.save_json(settings.clone()).await {
+        event!(
+            Level::WARN,
+            ?e,
+            guild_id = settings.guild_id,
+            "Error caching guild settings"
+        );
+
+        kv_client
+            .delete(settings)
+            .await
+            .context("The settings were saved, but the cached settings could not be updated")?;
+    }
+
+    Ok(())
+}
+
+/// The amount of guilds [`GuildSettingsCache::default`] keeps settings for.
+pub const DEFAULT_SETTINGS_CACHE_CAPACITY: usize = 1024;
+/// How long [`GuildSettingsCache::default`] keeps settings.
+pub const DEFAULT_SETTINGS_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+/// How often [`GuildSettingsCache::default`] checks if another process changed settings.
+pub const DEFAULT_SETTINGS_VERSION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Changes every time settings are saved, so processes that share a KV store know their cached settings are stale.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct SettingsVersion {
+    version: String,
+}
+
+impl SettingsVersion {
+    fn new() -> Self {
+        Self {
+            version: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl Identifiable for SettingsVersion {
+    fn kv_key(&self) -> KVIdentity {
+        "guild_settings_version".into()
+    }
+}
+
+/// An in-process cache in front of [`get_guild_settings`] and [`save_guild_settings`].
+///
+/// Guilds without settings are cached as well. Saving settings changes a version key in the KV store. Every cache
+/// checks that key at most once per version check interval and drops everything it has when it changed, so a change
+/// made by another process shows up after that interval at the latest.
+///
+/// Lookups that fail are not cached and return the error, so a failure can be told apart from a guild without
+/// settings.
+#[derive(Debug)]
+pub struct GuildSettingsCache {
+    settings: Cache<u64, Option<GuildSettings>>,
+    version: Mutex<VersionCheck>,
+    version_check_interval: Duration,
+}
+
+#[derive(Debug, Default)]
+struct VersionCheck {
+    /// The version the cached settings belong to.
+    seen: Option<SettingsVersion>,
+    checked_at: Option<Instant>,
+}
+
+impl Default for GuildSettingsCache {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_SETTINGS_CACHE_CAPACITY,
+            DEFAULT_SETTINGS_CACHE_TTL,
+            DEFAULT_SETTINGS_VERSION_CHECK_INTERVAL,
+        )
+    }
+}
+
+impl GuildSettingsCache {
+    pub fn new(capacity: usize, ttl: Duration, version_check_interval: Duration) -> Self {
+        Self {
+            settings: Cache::new(capacity, ttl),
+            version: Mutex::new(VersionCheck::default()),
+            version_check_interval,
+        }
+    }
+
+    fn version(&self) -> std::sync::MutexGuard<'_, VersionCheck> {
+        self.version
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Get the settings of a guild, `None` if nothing was saved for it yet.
+    pub async fn get(
+        &self,
+        pool: &AnyPool,
+        kv_client: &KVClient,
+        guild_id: u64,
+    ) -> Result<Option<GuildSettings>> {
+        if self.is_current(kv_client).await {
+            if let Some(settings) = self.settings.get(&guild_id) {
+                event!(Level::TRACE, guild_id, "Found cached guild settings");
+                return Ok(settings);
+            }
+        }
+
+        let settings = get_guild_settings(pool, kv_client, guild_id).await?;
+        self.settings.insert(guild_id, settings.clone());
+
+        Ok(settings)
+    }
+
+    /// Save the settings of a guild and let the caches of other processes know.
+    pub async fn save(
+        &self,
+        pool: &AnyPool,
+        kv_client: &KVClient,
+        settings: &GuildSettings,
+        events: Option<&EventBus>,
+    ) -> Result<()> {
+        save_guild_settings(pool, kv_client, settings, events).await?;
+        self.settings.invalidate(&settings.guild_id);
+
+        // Our own cache gets cleared by this as well, adopting the new version could hide a change made elsewhere
+        if let Err(e) = kv_client.save_json(SettingsVersion::new()).await {
+            event!(
+                Level::WARN,
+                ?e,
+                guild_id = settings.guild_id,
+                "Error announcing new guild settings, other processes keep the old ones until their cache expires"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Check the version key when the interval passed and drop the cached settings when it changed.
+    ///
+    /// Returns `false` when the version key could not be read, the cached settings can not be trusted then.
+    async fn is_current(&self, kv_client: &KVClient) -> bool {
+        let now = Instant::now();
+        {
+            let check = self.version();
+            if check.checked_at.is_some_and(|checked_at| {
+                now.duration_since(checked_at) < self.version_check_interval
+            }) {
+                return true;
+            }
+        }
+
+        let latest = match kv_client.get_json(&SettingsVersion::new()).await {
+            Ok(latest) => latest,
+            Err(e) => {
+                event!(Level::WARN, ?e, "Error checking the guild settings version");
+                return false;
+            }
+        };
+
+        let mut check = self.version();
+        if check.seen != latest {
+            event!(
+                Level::DEBUG,
+                "Guild settings changed in another process, clearing the cache"
+            );
+            self.settings.clear();
+            check.seen = latest;
+        }
+        check.checked_at = Some(now);
+
+        true
+    }
+
+    /// Drop the cached settings of every guild that `events` reports as changed, until the stream ends.
+    ///
+    /// Changes made by other processes show up right away then, instead of after the version check interval. When
+    /// events were missed, all cached settings are dropped.
+    pub async fn follow(&self, mut events: EventStream) {
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(
+                    StorageEvent::GuildSettingsSaved { guild_id }
+                    | StorageEvent::GuildSettingsDeleted { guild_id },
+                ) => {
+                    event!(
+                        Level::TRACE,
+                        guild_id,
+                        "Guild settings changed, dropping them from the cache"
+                    );
+                    self.settings.invalidate(&guild_id);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    event!(
+                        Level::WARN,
+                        ?e,
+                        "Missed storage events, clearing the guild settings cache"
+                    );
+                    self.settings.clear();
+                }
+            }
+        }
+    }
+}
+
+/// Move guild timezones from the old `guild_timezone_{id}` KV keys to the guild settings in the database.
+///
+/// A timezone that was already saved in the database is kept. The KV keys are removed once they are migrated, so
+/// this only does work the first time it runs. Returns the amount of migrated keys.
+pub async fn migrate_guild_timezones(pool: &AnyPool, kv_client: &KVClient) -> Result<usize> {
+    let repo = GuildSettings::repository(pool);
+    let keys = kv_client
+        .keys("guild_timezone_*")
+        .await
+        .context("Error looking for guild timezones in the KV store")?;
+
+    let mut migrated = 0;
+    for key in keys {
+        let Some(guild_id) = key
+            .strip_prefix("guild_timezone_")
+            .and_then(|id| id.parse::<u64>().ok())
+        else {
+            event!(Level::WARN, %key, "Skipping guild timezone key without a valid guild id");
+            continue;
+        };
+
+        let old_key = GuildTimezone {
+            guild_id,
+            timezone: String::new(),
+        };
+        let Some(guild_timezone) = kv_client.get_json(&old_key).await? else {
+            continue;
+        };
+
+        // There is nothing to salvage from a timezone that never was valid
+        let Ok(timezone) = Tz::try_from(guild_timezone) else {
+            event!(Level::WARN, guild_id, "Dropping invalid guild timezone");
+            kv_client.delete(&old_key).await?;
+            continue;
+        };
+
+        let mut settings = repo
+            .get_guild_settings(guild_id)
+            .await?
+            .unwrap_or_else(|| GuildSettings::new(guild_id));
+        if settings.timezone.is_none() {
+            settings.timezone = Some(timezone);
+            repo.save_guild_settings(&settings).await?;
+        }
+
+        kv_client.delete(&old_key).await?;
+        migrated += 1;
+    }
+
+    if migrated > 0 {
+        event!(
+            Level::INFO,
+            migrated,
+            "Migrated guild timezones from the KV store"
+        );
+    }
+
+    Ok(migrated)
+}
+
+/// Because a lot of our types are not supported by databases
+#[derive(Debug, FromRow)]
+struct GuildSettingsEntity {
+    pub guild_id: i64,
+    pub timezone: Option<String>,
+}
+
+impl TryFrom<GuildSettingsEntity> for GuildSettings {
+    type Error = anyhow::Error;
+
+    fn try_from(value: GuildSettingsEntity) -> Result<Self> {
+        let guild_id = from_db_snowflake(value.guild_id)?;
+        let timezone = value
+            .timezone
+            .map(|timezone| {
+                timezone
+                    .parse::<Tz>()
+                    .map_err(|e| anyhow!("Invalid timezone {timezone} for guild {guild_id}: {e}"))
+            })
+            .transpose()?;
+
+        Ok(Self { guild_id, timezone })
+    }
+}
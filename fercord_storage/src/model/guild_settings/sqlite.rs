@@ -0,0 +1,24 @@
+pub(super) const GET_QUERY: &str = "SELECT * FROM guild_settings WHERE guild_id = ?";
+
+pub(super) const SAVE_QUERY: &str = r#"INSERT INTO guild_settings (guild_id, timezone)
+VALUES (?, ?)
+ON CONFLICT (guild_id) DO UPDATE SET timezone = excluded.timezone;"#;
+
+pub(super) const DELETE_QUERY: &str = "DELETE FROM guild_settings WHERE guild_id = ?";
+
+pub(super) const LIST_QUERY: &str = "SELECT * FROM guild_settings ORDER BY guild_id";
+
+pub(super) const LIST_TIMEZONES_QUERY: &str =
+    "SELECT * FROM guild_settings WHERE timezone IS NOT NULL";
+
+pub(super) const CLEAR_TIMEZONE_QUERY: &str =
+    "UPDATE guild_settings SET timezone = NULL WHERE guild_id = ?";
+
+pub(crate) const QUERIES: super::GuildSettingsQueries = super::GuildSettingsQueries {
+    get: GET_QUERY,
+    save: SAVE_QUERY,
+    delete: DELETE_QUERY,
+    list: LIST_QUERY,
+    list_timezones: LIST_TIMEZONES_QUERY,
+    clear_timezone: CLEAR_TIMEZONE_QUERY,
+};
@@ -0,0 +1,233 @@
+//! Conformance suite for the `GuildSettingsRepo`, see [`conformance_suite`].
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use tokio_stream::StreamExt;
+
+use super::*;
+use crate::events::StorageEvent;
+use crate::testing::{conformance_suite, unique_snowflake};
+
+conformance_suite!(
+    unknown_guild_has_no_settings,
+    saved_settings_can_be_retrieved,
+    saving_replaces_settings,
+    deleted_settings_are_gone,
+    invalid_timezones_are_cleared,
+    cache_keeps_settings_until_they_are_saved_elsewhere,
+    cache_reports_failed_lookups,
+    changes_are_published,
+    cache_follows_published_changes,
+);
+
+async fn unknown_guild_has_no_settings(pool: &AnyPool) -> Result<()> {
+    let repo = GuildSettings::repository(pool);
+
+    assert_eq!(None, repo.get_guild_settings(unique_snowflake()).await?);
+
+    Ok(())
+}
+
+async fn saved_settings_can_be_retrieved(pool: &AnyPool) -> Result<()> {
+    let repo = GuildSettings::repository(pool);
+    let settings = GuildSettings {
+        guild_id: unique_snowflake(),
+        timezone: Some(Tz::Europe__Brussels),
+    };
+
+    repo.save_guild_settings(&settings).await?;
+
+    assert_eq!(
+        Some(settings.clone()),
+        repo.get_guild_settings(settings.guild_id).await?
+    );
+
+    Ok(())
+}
+
+async fn saving_replaces_settings(pool: &AnyPool) -> Result<()> {
+    let repo = GuildSettings::repository(pool);
+    let guild_id = unique_snowflake();
+    repo.save_guild_settings(&GuildSettings {
+        guild_id,
+        timezone: Some(Tz::Europe__Brussels),
+    })
+    .await?;
+
+    let cleared = GuildSettings::new(guild_id);
+    repo.save_guild_settings(&cleared).await?;
+
+    assert_eq!(Some(cleared), repo.get_guild_settings(guild_id).await?);
+
+    Ok(())
+}
+
+async fn deleted_settings_are_gone(pool: &AnyPool) -> Result<()> {
+    let repo = GuildSettings::repository(pool);
+    let settings = GuildSettings {
+        guild_id: unique_snowflake(),
+        timezone: Some(Tz::Asia__Tokyo),
+    };
+    repo.save_guild_settings(&settings).await?;
+
+    repo.delete_guild_settings(settings.guild_id).await?;
+
+    assert_eq!(None, repo.get_guild_settings(settings.guild_id).await?);
+
+    Ok(())
+}
+
+async fn invalid_timezones_are_cleared(pool: &AnyPool) -> Result<()> {
+    let repo = GuildSettings::repository(pool);
+    let valid = GuildSettings {
+        guild_id: unique_snowflake(),
+        timezone: Some(Tz::Asia__Tokyo),
+    };
+    repo.save_guild_settings(&valid).await?;
+    let invalid_guild_id = unique_snowflake();
+    sqlx_oldapi::query(repo.guild_settings_queries().save)
+        .bind(to_db_snowflake(invalid_guild_id)?)
+        .bind("Mars/Olympus_Mons")
+        .execute(pool)
+        .await?;
+    assert!(repo.get_guild_settings(invalid_guild_id).await.is_err());
+
+    let cleared = repo.clear_invalid_timezones().await?;
+
+    // Other cases share the Postgres database, so we only look for our own guilds
+    assert!(cleared.contains(&invalid_guild_id));
+    assert!(!cleared.contains(&valid.guild_id));
+    assert_eq!(
+        Some(GuildSettings::new(invalid_guild_id)),
+        repo.get_guild_settings(invalid_guild_id).await?
+    );
+    assert_eq!(
+        Some(valid.clone()),
+        repo.get_guild_settings(valid.guild_id).await?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn timezones_are_serialized_by_name() -> Result<()> {
+    let settings = GuildSettings {
+        guild_id: 1,
+        timezone: Some(Tz::Europe__Brussels),
+    };
+
+    let json = serde_json::to_string(&settings)?;
+
+    assert_eq!(r#"{"guild_id":1,"timezone":"Europe/Brussels"}"#, json);
+    assert!(
+        serde_json::from_str::<GuildSettings>(r#"{"guild_id":1,"timezone":"Nowhere"}"#).is_err()
+    );
+
+    Ok(())
+}
+
+/// A cache that checks the settings version on every lookup.
+fn eager_cache() -> GuildSettingsCache {
+    GuildSettingsCache::new(16, Duration::from_secs(60), Duration::ZERO)
+}
+
+async fn cache_keeps_settings_until_they_are_saved_elsewhere(pool: &AnyPool) -> Result<()> {
+    let kv_client = KVClient::in_memory();
+    let ours = eager_cache();
+    let theirs = eager_cache();
+    let guild_id = unique_snowflake();
+
+    assert_eq!(None, ours.get(pool, &kv_client, guild_id).await?);
+
+    // Nothing tells the cache about changes that bypass it
+    let settings = GuildSettings {
+        guild_id,
+        timezone: Some(Tz::Europe__Brussels),
+    };
+    GuildSettings::repository(pool)
+        .save_guild_settings(&settings)
+        .await?;
+    assert_eq!(None, ours.get(pool, &kv_client, guild_id).await?);
+
+    theirs.save(pool, &kv_client, &settings, None).await?;
+    assert_eq!(Some(settings), ours.get(pool, &kv_client, guild_id).await?);
+
+    Ok(())
+}
+
+async fn cache_reports_failed_lookups(pool: &AnyPool) -> Result<()> {
+    let kv_client = KVClient::in_memory();
+    let cache = eager_cache();
+    let guild_id = unique_snowflake();
+
+    pool.close().await;
+
+    assert!(cache.get(pool, &kv_client, guild_id).await.is_err());
+
+    Ok(())
+}
+
+async fn changes_are_published(pool: &AnyPool) -> Result<()> {
+    let bus = EventBus::in_process();
+    let mut events = bus.subscribe().await?;
+    let kv_client = KVClient::in_memory();
+    let guild_id = unique_snowflake();
+
+    save_guild_settings(pool, &kv_client, &GuildSettings::new(guild_id), Some(&bus)).await?;
+    GuildSettings::repository(pool)
+        .with_events(Some(&bus))
+        .delete_guild_settings(guild_id)
+        .await?;
+
+    assert_eq!(
+        Some(StorageEvent::GuildSettingsSaved { guild_id }),
+        events.next().await.transpose()?
+    );
+    assert_eq!(
+        Some(StorageEvent::GuildSettingsDeleted { guild_id }),
+        events.next().await.transpose()?
+    );
+
+    Ok(())
+}
+
+async fn cache_follows_published_changes(pool: &AnyPool) -> Result<()> {
+    let bus = EventBus::in_process();
+    let kv_client = KVClient::in_memory();
+    // Never checks the version again, only the events can tell it about changes
+    let cache = std::sync::Arc::new(GuildSettingsCache::new(
+        16,
+        Duration::from_secs(60),
+        Duration::from_secs(3600),
+    ));
+    let guild_id = unique_snowflake();
+    assert_eq!(None, cache.get(pool, &kv_client, guild_id).await?);
+
+    let events = bus.subscribe().await?;
+    let follower = tokio::spawn({
+        let cache = cache.clone();
+        async move { cache.follow(events).await }
+    });
+
+    let settings = GuildSettings {
+        guild_id,
+        timezone: Some(Tz::Europe__Brussels),
+    };
+    save_guild_settings(pool, &kv_client, &settings, Some(&bus)).await?;
+    bus.flush().await;
+
+    let followed = tokio::time::timeout(Duration::from_secs(5), async {
+        while cache.get(pool, &kv_client, guild_id).await?.as_ref() != Some(&settings) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        Ok::<_, anyhow::Error>(())
+    })
+    .await;
+    follower.abort();
+    followed??;
+
+    Ok(())
+}
@@ -5,6 +5,9 @@ use serde::{Deserialize, Serialize};
 use crate::kv::{Identifiable, KVIdentity};
 
 /// Contains the timezone set for a certain guild.
+///
+/// This is how timezones used to be stored in the KV store, they have moved to [`GuildSettings`](crate::model::GuildSettings).
+/// It is only kept around to migrate existing timezones with [`migrate_guild_timezones`](crate::model::migrate_guild_timezones).
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub struct GuildTimezone {
     pub guild_id: u64,
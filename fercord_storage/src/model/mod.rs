@@ -6,5 +6,17 @@ pub mod reminder;
 /// Store guild timezones as setting data
 pub mod guild_timezone;
 
+/// Per guild settings
+pub mod guild_settings;
+
+/// Guilds the bot was removed from
+pub mod guild_departure;
+
+/// Sessions of the web API
+pub mod session;
+
+pub use guild_departure::*;
+pub use guild_settings::*;
+pub use guild_timezone::*;
 pub use reminder::*;
-pub use guild_timezone::*;
\ No newline at end of file
+pub use session::*;
@@ -1,22 +1,28 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use chrono::{DateTime, Utc};
 use poise::async_trait;
-use sqlx_oldapi::{any::AnyRow, Any, AnyPool, FromRow, Row};
-use tracing::{event, trace, Level};
+use serde::{Deserialize, Serialize};
+use sqlx_oldapi::any::{AnyArguments, AnyRow};
+use sqlx_oldapi::query::Query;
+use sqlx_oldapi::{Any, AnyPool, FromRow, Row, Transaction};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{event, Level};
+
+use crate::db::{
+    from_db_snowflake, to_db_snowflake, Backend, ListOptions, Order, Page, Repo, Repository,
+    SqlBuilder,
+};
+use crate::encryption::FieldCipher;
+use crate::events::StorageEvent;
 
-use crate::db::{Repo, Repository};
-#[cfg(feature = "postgres")]
-use crate::model::reminder::postgres::*;
-#[cfg(feature = "sqlite")]
-use crate::model::reminder::sqlite::*;
-
-#[cfg(feature = "sqlite")]
 pub mod sqlite;
 
-#[cfg(feature = "postgres")]
 pub mod postgres;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Reminder {
     pub id: i64,
     pub who: u64,
@@ -24,195 +30,980 @@ pub struct Reminder {
     pub what: String,
     pub server: u64,
     pub channel: u64,
+    /// Whether `what` is encrypted, as it is stored. Only reminders read without a cipher can be, saving one stores
+    /// `what` as it is.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub what_encrypted: bool,
+    /// Since when the reminder can not be delivered, see [`ReminderRepo::mark_undeliverable`]. Only
+    /// [`ReminderRepo::insert_with_ids`] stores it, the other writes leave it as it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undeliverable_since: Option<DateTime<Utc>>,
 }
 
 pub type ReminderRepo<'r> = Repo<'r>;
 
+/// The maximum amount of ids bound to a single bulk delete, well below the variable limit of older sqlite versions.
+pub const DELETE_CHUNK_SIZE: usize = 500;
+
+/// Criteria for listing and counting reminders. Every criterion that is set has to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReminderFilter {
+    pub who: Option<u64>,
+    pub server: Option<u64>,
+    pub channel: Option<u64>,
+    /// Only reminders that are due at or after this moment.
+    pub due_from: Option<DateTime<Utc>>,
+    /// Only reminders that are due before this moment.
+    pub due_before: Option<DateTime<Utc>>,
+    /// Only reminders that are not marked undeliverable.
+    pub deliverable_only: bool,
+}
+
+/// A reminder that is stored, but can not be read. See [`ReminderRepo::list_readable`].
+#[derive(Debug)]
+pub struct UnreadableReminder {
+    pub id: i64,
+    pub error: anyhow::Error,
+}
+
+/// The fields reminders can be sorted on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReminderSort {
+    #[default]
+    Id,
+    /// When the reminder is due, reminders that are due at the same time are sorted by id.
+    When,
+}
+
+/// A position in a listing of reminders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReminderCursor {
+    pub id: i64,
+    pub when: DateTime<Utc>,
+}
+
+impl From<&Reminder> for ReminderCursor {
+    fn from(value: &Reminder) -> Self {
+        Self {
+            id: value.id,
+            when: value.when,
+        }
+    }
+}
+
+pub type ReminderListOptions = ListOptions<ReminderFilter, ReminderSort, ReminderCursor>;
+
+/// The SQL of every reminder query, for a single backend.
+pub(crate) struct ReminderQueries {
+    pub reminders_between: &'static str,
+    pub insert: &'static str,
+    /// Insert a reminder with the id it already has.
+    pub insert_with_id: &'static str,
+    /// Run after [`ReminderQueries::insert_with_id`], when the backend does not keep its ids in order by itself.
+    pub sync_ids: Option<&'static str>,
+    pub delete: &'static str,
+    pub get_one: &'static str,
+    pub update: &'static str,
+    pub table: &'static str,
+}
+
 impl<'r> ReminderRepo<'r> {
-    /// Get all reminders between the given moment and now.
-    pub async fn get_reminders_since(&self, moment: &DateTime<Utc>) -> Result<Vec<Reminder>> {
+    /// Encrypt `what` with the cipher when saving reminders and decrypt it when reading them.
+    ///
+    /// Without a cipher `what` is saved and read as it is stored, encrypted reminders stay encrypted. Backups and
+    /// copies work like that, so they never hold the plaintext of encrypted reminders.
+    pub fn with_cipher(mut self, cipher: Option<&'r FieldCipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Convert a reminder to what is stored.
+    fn to_entity(&self, reminder: &Reminder) -> Result<ReminderEntity> {
+        ReminderEntity::from_reminder(reminder, self.cipher)
+    }
+
+    /// Convert a stored reminder back.
+    fn to_reminder(&self, entity: ReminderEntity) -> Result<Reminder> {
+        entity.into_reminder(self.cipher)
+    }
+
+    /// The queries for the backend this repository is connected to.
+    fn queries(&self) -> &'static ReminderQueries {
+        match self.backend {
+            Backend::Sqlite => &sqlite::QUERIES,
+            Backend::Postgres => &postgres::QUERIES,
+        }
+    }
+
+    /// Add the `WHERE` clause for the filter and cursor to `sql`, returns the values that have to be bound.
+    fn push_filter(
+        &self,
+        sql: &mut SqlBuilder,
+        filter: &ReminderFilter,
+        after: Option<(&ReminderCursor, ReminderSort, Order)>,
+    ) -> Result<Vec<i64>> {
+        let mut values = Vec::new();
+        sql.push(" WHERE 1 = 1");
+
+        for (column, snowflake) in [
+            ("who", filter.who),
+            ("server", filter.server),
+            ("channel", filter.channel),
+        ] {
+            if let Some(snowflake) = snowflake {
+                let param = sql.placeholder();
+                sql.push(&format!(r#" AND "{column}" = {param}"#));
+                values.push(to_db_snowflake(snowflake)?);
+            }
+        }
+
+        for (comparison, moment) in [(">=", filter.due_from), ("<", filter.due_before)] {
+            if let Some(moment) = moment {
+                let param = sql.placeholder();
+                sql.push(&format!(r#" AND "when" {comparison} {param}"#));
+                values.push(moment.timestamp());
+            }
+        }
+
+        if filter.deliverable_only {
+            sql.push(" AND undeliverable_since IS NULL");
+        }
+
+        match after {
+            Some((cursor, ReminderSort::Id, order)) => {
+                let param = sql.placeholder();
+                sql.push(&format!(" AND id {} {param}", order.after()));
+                values.push(cursor.id);
+            }
+            Some((cursor, ReminderSort::When, order)) => {
+                let first_when = sql.placeholder();
+                let second_when = sql.placeholder();
+                let id = sql.placeholder();
+                sql.push(&format!(
+                    r#" AND ("when" {after} {first_when} OR ("when" = {second_when} AND id {after} {id}))"#,
+                    after = order.after()
+                ));
+                values.extend([cursor.when.timestamp(), cursor.when.timestamp(), cursor.id]);
+            }
+            None => {}
+        }
+
+        Ok(values)
+    }
+
+    /// Stream the reminders that are due from `from` until `until`, one row at a time.
+    fn stream_between(
+        &self,
+        from: &DateTime<Utc>,
+        until: &DateTime<Utc>,
+    ) -> impl Stream<Item = Result<Reminder>> + Send + 'r {
+        // The stream only borrows the pool and the cipher, not the repository
+        let cipher = self.cipher;
+
+        sqlx_oldapi::query(self.queries().reminders_between)
+            .bind(from.timestamp())
+            .bind(until.timestamp())
+            .fetch(self.pool)
+            .map(move |row| {
+                let row = row.with_context(|| "Error fetching reminders")?;
+                reminder_from_row(&row, cipher)
+            })
+    }
+
+    /// Stream all reminders between the given moment and now, except the undeliverable ones.
+    ///
+    /// Only the reminder that is being processed is kept in memory. A reminder that can not be read is an error that
+    /// names its id, the reminders after it can still be read from the stream.
+    pub fn stream_reminders_since(
+        &self,
+        moment: &DateTime<Utc>,
+    ) -> impl Stream<Item = Result<Reminder>> + Send + 'r {
         let now = Utc::now();
         event!(
             Level::TRACE,
-            "Getting all reminders between {} and {}",
+            "Streaming all reminders between {} and {}",
             &moment,
             &now
         );
 
-        let query = sqlx_oldapi::query(REMINDERS_BETWEEN_QUERY)
-            .bind(moment)
-            .bind(now)
-            .fetch_all(self.pool)
-            .await?;
-
-        trace!("Found {} reminders", query.len());
+        self.stream_between(moment, &now)
+    }
 
-        let reminders: Vec<Reminder> = query_to_entity(query);
+    /// Stream all reminders before the given moment, see [`ReminderRepo::stream_reminders_since`].
+    pub fn stream_reminders_before(
+        &self,
+        moment: &DateTime<Utc>,
+    ) -> impl Stream<Item = Result<Reminder>> + Send + 'r {
+        event!(Level::TRACE, "Streaming all reminders before {}", &moment);
 
-        trace!("{} records remaining after mapping", reminders.len());
+        self.stream_between(&DateTime::UNIX_EPOCH, moment)
+    }
 
-        Ok(reminders)
+    /// Get all reminders between the given moment and now.
+    ///
+    /// Fails when one of the reminders can not be read.
+    pub async fn get_reminders_since(&self, moment: &DateTime<Utc>) -> Result<Vec<Reminder>> {
+        self.stream_reminders_since(moment).collect().await
     }
 
     /// Get all reminders before the given moment
+    ///
+    /// Fails when one of the reminders can not be read.
     pub async fn get_reminders_before(&self, moment: &DateTime<Utc>) -> Result<Vec<Reminder>> {
-        event!(Level::TRACE, "Getting all reminders before {}", &moment);
-
-        let query = sqlx_oldapi::query(REMINDERS_BETWEEN_QUERY)
-            .bind(DateTime::UNIX_EPOCH)
-            .bind(moment)
-            .fetch_all(self.pool)
+        self.stream_reminders_before(moment)
+            .collect::<Result<Vec<_>>>()
             .await
-            .with_context(|| "Error fetching expired reminders")?;
-
-        Ok(query_to_entity(query))
+            .with_context(|| "Error fetching expired reminders")
     }
 
     /// Bulk delete reminders
     pub async fn delete_reminders(&self, reminders: Vec<Reminder>) -> Result<()> {
-        event!(Level::TRACE, "Deleting {} reminders", &reminders.len());
+        let ids: Vec<i64> = reminders.iter().map(|r| r.id).collect();
+
+        let deleted = self.delete_many(&ids).await?;
+        event!(Level::DEBUG, "Deleted {} reminders", deleted);
+
+        Ok(())
+    }
 
-        if reminders.is_empty() {
-            return Ok(());
+    /// Insert the reminders with the ids they already have, in a single transaction. Used to restore and copy data.
+    ///
+    /// Fails when one of the ids is taken. Returns the amount of inserted reminders.
+    pub async fn insert_with_ids(&self, reminders: &[Reminder]) -> Result<u64> {
+        event!(
+            Level::TRACE,
+            "Inserting {} reminders with their ids",
+            reminders.len()
+        );
+        let mut trans = self.pool.begin().await?;
+        self.insert_with_ids_in(&mut trans, reminders).await?;
+        trans
+            .commit()
+            .await
+            .with_context(|| "Error committing transaction")?;
+
+        if !reminders.is_empty() {
+            self.publish(StorageEvent::RemindersChanged);
         }
 
-        let reminder_ids = reminders
-            .iter()
-            .fold(String::from(""), |s, r| s + r.id.to_string().as_str() + ",");
-        let reminder_ids = reminder_ids.trim_end_matches(',');
+        Ok(reminders.len() as u64)
+    }
+
+    /// Insert the reminders with the ids they already have as part of `trans`, see [`ReminderRepo::insert_with_ids`].
+    pub(crate) async fn insert_with_ids_in(
+        &self,
+        trans: &mut Transaction<'_, Any>,
+        reminders: &[Reminder],
+    ) -> Result<()> {
+        let queries = self.queries();
+
+        for reminder in reminders {
+            let db_ent = self.to_entity(reminder)?;
+
+            sqlx_oldapi::query(queries.insert_with_id)
+                .bind(db_ent.id)
+                .bind(db_ent.who)
+                .bind(db_ent.when)
+                .bind(db_ent.what)
+                .bind(db_ent.what_encrypted)
+                .bind(db_ent.server)
+                .bind(db_ent.channel)
+                .bind(db_ent.undeliverable_since)
+                .execute(&mut **trans)
+                .await
+                .with_context(|| format!("Error inserting reminder {}", reminder.id))?;
+        }
 
-        let trans = self
+        if let Some(sync_ids) = queries.sync_ids {
+            sqlx_oldapi::query(sync_ids)
+                .execute(&mut **trans)
+                .await
+                .context("Error moving the reminder ids past the inserted reminders")?;
+        }
+
+        Ok(())
+    }
+
+    /// Encrypt every reminder that is not encrypted with the current key of the cipher yet, in batches of `batch_size`.
+    ///
+    /// Every batch is committed on its own. Returns the amount of reminders that were encrypted again.
+    pub async fn reencrypt(&self, batch_size: u32) -> Result<u64> {
+        let Some(cipher) = self.cipher else {
+            bail!("Reminders can only be encrypted again by a repository with a cipher");
+        };
+        let table = self.queries().table;
+
+        let mut select = SqlBuilder::new(
+            self.backend,
+            format!("SELECT id, what, what_encrypted FROM {table}"),
+        );
+        let after = select.placeholder();
+        select.push(&format!(
+            " WHERE id > {after} ORDER BY id LIMIT {}",
+            batch_size.max(1)
+        ));
+        let mut update = SqlBuilder::new(self.backend, format!("UPDATE {table} SET what = "));
+        let what = update.placeholder();
+        let encrypted = update.placeholder();
+        let id = update.placeholder();
+        update.push(&format!(
+            "{what}, what_encrypted = {encrypted} WHERE id = {id}"
+        ));
+
+        let mut last_id = 0;
+        let mut reencrypted = 0;
+        loop {
+            let mut trans = self
+                .pool
+                .begin()
+                .await
+                .with_context(|| "Error starting transaction")?;
+
+            let rows = sqlx_oldapi::query(select.sql())
+                .bind(last_id)
+                .fetch_all(&mut *trans)
+                .await
+                .with_context(|| "Error listing reminders")?;
+            let Some(last_row) = rows.last() else {
+                break;
+            };
+            last_id = last_row.try_get("id")?;
+
+            for row in &rows {
+                let id: i64 = row.try_get("id")?;
+                let what: String = row.try_get("what")?;
+                let encrypted: bool = row.try_get("what_encrypted")?;
+                if encrypted && cipher.is_current(&what) {
+                    continue;
+                }
+
+                let plaintext = match encrypted {
+                    true => cipher.decrypt(&what),
+                    false => Ok(what),
+                };
+                let what = plaintext
+                    .and_then(|what| cipher.encrypt(&what))
+                    .with_context(|| format!("Error encrypting reminder {id} again"))?;
+                sqlx_oldapi::query(update.sql())
+                    .bind(what)
+                    .bind(true)
+                    .bind(id)
+                    .execute(&mut *trans)
+                    .await
+                    .with_context(|| format!("Error updating reminder {id}"))?;
+                reencrypted += 1;
+            }
+
+            trans
+                .commit()
+                .await
+                .with_context(|| "Error committing transaction")?;
+            event!(
+                Level::DEBUG,
+                reencrypted,
+                last_id,
+                "Encrypted a batch of reminders again"
+            );
+        }
+
+        Ok(reencrypted)
+    }
+
+    /// Delete every reminder that was due before the given moment, without loading them first.
+    ///
+    /// Returns the amount of deleted reminders.
+    pub async fn delete_reminders_before(&self, moment: &DateTime<Utc>) -> Result<u64> {
+        event!(Level::TRACE, "Deleting all reminders before {}", &moment);
+
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!("DELETE FROM {}", self.queries().table),
+        );
+        let param = sql.placeholder();
+        sql.push(&format!(r#" WHERE "when" < {param}"#));
+
+        let mut trans = self
             .pool
             .begin()
             .await
             .with_context(|| "Error starting transaction")?;
 
-        let query = sqlx_oldapi::query(&BATCH_DELETE_QUERY.replace('?', reminder_ids))
-            .execute(self.pool)
+        let deleted = sqlx_oldapi::query(sql.sql())
+            .bind(moment.timestamp())
+            .execute(&mut *trans)
             .await
-            .with_context(|| "Error deleting reminders")?;
-
-        event!(Level::DEBUG, "Deleted {} reminders", query.rows_affected());
+            .with_context(|| "Error deleting expired reminders")?
+            .rows_affected();
 
         trans
             .commit()
             .await
-            .with_context(|| "Error committing transaction")
+            .with_context(|| "Error committing transaction")?;
+
+        event!(Level::DEBUG, "Deleted {} reminders", deleted);
+        if deleted > 0 {
+            self.publish(StorageEvent::RemindersChanged);
+        }
+
+        Ok(deleted)
+    }
+
+    /// Mark the reminders that match the filter as undeliverable since the given moment, for example because their
+    /// channel was deleted. They are not streamed as due reminders anymore.
+    ///
+    /// Reminders that were marked before keep their moment. Returns the amount of marked reminders.
+    pub async fn mark_undeliverable(
+        &self,
+        filter: &ReminderFilter,
+        since: &DateTime<Utc>,
+    ) -> Result<u64> {
+        event!(
+            Level::TRACE,
+            ?filter,
+            "Marking reminders undeliverable since {}",
+            &since
+        );
+
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!("UPDATE {} SET undeliverable_since = ", self.queries().table),
+        );
+        let param = sql.placeholder();
+        sql.push(&param);
+        let values = self.push_scoped_filter(&mut sql, filter)?;
+        sql.push(" AND undeliverable_since IS NULL");
+
+        let marked = bind_filter_values(
+            sqlx_oldapi::query(sql.sql()).bind(since.timestamp()),
+            values,
+        )
+        .execute(self.pool)
+        .await
+        .with_context(|| "Error marking reminders undeliverable")?
+        .rows_affected();
+
+        if marked > 0 {
+            self.publish(StorageEvent::RemindersChanged);
+        }
+
+        Ok(marked)
+    }
+
+    /// Make the reminders that match the filter and were marked undeliverable at or after the given moment deliverable
+    /// again. Returns the amount of reminders that can be delivered again.
+    pub async fn mark_deliverable(
+        &self,
+        filter: &ReminderFilter,
+        since: &DateTime<Utc>,
+    ) -> Result<u64> {
+        event!(
+            Level::TRACE,
+            ?filter,
+            "Marking reminders deliverable that were undeliverable since {}",
+            &since
+        );
+
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!(
+                "UPDATE {} SET undeliverable_since = NULL",
+                self.queries().table
+            ),
+        );
+        let mut values = self.push_scoped_filter(&mut sql, filter)?;
+        let param = sql.placeholder();
+        sql.push(&format!(" AND undeliverable_since >= {param}"));
+        values.push(since.timestamp());
+
+        let restored = bind_filter_values(sqlx_oldapi::query(sql.sql()), values)
+            .execute(self.pool)
+            .await
+            .with_context(|| "Error marking reminders deliverable")?
+            .rows_affected();
+
+        if restored > 0 {
+            self.publish(StorageEvent::RemindersChanged);
+        }
+
+        Ok(restored)
+    }
+
+    /// The filter of the updates and deletes that only apply to some reminders, it has to select a user, server or
+    /// channel.
+    fn push_scoped_filter(
+        &self,
+        sql: &mut SqlBuilder,
+        filter: &ReminderFilter,
+    ) -> Result<Vec<i64>> {
+        ensure!(
+            filter.who.is_some() || filter.server.is_some() || filter.channel.is_some(),
+            "Reminders are only marked per user, server or channel"
+        );
+
+        self.push_filter(sql, filter, None)
+    }
+
+    /// Delete every reminder that matches the filter, which has to select a user, server or channel.
+    ///
+    /// Returns the amount of deleted reminders.
+    pub async fn delete_reminders_matching(&self, filter: &ReminderFilter) -> Result<u64> {
+        event!(Level::TRACE, ?filter, "Deleting matching reminders");
+
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!("DELETE FROM {}", self.queries().table),
+        );
+        let values = self.push_scoped_filter(&mut sql, filter)?;
+
+        let deleted = bind_filter_values(sqlx_oldapi::query(sql.sql()), values)
+            .execute(self.pool)
+            .await
+            .with_context(|| "Error deleting reminders")?
+            .rows_affected();
+
+        event!(Level::DEBUG, "Deleted {} reminders", deleted);
+        if deleted > 0 {
+            self.publish(StorageEvent::RemindersChanged);
+        }
+
+        Ok(deleted)
+    }
+
+    /// Delete every reminder that was marked undeliverable before the given moment.
+    ///
+    /// Returns the amount of deleted reminders.
+    pub async fn delete_undeliverable_before(&self, moment: &DateTime<Utc>) -> Result<u64> {
+        event!(
+            Level::TRACE,
+            "Deleting the reminders that are undeliverable since before {}",
+            &moment
+        );
+
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!("DELETE FROM {}", self.queries().table),
+        );
+        let param = sql.placeholder();
+        sql.push(&format!(" WHERE undeliverable_since < {param}"));
+
+        let deleted = sqlx_oldapi::query(sql.sql())
+            .bind(moment.timestamp())
+            .execute(self.pool)
+            .await
+            .with_context(|| "Error deleting undeliverable reminders")?
+            .rows_affected();
+
+        event!(Level::DEBUG, "Deleted {} undeliverable reminders", deleted);
+        if deleted > 0 {
+            self.publish(StorageEvent::RemindersChanged);
+        }
+
+        Ok(deleted)
+    }
+
+    /// The rows of a page of reminders, with one extra row when there is a next page.
+    async fn list_rows(&self, options: &ReminderListOptions) -> Result<Vec<AnyRow>> {
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!("SELECT * FROM {}", self.queries().table),
+        );
+        let values = self.push_filter(
+            &mut sql,
+            &options.filter,
+            options
+                .after
+                .as_ref()
+                .map(|cursor| (cursor, options.sort, options.order)),
+        )?;
+
+        let order = options.order.sql();
+        match options.sort {
+            ReminderSort::Id => sql.push(&format!(" ORDER BY id {order}")),
+            ReminderSort::When => sql.push(&format!(r#" ORDER BY "when" {order}, id {order}"#)),
+        };
+        // One extra row tells us if there is a next page.
+        sql.push(&format!(" LIMIT {}", options.limit as u64 + 1));
+
+        bind_filter_values(sqlx_oldapi::query(sql.sql()), values)
+            .fetch_all(self.pool)
+            .await
+            .with_context(|| "Error listing reminders")
+    }
+
+    /// Get a page like [`Repository::list`], but a reminder that can not be read takes its place in the page as an
+    /// error instead of failing the whole page.
+    ///
+    /// Errors that can not be pinned on a single reminder still fail the page.
+    pub async fn list_readable(
+        &self,
+        options: &ReminderListOptions,
+    ) -> Result<Page<std::result::Result<Reminder, UnreadableReminder>, ReminderCursor>> {
+        event!(Level::TRACE, ?options, "Listing readable reminders");
+
+        let mut rows = self.list_rows(options).await?;
+        let has_next = rows.len() > options.limit as usize;
+        rows.truncate(options.limit as usize);
+
+        let next = match rows.last() {
+            Some(last) if has_next => Some(cursor_from_row(last, options.sort)?),
+            _ => None,
+        };
+        let items = rows
+            .iter()
+            .map(|row| {
+                let id: i64 = row
+                    .try_get("id")
+                    .context("Error reading the id of a reminder")?;
+
+                Ok(reminder_from_row(row, self.cipher)
+                    .map_err(|error| UnreadableReminder { id, error }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Page { items, next })
     }
 }
 
-/// Converts an iterator over `AnyRow`s into a vector of `Reminder`s
-fn query_to_entity(query: Vec<AnyRow>) -> Vec<Reminder> {
-    query
-        .iter()
-        .map(|r| ReminderEntity::from_row(r).ok())
-        .filter_map(|o| o.and_then(|f| Reminder::try_from(f).ok()))
-        .collect()
+/// Bind the values returned by [`ReminderRepo::push_filter`] to the query.
+fn bind_filter_values<'q>(
+    query: Query<'q, Any, AnyArguments<'q>>,
+    values: Vec<i64>,
+) -> Query<'q, Any, AnyArguments<'q>> {
+    values
+        .into_iter()
+        .fold(query, |query, value| query.bind(value))
 }
 
 #[async_trait]
 impl<'r> Repository<Reminder, i64> for ReminderRepo<'r> {
+    type Filter = ReminderFilter;
+    type Sort = ReminderSort;
+    type Cursor = ReminderCursor;
+
     /// Inserts a reminder into the database and returns the id of the inserted record upon success.
     async fn insert(&self, entity: &Reminder) -> Result<i64> {
         event!(Level::TRACE, "Adding or updating entity {:?}", entity);
-        let db_ent = ReminderEntity::from(entity);
+        let db_ent = self.to_entity(entity)?;
 
-        let trans = self.pool.begin().await?;
+        let mut trans = self.pool.begin().await?;
 
-        let query = sqlx_oldapi::query(INSERT_QUERY)
+        let query = sqlx_oldapi::query(self.queries().insert)
             .bind(db_ent.who)
             .bind(db_ent.when)
             .bind(db_ent.what)
+            .bind(db_ent.what_encrypted)
             .bind(db_ent.server)
             .bind(db_ent.channel)
-            .fetch_one(self.pool)
+            .fetch_one(&mut *trans)
             .await
             .with_context(|| "Error saving or updating entity")?;
 
         trans.commit().await?;
-        Ok(query.try_get(0)?)
+        let id = query.try_get(0)?;
+
+        self.publish(StorageEvent::ReminderInserted {
+            id,
+            when: entity.when,
+        });
+
+        Ok(id)
+    }
+
+    /// Inserts all reminders in a single transaction and returns their ids, in the same order.
+    async fn insert_many(&self, entities: &[Reminder]) -> Result<Vec<i64>> {
+        event!(Level::TRACE, "Inserting {} reminders", entities.len());
+
+        let mut trans = self.pool.begin().await?;
+        let mut ids = Vec::with_capacity(entities.len());
+
+        for entity in entities {
+            let db_ent = self.to_entity(entity)?;
+
+            let row = sqlx_oldapi::query(self.queries().insert)
+                .bind(db_ent.who)
+                .bind(db_ent.when)
+                .bind(db_ent.what)
+                .bind(db_ent.what_encrypted)
+                .bind(db_ent.server)
+                .bind(db_ent.channel)
+                .fetch_one(&mut *trans)
+                .await
+                .with_context(|| "Error inserting reminders")?;
+
+            ids.push(row.try_get(0)?);
+        }
+
+        trans
+            .commit()
+            .await
+            .with_context(|| "Error committing transaction")?;
+
+        if !ids.is_empty() {
+            self.publish(StorageEvent::RemindersChanged);
+        }
+
+        Ok(ids)
+    }
+
+    /// Updates every field of the reminder with the same id.
+    async fn update(&self, entity: &Reminder) -> Result<bool> {
+        event!(Level::TRACE, "Updating entity {:?}", entity);
+        let db_ent = self.to_entity(entity)?;
+
+        let result = sqlx_oldapi::query(self.queries().update)
+            .bind(db_ent.who)
+            .bind(db_ent.when)
+            .bind(db_ent.what)
+            .bind(db_ent.what_encrypted)
+            .bind(db_ent.server)
+            .bind(db_ent.channel)
+            .bind(db_ent.id)
+            .execute(self.pool)
+            .await
+            .with_context(|| format!("Error updating reminder {}", entity.id))?;
+
+        let updated = result.rows_affected() > 0;
+        if updated {
+            self.publish(StorageEvent::ReminderUpdated {
+                id: entity.id,
+                when: entity.when,
+            });
+        }
+
+        Ok(updated)
     }
 
     /// Deletes the given reminder from the database.
     async fn delete(&self, entity: Reminder) -> Result<()> {
         event!(Level::TRACE, "Deleting entity {:?}", &entity);
 
-        let trans = self.pool.begin().await?;
+        let mut trans = self.pool.begin().await?;
 
-        sqlx_oldapi::query(DELETE_QUERY)
+        sqlx_oldapi::query(self.queries().delete)
             .bind(entity.id)
-            .execute(self.pool)
+            .execute(&mut *trans)
             .await
             .with_context(|| "Error deleting reminder")?;
 
         trans.commit().await?;
 
+        self.publish(StorageEvent::RemindersDeleted {
+            ids: vec![entity.id],
+        });
+
         Ok(())
     }
 
+    /// Deletes the reminders with the given ids in a single transaction.
+    ///
+    /// The ids are bound as parameters, in chunks of at most [`DELETE_CHUNK_SIZE`].
+    async fn delete_many(&self, ids: &[i64]) -> Result<u64> {
+        event!(Level::TRACE, "Deleting {} reminders", ids.len());
+
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let table = self.queries().table;
+        let mut trans = self
+            .pool
+            .begin()
+            .await
+            .with_context(|| "Error starting transaction")?;
+        let mut deleted = 0;
+
+        for chunk in ids.chunks(DELETE_CHUNK_SIZE) {
+            let mut sql =
+                SqlBuilder::new(self.backend, format!("DELETE FROM {table} WHERE id IN ("));
+            let placeholders: Vec<String> = chunk.iter().map(|_| sql.placeholder()).collect();
+            sql.push(&placeholders.join(", ")).push(")");
+
+            let query = chunk
+                .iter()
+                .fold(sqlx_oldapi::query(sql.sql()), |query, id| query.bind(id));
+
+            deleted += query
+                .execute(&mut *trans)
+                .await
+                .with_context(|| "Error deleting reminders")?
+                .rows_affected();
+        }
+
+        trans
+            .commit()
+            .await
+            .with_context(|| "Error committing transaction")?;
+
+        if deleted > 0 {
+            self.publish(StorageEvent::RemindersDeleted { ids: ids.to_vec() });
+        }
+
+        Ok(deleted)
+    }
+
     /// Get a reminder by id.
     async fn get(&self, id: i64) -> Result<Option<Reminder>> {
         event!(Level::TRACE, "Retrieving Reminder with id {}", id);
 
-        if let Some(query) = sqlx_oldapi::query_as::<Any, ReminderEntity>(GET_ONE_QUERY)
+        if let Some(query) = sqlx_oldapi::query_as::<Any, ReminderEntity>(self.queries().get_one)
             .bind(id)
             .fetch_optional(self.pool)
             .await
             .with_context(|| "Error getting Reminder with id")?
         {
             Ok(Some(
-                query
-                    .try_into()
+                self.to_reminder(query)
                     .with_context(|| "Error converting entity to reminder")?,
             ))
         } else {
             Ok(None)
         }
     }
+
+    /// Get a page of the reminders that match the filter.
+    async fn list(&self, options: &ReminderListOptions) -> Result<Page<Reminder, ReminderCursor>> {
+        event!(Level::TRACE, ?options, "Listing reminders");
+
+        let rows = self.list_rows(options).await?;
+        let mut items = rows
+            .iter()
+            .map(|row| reminder_from_row(row, self.cipher))
+            .collect::<Result<Vec<_>>>()?;
+
+        let next = if items.len() > options.limit as usize {
+            items.truncate(options.limit as usize);
+            items.last().map(ReminderCursor::from)
+        } else {
+            None
+        };
+
+        Ok(Page { items, next })
+    }
+
+    /// Count the reminders that match the filter.
+    async fn count(&self, filter: &ReminderFilter) -> Result<u64> {
+        event!(Level::TRACE, ?filter, "Counting reminders");
+
+        let mut sql = SqlBuilder::new(
+            self.backend,
+            format!("SELECT COUNT(*) FROM {}", self.queries().table),
+        );
+        let values = self.push_filter(&mut sql, filter, None)?;
+
+        let count: i64 = bind_filter_values(sqlx_oldapi::query(sql.sql()), values)
+            .fetch_one(self.pool)
+            .await
+            .with_context(|| "Error counting reminders")?
+            .try_get(0)?;
+
+        Ok(count as u64)
+    }
 }
 
 impl Reminder {
     /// Create a `Reminder` repository that connects to the database with the borrowed pool.
-    pub fn repository(pool: &AnyPool) -> ReminderRepo {
-        Repo { pool }
+    pub fn repository(pool: &AnyPool) -> ReminderRepo<'_> {
+        Repo {
+            pool,
+            backend: Backend::of(pool),
+            cipher: None,
+            events: None,
+        }
     }
 }
 
+/// Convert a row to a reminder. The error names the id of the reminder, when the row has one.
+fn reminder_from_row(row: &AnyRow, cipher: Option<&FieldCipher>) -> Result<Reminder> {
+    let id: Option<i64> = row.try_get("id").ok();
+
+    ReminderEntity::from_row(row)
+        .map_err(anyhow::Error::from)
+        .and_then(|entity| entity.into_reminder(cipher))
+        .with_context(|| match id {
+            Some(id) => format!("Error converting reminder {} from the database", id),
+            None => "Error converting a reminder without id from the database".to_string(),
+        })
+}
+
+/// The cursor after a row, without reading the rest of the reminder. `when` is only needed when sorting on it.
+fn cursor_from_row(row: &AnyRow, sort: ReminderSort) -> Result<ReminderCursor> {
+    let id: i64 = row.try_get("id")?;
+    let when = match sort {
+        ReminderSort::Id => DateTime::UNIX_EPOCH,
+        ReminderSort::When => {
+            let when: i64 = row.try_get("when")?;
+            DateTime::from_timestamp(when, 0)
+                .with_context(|| format!("Invalid timestamp {} of reminder {}", when, id))?
+        }
+    };
+
+    Ok(ReminderCursor { id, when })
+}
+
 /// Because a lot of our types are not supported by databases
+///
+/// Snowflakes are stored as (signed) integers and `when` as a unix timestamp in seconds.
 #[derive(Debug, FromRow)]
 struct ReminderEntity {
     pub id: i64,
-    pub who: String,
-    pub when: DateTime<Utc>,
+    pub who: i64,
+    pub when: i64,
     pub what: String,
-    pub server: String,
-    pub channel: String,
-}
-
-impl From<&Reminder> for ReminderEntity {
-    fn from(value: &Reminder) -> Self {
-        Self {
-            id: value.id,
-            who: value.who.to_string(),
-            when: value.when,
-            what: value.what.clone(),
-            server: value.server.to_string(),
-            channel: value.channel.to_string(),
-        }
-    }
+    pub what_encrypted: bool,
+    pub server: i64,
+    pub channel: i64,
+    pub undeliverable_since: Option<i64>,
 }
 
-impl TryFrom<ReminderEntity> for Reminder {
-    type Error = anyhow::Error;
+impl ReminderEntity {
+    /// `what` is encrypted when there is a cipher, unless it already is.
+    fn from_reminder(value: &Reminder, cipher: Option<&FieldCipher>) -> Result<Self> {
+        let (what, what_encrypted) = match cipher {
+            _ if value.what_encrypted => (value.what.clone(), true),
+            Some(cipher) => (cipher.encrypt(&value.what)?, true),
+            None => (value.what.clone(), false),
+        };
 
-    fn try_from(value: ReminderEntity) -> Result<Self> {
         Ok(Self {
             id: value.id,
-            who: value.who.parse()?,
-            when: value.when,
-            what: value.what,
-            server: value.server.parse()?,
-            channel: value.channel.parse()?,
+            who: to_db_snowflake(value.who)?,
+            when: value.when.timestamp(),
+            what,
+            what_encrypted,
+            server: to_db_snowflake(value.server)?,
+            channel: to_db_snowflake(value.channel)?,
+            undeliverable_since: value.undeliverable_since.map(|since| since.timestamp()),
+        })
+    }
+
+    /// `what` is decrypted when it is encrypted and there is a cipher, without one it stays encrypted.
+    fn into_reminder(self, cipher: Option<&FieldCipher>) -> Result<Reminder> {
+        let (what, what_encrypted) = match cipher {
+            Some(cipher) if self.what_encrypted => (
+                cipher
+                    .decrypt(&self.what)
+                    .with_context(|| format!("Error decrypting reminder {}", self.id))?,
+                false,
+            ),
+            _ => (self.what, self.what_encrypted),
+        };
+
+        Ok(Reminder {
+            id: self.id,
+            who: from_db_snowflake(self.who)?,
+            when: DateTime::from_timestamp(self.when, 0)
+                .with_context(|| format!("Invalid timestamp {}", self.when))?,
+            what,
+            server: from_db_snowflake(self.server)?,
+            channel: from_db_snowflake(self.channel)?,
+            what_encrypted,
+            undeliverable_since: self
+                .undeliverable_since
+                .map(|since| {
+                    DateTime::from_timestamp(since, 0)
+                        .with_context(|| format!("Invalid timestamp {}", since))
+                })
+                .transpose()?,
         })
     }
 }
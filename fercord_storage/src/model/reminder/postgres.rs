@@ -1,12 +1,41 @@
+pub(super) const TABLE: &str = "public.reminders";
+
 pub(super) const REMINDERS_BETWEEN_QUERY: &str = r#"SELECT *
 FROM public.reminders
-WHERE "when" >= $1 and "when" < $2
+WHERE "when" >= $1 and "when" < $2 and undeliverable_since IS NULL
 "#;
 
 pub(super) const INSERT_QUERY: &str = r#"INSERT INTO public.reminders
-(who, "when", what, "server", channel)
-VALUES($1, $2, $3, $4, $5) RETURNING id;"#;
+(who, "when", what, what_encrypted, "server", channel)
+VALUES($1, $2, $3, $4, $5, $6) RETURNING id;"#;
+
+pub(super) const INSERT_WITH_ID_QUERY: &str = r#"INSERT INTO public.reminders
+(id, who, "when", what, what_encrypted, "server", channel, undeliverable_since)
+OVERRIDING SYSTEM VALUE
+VALUES($1, $2, $3, $4, $5, $6, $7, $8);"#;
+
+/// Make the identity continue after the highest id, explicit ids do not move it.
+pub(super) const SYNC_IDS_QUERY: &str = r#"SELECT setval(
+    pg_get_serial_sequence('public.reminders', 'id'),
+    COALESCE(MAX(id), 0) + 1,
+    false
+) FROM public.reminders;"#;
 
 pub(super) const DELETE_QUERY: &str = "DELETE FROM public.reminders WHERE id=$1;";
 
 pub(super) const GET_ONE_QUERY: &str = "SELECT * FROM public.reminders WHERE id = $1";
+
+pub(super) const UPDATE_QUERY: &str = r#"UPDATE public.reminders
+SET who = $1, "when" = $2, what = $3, what_encrypted = $4, "server" = $5, channel = $6
+WHERE id = $7;"#;
+
+pub(crate) const QUERIES: super::ReminderQueries = super::ReminderQueries {
+    reminders_between: REMINDERS_BETWEEN_QUERY,
+    insert: INSERT_QUERY,
+    insert_with_id: INSERT_WITH_ID_QUERY,
+    sync_ids: Some(SYNC_IDS_QUERY),
+    delete: DELETE_QUERY,
+    get_one: GET_ONE_QUERY,
+    update: UPDATE_QUERY,
+    table: TABLE,
+};
@@ -1,14 +1,34 @@
+pub(super) const TABLE: &str = "reminders";
+
 pub(super) const REMINDERS_BETWEEN_QUERY: &str = r#"SELECT *
 FROM reminders
-WHERE unixepoch("when") >= unixepoch(?) and unixepoch("when") < unixepoch(?)
+WHERE "when" >= ? and "when" < ? and undeliverable_since IS NULL
 "#;
 
 pub(super) const INSERT_QUERY: &str = r#"INSERT INTO reminders
-(who, 'when', what, server, channel)
-VALUES(?, ?, ?, ?, ?) RETURNING id;"#;
+(who, "when", what, what_encrypted, server, channel)
+VALUES(?, ?, ?, ?, ?, ?) RETURNING id;"#;
+
+pub(super) const INSERT_WITH_ID_QUERY: &str = r#"INSERT INTO reminders
+(id, who, "when", what, what_encrypted, server, channel, undeliverable_since)
+VALUES(?, ?, ?, ?, ?, ?, ?, ?);"#;
 
 pub(super) const DELETE_QUERY: &str = "DELETE FROM reminders WHERE id = ?;";
 
 pub(super) const GET_ONE_QUERY: &str = "SELECT * FROM reminders WHERE id = ?";
 
-pub(super) const BATCH_DELETE_QUERY: &str = "DELETE FROM reminders WHERE id IN (?)";
+pub(super) const UPDATE_QUERY: &str = r#"UPDATE reminders
+SET who = ?, "when" = ?, what = ?, what_encrypted = ?, server = ?, channel = ?
+WHERE id = ?;"#;
+
+pub(crate) const QUERIES: super::ReminderQueries = super::ReminderQueries {
+    reminders_between: REMINDERS_BETWEEN_QUERY,
+    insert: INSERT_QUERY,
+    insert_with_id: INSERT_WITH_ID_QUERY,
+    // AUTOINCREMENT keeps track of the highest id by itself
+    sync_ids: None,
+    delete: DELETE_QUERY,
+    get_one: GET_ONE_QUERY,
+    update: UPDATE_QUERY,
+    table: TABLE,
+};
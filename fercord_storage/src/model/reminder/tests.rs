@@ -0,0 +1,1010 @@
+//! Conformance suite for the `ReminderRepo`.
+//!
+//! Every case runs against each enabled backend, see [`conformance_suite`].
+//!
+//! The cases can share a database, so each one only looks at the reminders of its own (random) server.
+
+use anyhow::Result;
+use chrono::{Duration, SubsecRound};
+
+use super::*;
+use crate::db::Order;
+use crate::events::{EventBus, StorageEvent};
+use crate::testing::{conformance_suite, unique_snowflake};
+
+conformance_suite!(
+    inserted_reminder_can_be_retrieved,
+    unknown_id_returns_none,
+    deleted_reminder_is_gone,
+    reminders_since_only_returns_due_reminders,
+    reminders_before_only_returns_older_reminders,
+    delete_reminders_removes_all_given_reminders,
+    updated_reminder_is_saved,
+    insert_many_returns_ids_in_order,
+    delete_many_removes_given_reminders,
+    list_applies_filter,
+    list_pages_by_id,
+    list_pages_by_when,
+    count_applies_filter,
+    delete_many_spans_multiple_chunks,
+    delete_reminders_before_only_deletes_older_reminders,
+    insert_with_ids_keeps_ids,
+    encrypted_reminders_are_decrypted,
+    plaintext_that_looks_encrypted_stays_plaintext,
+    unreadable_reminders_are_reported_with_their_id,
+    undeliverable_reminders_are_skipped_and_deleted,
+    marking_needs_a_user_server_or_channel,
+    changes_are_published,
+);
+
+/// A server id that no other test case uses.
+fn unique_server() -> u64 {
+    unique_snowflake()
+}
+
+fn reminder(server: u64, when: DateTime<Utc>) -> Reminder {
+    Reminder {
+        id: 0,
+        who: 1,
+        when: when.trunc_subsecs(0),
+        what: "conformance".into(),
+        server,
+        channel: 2,
+        what_encrypted: false,
+        undeliverable_since: None,
+    }
+}
+
+/// Insert the reminders and return them with their new ids.
+async fn insert_all(repo: &ReminderRepo<'_>, reminders: Vec<Reminder>) -> Result<Vec<Reminder>> {
+    let mut inserted = Vec::with_capacity(reminders.len());
+
+    for mut reminder in reminders {
+        reminder.id = repo.insert(&reminder).await?;
+        inserted.push(reminder);
+    }
+
+    Ok(inserted)
+}
+
+fn for_server(reminders: Vec<Reminder>, server: u64) -> Vec<Reminder> {
+    reminders
+        .into_iter()
+        .filter(|r| r.server == server)
+        .collect()
+}
+
+async fn inserted_reminder_can_be_retrieved(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let reminder = reminder(unique_server(), Utc::now());
+
+    let id = repo.insert(&reminder).await?;
+
+    assert_eq!(Some(Reminder { id, ..reminder }), repo.get(id).await?);
+
+    Ok(())
+}
+
+async fn unknown_id_returns_none(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+
+    assert_eq!(None, repo.get(i64::MAX).await?);
+
+    Ok(())
+}
+
+async fn deleted_reminder_is_gone(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let inserted = insert_all(
+        &repo,
+        vec![reminder(server, Utc::now()), reminder(server, Utc::now())],
+    )
+    .await?;
+
+    repo.delete(inserted[0].clone()).await?;
+
+    assert_eq!(None, repo.get(inserted[0].id).await?);
+    assert_eq!(Some(inserted[1].clone()), repo.get(inserted[1].id).await?);
+
+    Ok(())
+}
+
+async fn reminders_since_only_returns_due_reminders(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let inserted = insert_all(
+        &repo,
+        vec![
+            reminder(server, now - Duration::days(1)),
+            reminder(server, now - Duration::hours(1)),
+            reminder(server, now + Duration::hours(1)),
+        ],
+    )
+    .await?;
+
+    let since = repo
+        .get_reminders_since(&(now - Duration::hours(2)))
+        .await?;
+
+    assert_eq!(vec![inserted[1].clone()], for_server(since, server));
+
+    Ok(())
+}
+
+async fn reminders_before_only_returns_older_reminders(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let inserted = insert_all(
+        &repo,
+        vec![
+            reminder(server, now - Duration::days(2)),
+            reminder(server, now - Duration::hours(1)),
+        ],
+    )
+    .await?;
+
+    let before = repo
+        .get_reminders_before(&(now - Duration::days(1)))
+        .await?;
+
+    assert_eq!(vec![inserted[0].clone()], for_server(before, server));
+
+    Ok(())
+}
+
+async fn delete_reminders_removes_all_given_reminders(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let inserted = insert_all(
+        &repo,
+        vec![
+            reminder(server, now - Duration::hours(3)),
+            reminder(server, now - Duration::hours(2)),
+            reminder(server, now - Duration::hours(1)),
+        ],
+    )
+    .await?;
+
+    repo.delete_reminders(Vec::new()).await?;
+    repo.delete_reminders(inserted[..2].to_vec()).await?;
+
+    let remaining = repo.get_reminders_before(&now).await?;
+    assert_eq!(vec![inserted[2].clone()], for_server(remaining, server));
+
+    Ok(())
+}
+
+/// Follow the cursors until the last page and return every page.
+async fn all_pages(
+    repo: &ReminderRepo<'_>,
+    mut options: ReminderListOptions,
+) -> Result<Vec<Vec<Reminder>>> {
+    let mut pages = Vec::new();
+
+    loop {
+        let page = repo.list(&options).await?;
+        pages.push(page.items);
+
+        match page.next {
+            Some(next) => options.after = Some(next),
+            None => return Ok(pages),
+        }
+    }
+}
+
+fn server_filter(server: u64) -> ReminderFilter {
+    ReminderFilter {
+        server: Some(server),
+        ..Default::default()
+    }
+}
+
+async fn updated_reminder_is_saved(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let mut inserted = reminder(server, Utc::now());
+    inserted.id = repo.insert(&inserted).await?;
+
+    let updated = Reminder {
+        who: 3,
+        when: inserted.when + Duration::hours(1),
+        what: "updated".into(),
+        channel: 4,
+        ..inserted
+    };
+
+    assert!(repo.update(&updated).await?);
+    assert_eq!(Some(updated.clone()), repo.get(updated.id).await?);
+    assert!(
+        !repo
+            .update(&Reminder {
+                id: i64::MAX,
+                ..updated
+            })
+            .await?
+    );
+
+    Ok(())
+}
+
+async fn insert_many_returns_ids_in_order(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let reminders = vec![
+        reminder(server, now),
+        reminder(server, now + Duration::hours(1)),
+    ];
+
+    let ids = repo.insert_many(&reminders).await?;
+
+    assert_eq!(2, ids.len());
+    for (id, reminder) in ids.into_iter().zip(reminders) {
+        assert_eq!(Some(Reminder { id, ..reminder }), repo.get(id).await?);
+    }
+    assert!(repo.insert_many(&[]).await?.is_empty());
+
+    Ok(())
+}
+
+async fn delete_many_removes_given_reminders(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let inserted = insert_all(
+        &repo,
+        vec![
+            reminder(server, now),
+            reminder(server, now),
+            reminder(server, now),
+        ],
+    )
+    .await?;
+
+    let deleted = repo
+        .delete_many(&[inserted[0].id, inserted[2].id, i64::MAX])
+        .await?;
+
+    assert_eq!(2, deleted);
+    assert_eq!(1, repo.count(&server_filter(server)).await?);
+    assert_eq!(Some(inserted[1].clone()), repo.get(inserted[1].id).await?);
+
+    Ok(())
+}
+
+async fn list_applies_filter(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let inserted = insert_all(
+        &repo,
+        vec![
+            Reminder {
+                who: 10,
+                ..reminder(server, now - Duration::hours(1))
+            },
+            Reminder {
+                who: 10,
+                ..reminder(server, now + Duration::hours(1))
+            },
+            Reminder {
+                who: 11,
+                ..reminder(server, now + Duration::hours(1))
+            },
+            reminder(unique_server(), now + Duration::hours(1)),
+        ],
+    )
+    .await?;
+
+    let page = repo
+        .list(&ReminderListOptions {
+            filter: ReminderFilter {
+                who: Some(10),
+                due_from: Some(now),
+                due_before: Some(now + Duration::days(1)),
+                ..server_filter(server)
+            },
+            ..Default::default()
+        })
+        .await?;
+
+    assert_eq!(vec![inserted[1].clone()], page.items);
+    assert_eq!(None, page.next);
+
+    Ok(())
+}
+
+async fn list_pages_by_id(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let inserted = insert_all(&repo, (0..5).map(|_| reminder(server, now)).collect()).await?;
+    let options = ReminderListOptions {
+        filter: server_filter(server),
+        limit: 2,
+        ..Default::default()
+    };
+
+    let ascending = all_pages(&repo, options.clone()).await?;
+    let descending = all_pages(
+        &repo,
+        ReminderListOptions {
+            order: Order::Descending,
+            ..options
+        },
+    )
+    .await?;
+
+    assert_eq!(
+        vec![
+            inserted[0..2].to_vec(),
+            inserted[2..4].to_vec(),
+            inserted[4..].to_vec()
+        ],
+        ascending
+    );
+    let mut reversed = inserted.clone();
+    reversed.reverse();
+    assert_eq!(
+        vec![
+            reversed[0..2].to_vec(),
+            reversed[2..4].to_vec(),
+            reversed[4..].to_vec()
+        ],
+        descending
+    );
+
+    Ok(())
+}
+
+async fn list_pages_by_when(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    // Two reminders are due at the same time, so the cursor has to use the id as well.
+    let inserted = insert_all(
+        &repo,
+        vec![
+            reminder(server, now + Duration::hours(2)),
+            reminder(server, now),
+            reminder(server, now + Duration::hours(1)),
+            reminder(server, now + Duration::hours(1)),
+        ],
+    )
+    .await?;
+
+    let pages = all_pages(
+        &repo,
+        ReminderListOptions {
+            filter: server_filter(server),
+            sort: ReminderSort::When,
+            limit: 2,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    assert_eq!(
+        vec![
+            vec![inserted[1].clone(), inserted[2].clone()],
+            vec![inserted[3].clone(), inserted[0].clone()],
+        ],
+        pages
+    );
+
+    Ok(())
+}
+
+async fn count_applies_filter(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    insert_all(
+        &repo,
+        vec![
+            reminder(server, now - Duration::hours(1)),
+            reminder(server, now + Duration::hours(1)),
+            reminder(server, now + Duration::hours(2)),
+        ],
+    )
+    .await?;
+
+    assert_eq!(3, repo.count(&server_filter(server)).await?);
+    assert_eq!(
+        2,
+        repo.count(&ReminderFilter {
+            due_from: Some(now),
+            ..server_filter(server)
+        })
+        .await?
+    );
+
+    Ok(())
+}
+
+async fn delete_many_spans_multiple_chunks(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let reminders: Vec<Reminder> = (0..DELETE_CHUNK_SIZE * 2 + 1)
+        .map(|_| reminder(server, now))
+        .collect();
+    let ids = repo.insert_many(&reminders).await?;
+
+    let deleted = repo.delete_many(&ids).await?;
+
+    assert_eq!(ids.len() as u64, deleted);
+    assert_eq!(0, repo.count(&server_filter(server)).await?);
+
+    Ok(())
+}
+
+async fn delete_reminders_before_only_deletes_older_reminders(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let cutoff = Utc::now() - Duration::days(365);
+    let inserted = insert_all(
+        &repo,
+        vec![
+            reminder(server, cutoff - Duration::hours(1)),
+            reminder(server, cutoff + Duration::hours(1)),
+        ],
+    )
+    .await?;
+
+    repo.delete_reminders_before(&cutoff).await?;
+
+    assert_eq!(None, repo.get(inserted[0].id).await?);
+    assert_eq!(Some(inserted[1].clone()), repo.get(inserted[1].id).await?);
+
+    Ok(())
+}
+
+/// Old rows have to survive the migration to the v2 schema, which stores timestamps and snowflakes as integers.
+mod upgrade {
+    use std::borrow::Cow;
+
+    use anyhow::Result;
+    use chrono::{DateTime, TimeZone, Utc};
+    use sqlx_oldapi::any::AnyPoolOptions;
+    use sqlx_oldapi::AnyPool;
+
+    use crate::db::{self, Backend, Repository};
+    use crate::model::reminder::Reminder;
+    #[cfg(feature = "postgres")]
+    use crate::testing::TempPostgresDatabase;
+
+    // These differ from the current queries, otherwise the connection would reuse the statements it prepared
+    // for the v1 schema.
+    #[cfg(feature = "sqlite")]
+    const SQLITE_V1_INSERT: &str =
+        "INSERT INTO reminders (who, 'when', what, server, channel) VALUES (?, ?, ?, ?, ?) RETURNING id";
+
+    #[cfg(feature = "postgres")]
+    const POSTGRES_V1_INSERT: &str = r#"INSERT INTO public.reminders (who, "when", what, "server", channel) VALUES ($1, $2, $3, $4, $5) RETURNING id"#;
+
+    /// Run the migrations up to and including `version`.
+    async fn migrate_to(pool: &AnyPool, version: i64) -> Result<()> {
+        let mut migrator = db::migrator(Backend::of(pool))?;
+        migrator.migrations = Cow::Owned(
+            migrator
+                .migrations
+                .iter()
+                .filter(|m| m.version <= version)
+                .cloned()
+                .collect(),
+        );
+
+        Ok(migrator.run(pool).await?)
+    }
+
+    async fn old_rows_survive_schema_v2(
+        pool: &AnyPool,
+        v1_version: i64,
+        v1_insert: &str,
+    ) -> Result<()> {
+        migrate_to(pool, v1_version).await?;
+
+        let when: DateTime<Utc> = Utc.with_ymd_and_hms(2024, 2, 29, 13, 37, 42).unwrap();
+        let mut ids = Vec::new();
+        for (who, when) in [
+            ("1234", when),
+            (
+                "987654321098765432",
+                when + chrono::Duration::milliseconds(250),
+            ),
+        ] {
+            let id: i64 = sqlx_oldapi::Row::try_get(
+                &sqlx_oldapi::query(v1_insert)
+                    .bind(who)
+                    .bind(when)
+                    .bind("old reminder")
+                    .bind("42")
+                    .bind("4242")
+                    .fetch_one(pool)
+                    .await?,
+                0,
+            )?;
+            ids.push(id);
+        }
+
+        db::run_migrations(pool).await?;
+
+        let repo = Reminder::repository(pool);
+        assert_eq!(
+            Some(Reminder {
+                id: ids[0],
+                who: 1234,
+                when,
+                what: "old reminder".into(),
+                server: 42,
+                channel: 4242,
+                what_encrypted: false,
+                undeliverable_since: None,
+            }),
+            repo.get(ids[0]).await?
+        );
+        // Timestamps are stored in seconds from now on.
+        assert_eq!(
+            Some(Reminder {
+                id: ids[1],
+                who: 987654321098765432,
+                when,
+                what: "old reminder".into(),
+                server: 42,
+                channel: 4242,
+                what_encrypted: false,
+                undeliverable_since: None,
+            }),
+            repo.get(ids[1]).await?
+        );
+
+        // New reminders do not reuse the ids of the migrated ones.
+        let new_id = repo.insert(&repo.get(ids[0]).await?.unwrap()).await?;
+        assert!(new_id > ids[1]);
+
+        Ok(())
+    }
+
+    async fn rows_survive_reverting_schema_v2(pool: &AnyPool, v1_version: i64) -> Result<()> {
+        db::run_migrations(pool).await?;
+        let when: DateTime<Utc> = Utc.with_ymd_and_hms(2024, 2, 29, 13, 37, 42).unwrap();
+        let reminder = Reminder {
+            id: 0,
+            who: 987654321098765432,
+            when,
+            what: "reverted reminder".into(),
+            server: 42,
+            channel: 4242,
+            what_encrypted: false,
+            undeliverable_since: None,
+        };
+        let id = Reminder::repository(pool).insert(&reminder).await?;
+
+        let reverted = db::revert_migrations(pool, v1_version).await?;
+        assert_eq!(Some(&(v1_version + 1)), reverted.last());
+        db::run_migrations(pool).await?;
+
+        assert_eq!(
+            Some(Reminder { id, ..reminder }),
+            Reminder::repository(pool).get(id).await?
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_rows_survive_reverting_schema_v2() -> Result<()> {
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+
+        rows_survive_reverting_schema_v2(&pool, 0).await
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_rows_survive_reverting_schema_v2() -> Result<()> {
+        let Some(database) = TempPostgresDatabase::create("fercord_revert").await? else {
+            return Ok(());
+        };
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect(&database.url)
+            .await?;
+        let result = rows_survive_reverting_schema_v2(&pool, 1).await;
+        pool.close().await;
+
+        database.drop_database().await?;
+
+        result
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_rows_survive_schema_v2() -> Result<()> {
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+
+        old_rows_survive_schema_v2(&pool, 0, SQLITE_V1_INSERT).await
+    }
+
+    /// The v1 sqlite schema has no constraints at all, the rows v2 can not hold are quarantined.
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_invalid_rows_are_quarantined_by_schema_v2() -> Result<()> {
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        migrate_to(&pool, 0).await?;
+
+        let when = "2024-02-29T13:37:42Z";
+        for (who, channel) in [
+            (Some("1234"), Some("4242")),
+            (Some("not a snowflake"), Some("4242")),
+            (Some("12.5"), Some("4242")),
+            (Some("99999999999999999999"), Some("4242")),
+            (Some("1234"), None),
+        ] {
+            sqlx_oldapi::query(SQLITE_V1_INSERT)
+                .bind(who)
+                .bind(when)
+                .bind("old reminder")
+                .bind("42")
+                .bind(channel)
+                .fetch_one(&pool)
+                .await?;
+        }
+
+        db::run_migrations(&pool).await?;
+
+        let reminders: i64 = sqlx_oldapi::query_scalar("SELECT COUNT(*) FROM reminders")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(1, reminders);
+        let quarantined: Vec<(Option<String>, String)> =
+            sqlx_oldapi::query_as("SELECT who, reason FROM reminders_quarantine ORDER BY id")
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(
+            vec![
+                (Some("not a snowflake".into()), "invalid snowflake".into()),
+                (Some("12.5".into()), "invalid snowflake".into()),
+                (
+                    Some("99999999999999999999".into()),
+                    "invalid snowflake".into()
+                ),
+                (Some("1234".into()), "missing value".into()),
+            ],
+            quarantined
+        );
+
+        // Reverting puts them back
+        db::revert_migrations(&pool, 0).await?;
+        let reminders: i64 = sqlx_oldapi::query_scalar("SELECT COUNT(*) FROM reminders")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(5, reminders);
+
+        Ok(())
+    }
+
+    /// Runs in a new database, the shared test database is already migrated.
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_rows_survive_schema_v2() -> Result<()> {
+        let Some(database) = TempPostgresDatabase::create("fercord_upgrade").await? else {
+            return Ok(());
+        };
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect(&database.url)
+            .await?;
+        let result = old_rows_survive_schema_v2(&pool, 1, POSTGRES_V1_INSERT).await;
+        pool.close().await;
+
+        database.drop_database().await?;
+
+        result
+    }
+}
+
+async fn undeliverable_reminders_are_skipped_and_deleted(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let mut deleted_channel = reminder(server, now - Duration::minutes(1));
+    deleted_channel.channel = unique_snowflake();
+    let [deleted_channel, other_channel] = <[Reminder; 2]>::try_from(
+        insert_all(
+            &repo,
+            vec![
+                deleted_channel,
+                reminder(server, now - Duration::minutes(1)),
+            ],
+        )
+        .await?,
+    )
+    .unwrap();
+    let filter = ReminderFilter {
+        channel: Some(deleted_channel.channel),
+        ..Default::default()
+    };
+    // Before the marks the other cases make, so they are not deleted below
+    let since = now - Duration::days(2);
+
+    assert_eq!(1, repo.mark_undeliverable(&filter, &since).await?);
+    assert_eq!(0, repo.mark_undeliverable(&filter, &now).await?);
+
+    let due: Vec<i64> = repo
+        .stream_reminders_since(&(now - Duration::minutes(2)))
+        .filter_map(|r| r.ok())
+        .map(|r| r.id)
+        .collect()
+        .await;
+    assert!(!due.contains(&deleted_channel.id));
+    assert!(due.contains(&other_channel.id));
+    let deliverable = ReminderFilter {
+        server: Some(server),
+        deliverable_only: true,
+        ..Default::default()
+    };
+    assert_eq!(1, repo.count(&deliverable).await?);
+
+    // Marked before `now`, so they stay undeliverable
+    assert_eq!(0, repo.mark_deliverable(&filter, &now).await?);
+    repo.delete_undeliverable_before(&(since + Duration::seconds(1)))
+        .await?;
+
+    assert_eq!(None, repo.get(deleted_channel.id).await?);
+    assert!(repo.get(other_channel.id).await?.is_some());
+
+    Ok(())
+}
+
+async fn marking_needs_a_user_server_or_channel(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+
+    assert!(repo
+        .mark_undeliverable(&ReminderFilter::default(), &Utc::now())
+        .await
+        .is_err());
+    assert!(repo
+        .delete_reminders_matching(&ReminderFilter::default())
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+async fn insert_with_ids_keeps_ids(pool: &AnyPool) -> Result<()> {
+    let repo = Reminder::repository(pool);
+    let server = unique_server();
+    let now = Utc::now();
+    let mut kept = reminder(server, now);
+    // Well above the ids the other cases get
+    kept.id = (unique_snowflake() >> 20) as i64 + 1_000_000;
+
+    assert_eq!(1, repo.insert_with_ids(&[kept.clone()]).await?);
+    assert_eq!(Some(kept.clone()), repo.get(kept.id).await?);
+
+    // New reminders continue after the inserted id instead of running into it later
+    let next_id = repo.insert(&reminder(server, now)).await?;
+    assert!(next_id > kept.id);
+
+    assert!(repo.insert_with_ids(&[kept]).await.is_err());
+
+    Ok(())
+}
+
+fn test_cipher(key_id: &str, key: u8) -> FieldCipher {
+    FieldCipher::new(key_id, &[key; 32]).unwrap()
+}
+
+async fn encrypted_reminders_are_decrypted(pool: &AnyPool) -> Result<()> {
+    let cipher = test_cipher("test", 1);
+    let repo = Reminder::repository(pool).with_cipher(Some(&cipher));
+    let server = unique_server();
+    let plaintext = Reminder::repository(pool)
+        .insert(&reminder(server, Utc::now()))
+        .await?;
+
+    let encrypted = repo.insert(&reminder(server, Utc::now())).await?;
+
+    let stored = Reminder::repository(pool).get(encrypted).await?.unwrap();
+    assert!(stored.what.starts_with("enc:v1:test:"), "{}", stored.what);
+    assert!(stored.what_encrypted);
+    let listed = repo
+        .list(&ReminderListOptions {
+            filter: server_filter(server),
+            ..Default::default()
+        })
+        .await?
+        .items;
+    assert_eq!(
+        vec![plaintext, encrypted],
+        listed.iter().map(|r| r.id).collect::<Vec<_>>()
+    );
+    assert!(listed
+        .iter()
+        .all(|r| r.what == "conformance" && !r.what_encrypted));
+
+    // Saving a reminder that was read without the cipher keeps it encrypted, like copies and restores do
+    let copy = Reminder::repository(pool).insert(&stored).await?;
+    assert_eq!(
+        Some("conformance".to_owned()),
+        repo.get(copy).await?.map(|r| r.what)
+    );
+
+    Ok(())
+}
+
+async fn plaintext_that_looks_encrypted_stays_plaintext(pool: &AnyPool) -> Result<()> {
+    let cipher = test_cipher("test", 1);
+    let lookalike = cipher.encrypt("not what it seems")?;
+    let id = Reminder::repository(pool)
+        .insert(&Reminder {
+            what: lookalike.clone(),
+            ..reminder(unique_server(), Utc::now())
+        })
+        .await?;
+
+    let read = Reminder::repository(pool)
+        .with_cipher(Some(&cipher))
+        .get(id)
+        .await?
+        .unwrap();
+
+    assert_eq!(lookalike, read.what);
+    assert!(!read.what_encrypted);
+
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn reencrypt_uses_the_current_key() -> Result<()> {
+    // Not part of the conformance suite, it encrypts every reminder in the database
+    let pool = crate::db::setup("sqlite::memory:").await?;
+    let old_cipher = test_cipher("old", 1);
+    let cipher = test_cipher("new", 2).with_previous_key("old", &[1; 32])?;
+    let server = unique_server();
+    Reminder::repository(&pool)
+        .insert(&reminder(server, Utc::now()))
+        .await?;
+    Reminder::repository(&pool)
+        .with_cipher(Some(&old_cipher))
+        .insert(&reminder(server, Utc::now()))
+        .await?;
+    Reminder::repository(&pool)
+        .with_cipher(Some(&cipher))
+        .insert(&reminder(server, Utc::now()))
+        .await?;
+
+    let repo = Reminder::repository(&pool).with_cipher(Some(&cipher));
+    assert_eq!(2, repo.reencrypt(1).await?);
+    assert_eq!(0, repo.reencrypt(1).await?);
+
+    let stored = Reminder::repository(&pool)
+        .list(&ReminderListOptions::default())
+        .await?
+        .items;
+    assert_eq!(3, stored.len());
+    assert!(stored.iter().all(|r| cipher.is_current(&r.what)));
+    let decrypted = repo.list(&ReminderListOptions::default()).await?.items;
+    assert!(decrypted.iter().all(|r| r.what == "conformance"));
+    assert!(Reminder::repository(&pool).reencrypt(1).await.is_err());
+
+    Ok(())
+}
+
+async fn unreadable_reminders_are_reported_with_their_id(pool: &AnyPool) -> Result<()> {
+    let writer = test_cipher("unreadable", 3);
+    let reader = test_cipher("reader", 4);
+    let server = unique_server();
+    let moment = Utc::now() - Duration::days(1);
+    let readable = Reminder::repository(pool)
+        .insert(&reminder(server, moment - Duration::hours(1)))
+        .await?;
+    let unreadable = Reminder::repository(pool)
+        .with_cipher(Some(&writer))
+        .insert(&reminder(server, moment - Duration::hours(1)))
+        .await?;
+
+    let repo = Reminder::repository(pool).with_cipher(Some(&reader));
+    let results: Vec<Result<Reminder>> = repo.stream_reminders_before(&moment).collect().await;
+
+    assert!(results
+        .iter()
+        .any(|r| r.as_ref().is_ok_and(|r| r.id == readable)));
+    assert!(results.iter().any(|r| r
+        .as_ref()
+        .is_err_and(|e| format!("{e:#}").contains(&format!("reminder {unreadable}")))));
+    assert!(repo.get_reminders_before(&moment).await.is_err());
+
+    // Paging keeps going past it, one reminder per page
+    let mut options = ReminderListOptions {
+        filter: server_filter(server),
+        limit: 1,
+        ..Default::default()
+    };
+    let mut listed = Vec::new();
+    loop {
+        let page = repo.list_readable(&options).await?;
+        listed.extend(page.items.into_iter().map(|r| match r {
+            Ok(reminder) => Ok(reminder.id),
+            Err(unreadable) => Err(unreadable.id),
+        }));
+        match page.next {
+            Some(next) => options.after = Some(next),
+            None => break,
+        }
+    }
+    assert_eq!(vec![Ok(readable), Err(unreadable)], listed);
+
+    Ok(())
+}
+
+async fn changes_are_published(pool: &AnyPool) -> Result<()> {
+    let bus = EventBus::in_process();
+    let mut events = bus.subscribe().await?;
+    let repo = Reminder::repository(pool).with_events(Some(&bus));
+    let server = unique_server();
+    let mut saved = reminder(server, Utc::now() + Duration::hours(1));
+
+    saved.id = repo.insert(&saved).await?;
+    saved.when += Duration::hours(1);
+    repo.update(&saved).await?;
+    repo.delete(saved.clone()).await?;
+    // Nothing is left to delete, so nothing changed
+    repo.delete_reminders_matching(&ReminderFilter {
+        server: Some(server),
+        ..Default::default()
+    })
+    .await?;
+    // Without a bus nothing is published
+    Reminder::repository(pool).insert(&saved).await?;
+    repo.delete_reminders_matching(&ReminderFilter {
+        server: Some(server),
+        ..Default::default()
+    })
+    .await?;
+    // A single event for the whole batch
+    repo.insert_many(&[saved.clone(), saved.clone()]).await?;
+
+    let mut published = Vec::new();
+    for _ in 0..5 {
+        published.push(events.next().await.transpose()?);
+    }
+    assert_eq!(
+        vec![
+            Some(StorageEvent::ReminderInserted {
+                id: saved.id,
+                when: saved.when - Duration::hours(1),
+            }),
+            Some(StorageEvent::ReminderUpdated {
+                id: saved.id,
+                when: saved.when,
+            }),
+            Some(StorageEvent::RemindersDeleted {
+                ids: vec![saved.id]
+            }),
+            Some(StorageEvent::RemindersChanged),
+            Some(StorageEvent::RemindersChanged),
+        ],
+        published
+    );
+
+    Ok(())
+}
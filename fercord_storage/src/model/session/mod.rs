@@ -0,0 +1,252 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx_oldapi::{Any, AnyPool, FromRow};
+use tracing::{event, Level};
+
+use crate::db::{from_db_snowflake, to_db_snowflake, Backend, Repo};
+use crate::encryption::FieldCipher;
+
+mod sqlite;
+
+mod postgres;
+
+#[cfg(test)]
+mod tests;
+
+/// A session of the web API, for installs that keep their sessions in the database instead of redis.
+///
+/// The API decides what is in the state, it is encrypted when the repository has a cipher. Expired sessions are never
+/// returned and are deleted by [`crate::maintenance`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub key: String,
+    /// The Discord user that logged in with the session, `None` until the user logged in.
+    pub user_id: Option<u64>,
+    pub state: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    /// Create a `Session` repository that connects to the database with the borrowed pool.
+    pub fn repository(pool: &AnyPool) -> SessionRepo<'_> {
+        Repo {
+            pool,
+            backend: Backend::of(pool),
+            cipher: None,
+            events: None,
+        }
+    }
+}
+
+pub type SessionRepo<'r> = Repo<'r>;
+
+/// The SQL of every session query, for a single backend.
+pub(crate) struct SessionQueries {
+    pub get: &'static str,
+    pub insert: &'static str,
+    pub update: &'static str,
+    pub update_expiry: &'static str,
+    pub delete: &'static str,
+    pub list_for_user: &'static str,
+    pub delete_for_user: &'static str,
+    pub delete_expired: &'static str,
+}
+
+impl<'r> SessionRepo<'r> {
+    fn session_queries(&self) -> &'static SessionQueries {
+        match self.backend {
+            Backend::Sqlite => &sqlite::QUERIES,
+            Backend::Postgres => &postgres::QUERIES,
+        }
+    }
+
+    /// Convert a session to its database entity, encrypting the state when there is a cipher.
+    fn to_session_entity(&self, session: &Session) -> Result<SessionEntity> {
+        SessionEntity::from_session(session, self.cipher)
+    }
+
+    /// Get a session, `None` if it does not exist or expired.
+    pub async fn get_session(&self, key: &str) -> Result<Option<Session>> {
+        event!(Level::TRACE, "Retrieving session");
+
+        let entity = sqlx_oldapi::query_as::<Any, SessionEntity>(self.session_queries().get)
+            .bind(key)
+            .bind(Utc::now().timestamp())
+            .fetch_optional(self.pool)
+            .await
+            .context("Error getting session")?;
+
+        entity
+            .map(|entity| entity.into_session(self.cipher))
+            .transpose()
+    }
+
+    /// Save a new session. Returns `false` when a session with the same key already exists.
+    pub async fn insert_session(&self, session: &Session) -> Result<bool> {
+        event!(Level::TRACE, expires_at = %session.expires_at, "Saving new session");
+        let entity = self.to_session_entity(session)?;
+
+        let inserted = sqlx_oldapi::query(self.session_queries().insert)
+            .bind(entity.session_key)
+            .bind(entity.user_id)
+            .bind(entity.state)
+            .bind(entity.state_encrypted)
+            .bind(entity.expires_at)
+            .execute(self.pool)
+            .await
+            .context("Error saving session")?
+            .rows_affected();
+
+        Ok(inserted > 0)
+    }
+
+    /// Replace the user, state and expiry of a session. Returns `false` when the session does not exist or expired.
+    pub async fn update_session(&self, session: &Session) -> Result<bool> {
+        event!(Level::TRACE, expires_at = %session.expires_at, "Updating session");
+        let entity = self.to_session_entity(session)?;
+
+        let updated = sqlx_oldapi::query(self.session_queries().update)
+            .bind(entity.user_id)
+            .bind(entity.state)
+            .bind(entity.state_encrypted)
+            .bind(entity.expires_at)
+            .bind(entity.session_key)
+            .bind(Utc::now().timestamp())
+            .execute(self.pool)
+            .await
+            .context("Error updating session")?
+            .rows_affected();
+
+        Ok(updated > 0)
+    }
+
+    /// Move the expiry of a session. Returns `false` when the session does not exist or expired.
+    pub async fn update_session_expiry(
+        &self,
+        key: &str,
+        expires_at: &DateTime<Utc>,
+    ) -> Result<bool> {
+        event!(Level::TRACE, %expires_at, "Updating session expiry");
+
+        let updated = sqlx_oldapi::query(self.session_queries().update_expiry)
+            .bind(expires_at.timestamp())
+            .bind(key)
+            .bind(Utc::now().timestamp())
+            .execute(self.pool)
+            .await
+            .context("Error updating session expiry")?
+            .rows_affected();
+
+        Ok(updated > 0)
+    }
+
+    /// Delete a session.
+    pub async fn delete_session(&self, key: &str) -> Result<()> {
+        event!(Level::TRACE, "Deleting session");
+
+        sqlx_oldapi::query(self.session_queries().delete)
+            .bind(key)
+            .execute(self.pool)
+            .await
+            .context("Error deleting session")?;
+
+        Ok(())
+    }
+
+    /// The sessions of a user that did not expire, the first to expire first.
+    pub async fn sessions_of_user(&self, user_id: u64) -> Result<Vec<Session>> {
+        event!(Level::TRACE, user_id, "Retrieving the sessions of a user");
+
+        sqlx_oldapi::query_as::<Any, SessionEntity>(self.session_queries().list_for_user)
+            .bind(to_db_snowflake(user_id)?)
+            .bind(Utc::now().timestamp())
+            .fetch_all(self.pool)
+            .await
+            .with_context(|| format!("Error listing the sessions of user {}", user_id))?
+            .into_iter()
+            .map(|entity| entity.into_session(self.cipher))
+            .collect()
+    }
+
+    /// Delete every session of a user, expired or not.
+    ///
+    /// Returns the amount of deleted sessions.
+    pub async fn delete_sessions_of_user(&self, user_id: u64) -> Result<u64> {
+        event!(Level::TRACE, user_id, "Deleting the sessions of a user");
+
+        let deleted = sqlx_oldapi::query(self.session_queries().delete_for_user)
+            .bind(to_db_snowflake(user_id)?)
+            .execute(self.pool)
+            .await
+            .with_context(|| format!("Error deleting the sessions of user {}", user_id))?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// Delete every session that expired at or before the given moment.
+    ///
+    /// Returns the amount of deleted sessions.
+    pub async fn delete_sessions_expired_before(&self, moment: &DateTime<Utc>) -> Result<u64> {
+        event!(Level::TRACE, "Deleting sessions expired before {}", &moment);
+
+        let deleted = sqlx_oldapi::query(self.session_queries().delete_expired)
+            .bind(moment.timestamp())
+            .execute(self.pool)
+            .await
+            .context("Error deleting expired sessions")?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+}
+
+/// Because a lot of our types are not supported by databases
+#[derive(Debug, FromRow)]
+struct SessionEntity {
+    pub session_key: String,
+    pub user_id: Option<i64>,
+    pub state: String,
+    pub state_encrypted: bool,
+    pub expires_at: i64,
+}
+
+impl SessionEntity {
+    /// The state is encrypted when there is a cipher.
+    fn from_session(value: &Session, cipher: Option<&FieldCipher>) -> Result<Self> {
+        let (state, state_encrypted) = match cipher {
+            Some(cipher) => (cipher.encrypt(&value.state)?, true),
+            None => (value.state.clone(), false),
+        };
+
+        Ok(Self {
+            session_key: value.key.clone(),
+            user_id: value.user_id.map(to_db_snowflake).transpose()?,
+            state,
+            state_encrypted,
+            expires_at: value.expires_at.timestamp(),
+        })
+    }
+
+    /// Unlike reminders, an encrypted state can not be used without decrypting it, so that fails without a cipher.
+    fn into_session(self, cipher: Option<&FieldCipher>) -> Result<Session> {
+        let state = match (self.state_encrypted, cipher) {
+            (false, _) => self.state,
+            (true, Some(cipher)) => cipher
+                .decrypt(&self.state)
+                .context("Error decrypting session state")?,
+            (true, None) => {
+                bail!("The session state is encrypted, but no encryption key is configured")
+            }
+        };
+        let expires_at = DateTime::from_timestamp(self.expires_at, 0)
+            .ok_or_else(|| anyhow!("Invalid session expiry {}", self.expires_at))?;
+
+        Ok(Session {
+            key: self.session_key,
+            user_id: self.user_id.map(from_db_snowflake).transpose()?,
+            state,
+            expires_at,
+        })
+    }
+}
@@ -0,0 +1,31 @@
+pub(super) const GET_QUERY: &str =
+    "SELECT * FROM public.sessions WHERE session_key = $1 AND expires_at > $2";
+
+pub(super) const INSERT_QUERY: &str = r#"INSERT INTO public.sessions (session_key, user_id, state, state_encrypted, expires_at)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (session_key) DO NOTHING;"#;
+
+pub(super) const UPDATE_QUERY: &str = r#"UPDATE public.sessions SET user_id = $1, state = $2, state_encrypted = $3, expires_at = $4
+WHERE session_key = $5 AND expires_at > $6"#;
+
+pub(super) const UPDATE_EXPIRY_QUERY: &str =
+    "UPDATE public.sessions SET expires_at = $1 WHERE session_key = $2 AND expires_at > $3";
+
+pub(super) const DELETE_QUERY: &str = "DELETE FROM public.sessions WHERE session_key = $1";
+
+pub(super) const LIST_FOR_USER_QUERY: &str = "SELECT * FROM public.sessions WHERE user_id = $1 AND expires_at > $2 ORDER BY expires_at, session_key";
+
+pub(super) const DELETE_FOR_USER_QUERY: &str = "DELETE FROM public.sessions WHERE user_id = $1";
+
+pub(super) const DELETE_EXPIRED_QUERY: &str = "DELETE FROM public.sessions WHERE expires_at <= $1";
+
+pub(crate) const QUERIES: super::SessionQueries = super::SessionQueries {
+    get: GET_QUERY,
+    insert: INSERT_QUERY,
+    update: UPDATE_QUERY,
+    update_expiry: UPDATE_EXPIRY_QUERY,
+    delete: DELETE_QUERY,
+    list_for_user: LIST_FOR_USER_QUERY,
+    delete_for_user: DELETE_FOR_USER_QUERY,
+    delete_expired: DELETE_EXPIRED_QUERY,
+};
@@ -0,0 +1,32 @@
+pub(super) const GET_QUERY: &str =
+    "SELECT * FROM sessions WHERE session_key = ? AND expires_at > ?";
+
+pub(super) const INSERT_QUERY: &str = r#"INSERT INTO sessions (session_key, user_id, state, state_encrypted, expires_at)
+VALUES (?, ?, ?, ?, ?)
+ON CONFLICT (session_key) DO NOTHING;"#;
+
+pub(super) const UPDATE_QUERY: &str = r#"UPDATE sessions SET user_id = ?, state = ?, state_encrypted = ?, expires_at = ?
+WHERE session_key = ? AND expires_at > ?"#;
+
+pub(super) const UPDATE_EXPIRY_QUERY: &str =
+    "UPDATE sessions SET expires_at = ? WHERE session_key = ? AND expires_at > ?";
+
+pub(super) const DELETE_QUERY: &str = "DELETE FROM sessions WHERE session_key = ?";
+
+pub(super) const LIST_FOR_USER_QUERY: &str =
+    "SELECT * FROM sessions WHERE user_id = ? AND expires_at > ? ORDER BY expires_at, session_key";
+
+pub(super) const DELETE_FOR_USER_QUERY: &str = "DELETE FROM sessions WHERE user_id = ?";
+
+pub(super) const DELETE_EXPIRED_QUERY: &str = "DELETE FROM sessions WHERE expires_at <= ?";
+
+pub(crate) const QUERIES: super::SessionQueries = super::SessionQueries {
+    get: GET_QUERY,
+    insert: INSERT_QUERY,
+    update: UPDATE_QUERY,
+    update_expiry: UPDATE_EXPIRY_QUERY,
+    delete: DELETE_QUERY,
+    list_for_user: LIST_FOR_USER_QUERY,
+    delete_for_user: DELETE_FOR_USER_QUERY,
+    delete_expired: DELETE_EXPIRED_QUERY,
+};
@@ -0,0 +1,178 @@
+//! Conformance suite for the `SessionRepo`, see [`conformance_suite`].
+
+use anyhow::Result;
+use chrono::{Duration, SubsecRound};
+
+use super::*;
+use crate::encryption::FieldCipher;
+use crate::testing::{conformance_suite, unique_snowflake};
+
+conformance_suite!(
+    saved_sessions_can_be_retrieved,
+    session_keys_are_not_reused,
+    expired_sessions_are_not_returned,
+    updating_replaces_state_and_expiry,
+    expired_sessions_can_not_be_updated,
+    expired_sessions_are_deleted,
+    sessions_of_a_user_are_listed_and_deleted,
+    state_is_encrypted_with_a_cipher,
+);
+
+fn session(expires_at: DateTime<Utc>) -> Session {
+    Session {
+        key: format!("session-{}", unique_snowflake()),
+        user_id: None,
+        state: r#"{"session_data":"{}"}"#.into(),
+        expires_at: expires_at.trunc_subsecs(0),
+    }
+}
+
+async fn saved_sessions_can_be_retrieved(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    let saved = session(Utc::now() + Duration::hours(1));
+
+    assert!(repo.insert_session(&saved).await?);
+
+    assert_eq!(Some(saved.clone()), repo.get_session(&saved.key).await?);
+
+    repo.delete_session(&saved.key).await?;
+    assert_eq!(None, repo.get_session(&saved.key).await?);
+
+    Ok(())
+}
+
+async fn session_keys_are_not_reused(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    let first = session(Utc::now() + Duration::hours(1));
+    repo.insert_session(&first).await?;
+
+    let second = Session {
+        state: "{}".into(),
+        ..first.clone()
+    };
+    assert!(!repo.insert_session(&second).await?);
+
+    assert_eq!(Some(first.clone()), repo.get_session(&first.key).await?);
+
+    Ok(())
+}
+
+async fn expired_sessions_are_not_returned(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    let expired = session(Utc::now() - Duration::seconds(1));
+    repo.insert_session(&expired).await?;
+
+    assert_eq!(None, repo.get_session(&expired.key).await?);
+
+    Ok(())
+}
+
+async fn updating_replaces_state_and_expiry(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    let mut saved = session(Utc::now() + Duration::hours(1));
+    repo.insert_session(&saved).await?;
+
+    saved.state = "{}".into();
+    saved.expires_at += Duration::hours(1);
+    assert!(repo.update_session(&saved).await?);
+    assert_eq!(Some(saved.clone()), repo.get_session(&saved.key).await?);
+
+    let expires_at = saved.expires_at + Duration::hours(1);
+    assert!(repo.update_session_expiry(&saved.key, &expires_at).await?);
+    assert_eq!(
+        Some(expires_at),
+        repo.get_session(&saved.key).await?.map(|s| s.expires_at)
+    );
+
+    Ok(())
+}
+
+async fn expired_sessions_can_not_be_updated(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    let expired = session(Utc::now() - Duration::seconds(1));
+    repo.insert_session(&expired).await?;
+    let revived = Session {
+        expires_at: expired.expires_at + Duration::hours(1),
+        ..expired.clone()
+    };
+
+    assert!(!repo.update_session(&revived).await?);
+    assert!(
+        !repo
+            .update_session_expiry(&expired.key, &revived.expires_at)
+            .await?
+    );
+    assert!(!repo.update_session(&session(revived.expires_at)).await?);
+
+    Ok(())
+}
+
+async fn expired_sessions_are_deleted(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    // Well before the sessions of the other cases
+    let cutoff =
+        Utc::now().trunc_subsecs(0) - Duration::days(3650 + (unique_snowflake() % 1000) as i64);
+    let expired = session(cutoff - Duration::hours(1));
+    let live = session(cutoff + Duration::hours(1));
+    repo.insert_session(&expired).await?;
+    repo.insert_session(&live).await?;
+
+    assert!(repo.delete_sessions_expired_before(&cutoff).await? >= 1);
+
+    // Both expired long ago, so only the rows that are left tell them apart
+    assert!(!repo.insert_session(&live).await?);
+    assert!(repo.insert_session(&expired).await?);
+
+    Ok(())
+}
+
+async fn sessions_of_a_user_are_listed_and_deleted(pool: &AnyPool) -> Result<()> {
+    let repo = Session::repository(pool);
+    let user_id = unique_snowflake();
+    let mut theirs = Vec::new();
+    for hours in [2, 1] {
+        let mut saved = session(Utc::now() + Duration::hours(hours));
+        saved.user_id = Some(user_id);
+        repo.insert_session(&saved).await?;
+        theirs.push(saved);
+    }
+    let mut expired = session(Utc::now() - Duration::seconds(1));
+    expired.user_id = Some(user_id);
+    repo.insert_session(&expired).await?;
+    let anonymous = session(Utc::now() + Duration::hours(1));
+    repo.insert_session(&anonymous).await?;
+
+    theirs.reverse();
+    assert_eq!(theirs, repo.sessions_of_user(user_id).await?);
+
+    assert_eq!(3, repo.delete_sessions_of_user(user_id).await?);
+    assert!(repo.sessions_of_user(user_id).await?.is_empty());
+    assert!(repo.get_session(&anonymous.key).await?.is_some());
+
+    Ok(())
+}
+
+async fn state_is_encrypted_with_a_cipher(pool: &AnyPool) -> Result<()> {
+    let cipher = FieldCipher::new("test", &[1; 32])?;
+    let repo = Session::repository(pool).with_cipher(Some(&cipher));
+    let saved = session(Utc::now() + Duration::hours(1));
+    repo.insert_session(&saved).await?;
+
+    assert_eq!(Some(saved.clone()), repo.get_session(&saved.key).await?);
+    let (stored,): (String,) = sqlx_oldapi::query_as(match Backend::of(pool) {
+        Backend::Sqlite => "SELECT state FROM sessions WHERE session_key = ?",
+        Backend::Postgres => "SELECT state FROM public.sessions WHERE session_key = $1",
+    })
+    .bind(&saved.key)
+    .fetch_one(pool)
+    .await?;
+    assert_ne!(saved.state, stored);
+
+    // The state is of no use without the key
+    assert!(Session::repository(pool)
+        .get_session(&saved.key)
+        .await
+        .is_err());
+
+    Ok(())
+}
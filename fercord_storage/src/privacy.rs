@@ -0,0 +1,313 @@
+//! The personal data fercord stores about users, so users can see it and have it deleted.
+//!
+//! [`UserDataLocation`] has a variant for every place that holds data of a user, and each variant knows how to export
+//! and delete that data. A new place with user data only has to be added there.
+
+use std::fmt::{Display, Formatter};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx_oldapi::AnyPool;
+use tracing::{event, Level};
+
+use crate::encryption::FieldCipher;
+use crate::events::EventBus;
+use crate::model::{Reminder, ReminderFilter, ReminderListOptions, Session};
+
+/// The amount of reminders read from the database at a time while exporting.
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// A place where data of a user is stored.
+///
+/// Guild settings are not included, they belong to the guild. Web sessions are only found when the API keeps them in
+/// the database (`session_store = "database"`), the redis session store can not look them up by user. Those sessions
+/// stay in the KV store until they expire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserDataLocation {
+    /// The reminders the user created.
+    Reminders,
+    /// The web sessions the user logged in with, when the API keeps its sessions in the database.
+    Sessions,
+}
+
+impl UserDataLocation {
+    /// Every place that holds data of a user.
+    pub const ALL: &'static [UserDataLocation] =
+        &[UserDataLocation::Reminders, UserDataLocation::Sessions];
+
+    /// Add the data of the user in this location to `export`.
+    async fn export(
+        &self,
+        pool: &AnyPool,
+        cipher: Option<&FieldCipher>,
+        export: &mut UserDataExport,
+    ) -> Result<()> {
+        match self {
+            UserDataLocation::Reminders => {
+                let repo = Reminder::repository(pool).with_cipher(cipher);
+                let mut options = ReminderListOptions {
+                    filter: user_reminders(export.user_id),
+                    limit: EXPORT_PAGE_SIZE,
+                    ..Default::default()
+                };
+                loop {
+                    // A reminder that can not be read should not keep the user from the rest of their data
+                    let page = repo.list_readable(&options).await?;
+                    for reminder in page.items {
+                        match reminder {
+                            Ok(reminder) => export.reminders.push(reminder),
+                            Err(unreadable) => {
+                                event!(
+                                    Level::WARN,
+                                    reminder_id = unreadable.id,
+                                    error = ?unreadable.error,
+                                    "Leaving a reminder that could not be read out of the export"
+                                );
+                                export.unreadable_reminders.push(unreadable.id);
+                            }
+                        }
+                    }
+
+                    match page.next {
+                        Some(next) => options.after = Some(next),
+                        None => break,
+                    }
+                }
+            }
+            UserDataLocation::Sessions => {
+                let sessions = Session::repository(pool)
+                    .with_cipher(cipher)
+                    .sessions_of_user(export.user_id)
+                    .await?;
+                export
+                    .sessions
+                    .extend(sessions.into_iter().map(|session| SessionExport {
+                        expires_at: session.expires_at,
+                    }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete the data of the user in this location and count it in `summary`.
+    async fn delete(
+        &self,
+        pool: &AnyPool,
+        user_id: u64,
+        events: Option<&EventBus>,
+        summary: &mut UserDataSummary,
+    ) -> Result<()> {
+        match self {
+            UserDataLocation::Reminders => {
+                summary.reminders += Reminder::repository(pool)
+                    .with_events(events)
+                    .delete_reminders_matching(&user_reminders(user_id))
+                    .await?;
+            }
+            UserDataLocation::Sessions => {
+                summary.sessions += Session::repository(pool)
+                    .delete_sessions_of_user(user_id)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything fercord stores about a user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserDataExport {
+    pub user_id: u64,
+    pub exported_at: DateTime<Utc>,
+    /// The reminders the user created, decrypted when a cipher was given.
+    pub reminders: Vec<Reminder>,
+    /// The ids of the reminders of the user that could not be read, like ones encrypted with a key that is gone.
+    pub unreadable_reminders: Vec<i64>,
+    /// The web sessions of the user that did not expire.
+    pub sessions: Vec<SessionExport>,
+}
+
+/// A web session of a user. The session key and the Discord tokens in its state are credentials, so they are left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionExport {
+    pub expires_at: DateTime<Utc>,
+}
+
+/// How much data was deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserDataSummary {
+    pub reminders: u64,
+    pub sessions: u64,
+}
+
+impl Display for UserDataSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} reminders and {} sessions",
+            self.reminders, self.sessions
+        )
+    }
+}
+
+fn user_reminders(user_id: u64) -> ReminderFilter {
+    ReminderFilter {
+        who: Some(user_id),
+        ..Default::default()
+    }
+}
+
+/// Collect everything that is stored about the user.
+///
+/// Without a cipher encrypted reminders are exported as they are stored, and encrypted sessions can not be read.
+/// Reminders that can not be read with the cipher are only listed by id.
+pub async fn export_user_data(
+    pool: &AnyPool,
+    cipher: Option<&FieldCipher>,
+    user_id: u64,
+) -> Result<UserDataExport> {
+    event!(Level::DEBUG, user_id, "Exporting user data");
+
+    let mut export = UserDataExport {
+        user_id,
+        exported_at: Utc::now(),
+        reminders: Vec::new(),
+        unreadable_reminders: Vec::new(),
+        sessions: Vec::new(),
+    };
+    for location in UserDataLocation::ALL {
+        location.export(pool, cipher, &mut export).await?;
+    }
+
+    Ok(export)
+}
+
+/// Delete everything that is stored about the user. The deletions are published on `events`, when given.
+pub async fn delete_user_data(
+    pool: &AnyPool,
+    user_id: u64,
+    events: Option<&EventBus>,
+) -> Result<UserDataSummary> {
+    let mut summary = UserDataSummary::default();
+    for location in UserDataLocation::ALL {
+        location.delete(pool, user_id, events, &mut summary).await?;
+    }
+
+    event!(Level::INFO, user_id, %summary, "Deleted user data");
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{SubsecRound, TimeDelta};
+
+    use super::*;
+    use crate::db::{self, Repository};
+
+    fn reminder(who: u64, what: &str) -> Reminder {
+        Reminder {
+            id: 0,
+            who,
+            when: Utc::now().trunc_subsecs(0),
+            what: what.into(),
+            server: 42,
+            channel: 4242,
+            what_encrypted: false,
+            undeliverable_since: None,
+        }
+    }
+
+    fn session(key: &str, user_id: Option<u64>) -> Session {
+        Session {
+            key: key.into(),
+            user_id,
+            state: r#"{"session_data":"{}"}"#.into(),
+            expires_at: Utc::now().trunc_subsecs(0) + TimeDelta::hours(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn export_holds_only_the_data_of_the_user() -> Result<()> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let cipher = FieldCipher::new("test", &[1; 32])?;
+        let repo = Reminder::repository(&pool).with_cipher(Some(&cipher));
+        let mut theirs = reminder(1, "their thing");
+        theirs.id = repo.insert(&theirs).await?;
+        repo.insert(&reminder(2, "someone else's thing")).await?;
+        let theirs_session = session("theirs", Some(1));
+        let sessions = Session::repository(&pool).with_cipher(Some(&cipher));
+        sessions.insert_session(&theirs_session).await?;
+        sessions.insert_session(&session("anonymous", None)).await?;
+
+        let export = export_user_data(&pool, Some(&cipher), 1).await?;
+
+        assert_eq!(1, export.user_id);
+        assert_eq!(vec![theirs], export.reminders);
+        assert!(export.unreadable_reminders.is_empty());
+        assert_eq!(
+            vec![SessionExport {
+                expires_at: theirs_session.expires_at
+            }],
+            export.sessions
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreadable_reminders_are_named_in_the_export() -> Result<()> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let lost = FieldCipher::new("lost", &[1; 32])?;
+        let readable = Reminder::repository(&pool)
+            .insert(&reminder(1, "their thing"))
+            .await?;
+        let unreadable = Reminder::repository(&pool)
+            .with_cipher(Some(&lost))
+            .insert(&reminder(1, "their secret thing"))
+            .await?;
+
+        let current = FieldCipher::new("current", &[2; 32])?;
+        let export = export_user_data(&pool, Some(&current), 1).await?;
+
+        assert_eq!(
+            vec![readable],
+            export.reminders.iter().map(|r| r.id).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![unreadable], export.unreadable_reminders);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleting_leaves_other_users_alone() -> Result<()> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let repo = Reminder::repository(&pool);
+        repo.insert(&reminder(1, "their thing")).await?;
+        repo.insert(&reminder(1, "their other thing")).await?;
+        let other = repo.insert(&reminder(2, "someone else's thing")).await?;
+        let sessions = Session::repository(&pool);
+        sessions.insert_session(&session("theirs", Some(1))).await?;
+        sessions
+            .insert_session(&session("someone else's", Some(2)))
+            .await?;
+
+        let summary = delete_user_data(&pool, 1, None).await?;
+
+        assert_eq!(
+            UserDataSummary {
+                reminders: 2,
+                sessions: 1
+            },
+            summary
+        );
+        assert!(export_user_data(&pool, None, 1).await?.reminders.is_empty());
+        assert!(repo.get(other).await?.is_some());
+        assert!(sessions.get_session("someone else's").await?.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! Helpers shared by the tests of the storage models.
+
+/// Generate a test per backend for every case.
+///
+/// A case is an `async fn(&AnyPool) -> Result<()>` in the module that invokes the macro. sqlite runs in memory,
+/// Postgres runs against the database in `FERCORD_TEST_POSTGRES_URL` and is skipped when that variable is not set.
+macro_rules! conformance_suite {
+    ($($case:ident),* $(,)?) => {
+        #[cfg(feature = "sqlite")]
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $case() -> anyhow::Result<()> {
+                    let pool = crate::db::setup("sqlite::memory:").await?;
+
+                    super::$case(&pool).await
+                }
+            )*
+        }
+
+        #[cfg(feature = "postgres")]
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $case() -> anyhow::Result<()> {
+                    let Ok(url) = std::env::var("FERCORD_TEST_POSTGRES_URL") else {
+                        eprintln!("FERCORD_TEST_POSTGRES_URL is not set, skipping");
+                        return Ok(());
+                    };
+                    let pool = crate::db::setup(&url).await?;
+
+                    super::$case(&pool).await
+                }
+            )*
+        }
+    };
+}
+
+pub(crate) use conformance_suite;
+
+/// A snowflake that no other test case uses, so cases can share a database.
+pub(crate) fn unique_snowflake() -> u64 {
+    uuid::Uuid::new_v4().as_u64_pair().0 >> 1
+}
+
+/// A Postgres database of its own, for tests that can not share the test database.
+#[cfg(feature = "postgres")]
+pub(crate) struct TempPostgresDatabase {
+    admin: sqlx_oldapi::AnyPool,
+    name: String,
+    pub url: String,
+}
+
+#[cfg(feature = "postgres")]
+impl TempPostgresDatabase {
+    /// Create a database on the server of `FERCORD_TEST_POSTGRES_URL`, `None` when that variable is not set.
+    pub async fn create(prefix: &str) -> anyhow::Result<Option<Self>> {
+        use anyhow::Context;
+
+        let Ok(url) = std::env::var("FERCORD_TEST_POSTGRES_URL") else {
+            eprintln!("FERCORD_TEST_POSTGRES_URL is not set, skipping");
+            return Ok(None);
+        };
+        let (server_url, _) = url
+            .rsplit_once('/')
+            .context("FERCORD_TEST_POSTGRES_URL should end with a database name")?;
+        let name = format!("{prefix}_{}", uuid::Uuid::new_v4().simple());
+
+        let admin = sqlx_oldapi::any::AnyPoolOptions::new()
+            .max_connections(1)
+            .connect(&url)
+            .await?;
+        sqlx_oldapi::query(&format!("CREATE DATABASE {name}"))
+            .execute(&admin)
+            .await?;
+
+        Ok(Some(Self {
+            admin,
+            url: format!("{server_url}/{name}"),
+            name,
+        }))
+    }
+
+    /// Drop the database, every pool connected to it has to be closed first.
+    pub async fn drop_database(self) -> anyhow::Result<()> {
+        sqlx_oldapi::query(&format!("DROP DATABASE {}", self.name))
+            .execute(&self.admin)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+//! Copying all data from one database to another, for example to move from sqlite to Postgres.
+//!
+//! Reminders are copied in batches, in the order of their ids and with the ids they have. Every batch is committed on
+//! its own, so a copy that was interrupted continues after the last reminder that made it to the destination.
+
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use sqlx_oldapi::AnyPool;
+use tracing::{event, Level};
+
+use crate::db::{Order, Repository};
+use crate::kv::{DatabaseStore, KVClient};
+use crate::model::{
+    GuildDeparture, GuildSettings, Reminder, ReminderCursor, ReminderFilter, ReminderListOptions,
+};
+
+pub use fercord_common::cli::DEFAULT_COPY_BATCH_SIZE;
+
+/// The amount of records in a table of both databases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableCount {
+    pub source: u64,
+    pub destination: u64,
+}
+
+impl TableCount {
+    pub fn matches(&self) -> bool {
+        self.source == self.destination
+    }
+}
+
+/// What a copy did and how both databases compare afterwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopySummary {
+    /// The reminders copied by this run, earlier runs that were interrupted are not included.
+    pub copied_reminders: u64,
+    pub reminders: TableCount,
+    /// The reminders that are marked undeliverable, these are included in `reminders` as well.
+    pub undeliverable_reminders: TableCount,
+    pub guild_settings: TableCount,
+    pub guild_departures: TableCount,
+    pub kv_entries: TableCount,
+}
+
+impl CopySummary {
+    /// Whether the destination has as many records as the source in every table.
+    pub fn is_complete(&self) -> bool {
+        self.reminders.matches()
+            && self.undeliverable_reminders.matches()
+            && self.guild_settings.matches()
+            && self.guild_departures.matches()
+            && self.kv_entries.matches()
+    }
+}
+
+impl Display for CopySummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Copied {} reminders", self.copied_reminders)?;
+        for (table, count) in [
+            ("reminders", self.reminders),
+            ("reminders (undeliverable)", self.undeliverable_reminders),
+            ("guild_settings", self.guild_settings),
+            ("guild_departures", self.guild_departures),
+            ("kv_store", self.kv_entries),
+        ] {
+            writeln!(
+                f,
+                "{table}: {} in the source, {} in the destination{}",
+                count.source,
+                count.destination,
+                if count.matches() { "" } else { " (MISMATCH)" }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy all reminders, guild settings, guild departures and KV store entries from `source` to `destination`.
+///
+/// Both pools should come from [`db::setup`](crate::db::setup), so both databases are migrated. The destination may
+/// only have reminders that a previous copy from the same source left there. Guild settings and KV store entries
+/// replace the ones in the destination, entries that expire keep the time they have left. Guild departures that the
+/// destination already has are kept.
+pub async fn copy_database(
+    source: &AnyPool,
+    destination: &AnyPool,
+    batch_size: u32,
+) -> Result<CopySummary> {
+    let source_reminders = Reminder::repository(source);
+    let destination_reminders = Reminder::repository(destination);
+
+    let mut options = ReminderListOptions {
+        limit: batch_size.max(1),
+        after: last_copied_reminder(source, destination).await?,
+        ..Default::default()
+    };
+    if let Some(after) = &options.after {
+        event!(Level::INFO, after = after.id, "Continuing an earlier copy");
+    }
+
+    let mut copied_reminders = 0;
+    loop {
+        let page = source_reminders.list(&options).await?;
+        copied_reminders += destination_reminders.insert_with_ids(&page.items).await?;
+        event!(Level::INFO, copied_reminders, "Copied a batch of reminders");
+
+        match page.next {
+            Some(next) => options.after = Some(next),
+            None => break,
+        }
+    }
+
+    let destination_settings = GuildSettings::repository(destination);
+    for settings in GuildSettings::repository(source)
+        .all_guild_settings()
+        .await?
+    {
+        destination_settings.save_guild_settings(&settings).await?;
+    }
+
+    let destination_departures = GuildDeparture::repository(destination);
+    for departure in GuildDeparture::repository(source)
+        .all_guild_departures()
+        .await?
+    {
+        destination_departures
+            .save_guild_departure(&departure)
+            .await?;
+    }
+
+    let source_kv = KVClient::from_store(Arc::new(DatabaseStore::new(source.clone())));
+    let destination_kv = KVClient::from_store(Arc::new(DatabaseStore::new(destination.clone())));
+    for key in source_kv.keys("*").await? {
+        match source_kv.get_raw_with_ttl(&key).await? {
+            // Expired between listing and reading it
+            None => {}
+            Some((_, Some(ttl))) if ttl.is_zero() => {}
+            Some((value, ttl)) => destination_kv.save_raw(&key, value, ttl).await?,
+        }
+    }
+
+    let all_reminders = ReminderFilter::default();
+    let deliverable_reminders = ReminderFilter {
+        deliverable_only: true,
+        ..Default::default()
+    };
+    let source_deliverable = source_reminders.count(&deliverable_reminders).await?;
+    let destination_deliverable = destination_reminders.count(&deliverable_reminders).await?;
+    let reminders = TableCount {
+        source: source_reminders.count(&all_reminders).await?,
+        destination: destination_reminders.count(&all_reminders).await?,
+    };
+    let summary = CopySummary {
+        copied_reminders,
+        reminders,
+        undeliverable_reminders: TableCount {
+            source: reminders.source - source_deliverable,
+            destination: reminders.destination - destination_deliverable,
+        },
+        guild_settings: TableCount {
+            source: GuildSettings::repository(source)
+                .all_guild_settings()
+                .await?
+                .len() as u64,
+            destination: destination_settings.all_guild_settings().await?.len() as u64,
+        },
+        guild_departures: TableCount {
+            source: GuildDeparture::repository(source)
+                .all_guild_departures()
+                .await?
+                .len() as u64,
+            destination: destination_departures.all_guild_departures().await?.len() as u64,
+        },
+        kv_entries: TableCount {
+            source: source_kv.keys("*").await?.len() as u64,
+            destination: destination_kv.keys("*").await?.len() as u64,
+        },
+    };
+    event!(Level::INFO, ?summary, "Finished copying the database");
+
+    Ok(summary)
+}
+
+/// Where an earlier copy stopped, after the highest id in the destination. `None` when it has no reminders yet.
+///
+/// The source may have deleted that reminder since, but when it still has it both have to be the same. Otherwise the
+/// destination has other data and this fails.
+async fn last_copied_reminder(
+    source: &AnyPool,
+    destination: &AnyPool,
+) -> Result<Option<ReminderCursor>> {
+    let newest = ReminderListOptions {
+        order: Order::Descending,
+        limit: 1,
+        ..Default::default()
+    };
+    let Some(last) = Reminder::repository(destination)
+        .list(&newest)
+        .await?
+        .items
+        .pop()
+    else {
+        return Ok(None);
+    };
+
+    let in_source = Reminder::repository(source).get(last.id).await?;
+    if in_source.is_some_and(|reminder| reminder != last) {
+        bail!(
+            "Reminder {} in the destination does not match the source, the destination should be empty or hold an earlier copy of the source",
+            last.id
+        );
+    }
+
+    Ok(Some(ReminderCursor::from(&last)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+
+    use super::*;
+    use crate::db;
+
+    fn reminder(what: &str) -> Reminder {
+        Reminder {
+            id: 0,
+            who: 1,
+            when: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            what: what.into(),
+            server: 2,
+            channel: 3,
+            what_encrypted: false,
+            undeliverable_since: None,
+        }
+    }
+
+    /// A sqlite database with five reminders, of which the second one was deleted and the last one is undeliverable,
+    /// some settings and a guild departure.
+    async fn source_database() -> Result<AnyPool> {
+        let pool = db::setup("sqlite::memory:").await?;
+        let repo = Reminder::repository(&pool);
+        let mut ids = Vec::new();
+        for what in ["one", "two", "three", "four", "five"] {
+            ids.push(repo.insert(&reminder(what)).await?);
+        }
+        repo.delete_many(&ids[1..2]).await?;
+        let mut undeliverable = repo.get(ids[4]).await?.unwrap();
+        undeliverable.undeliverable_since = DateTime::from_timestamp(1_700_000_100, 0);
+        repo.delete_many(&ids[4..]).await?;
+        repo.insert_with_ids(&[undeliverable]).await?;
+
+        GuildSettings::repository(&pool)
+            .save_guild_settings(&GuildSettings {
+                guild_id: 2,
+                timezone: Some(Tz::Europe__Brussels),
+            })
+            .await?;
+        GuildDeparture::repository(&pool)
+            .save_guild_departure(&GuildDeparture {
+                guild_id: 5,
+                left_at: DateTime::from_timestamp(1_700_000_200, 0).unwrap(),
+            })
+            .await?;
+        KVClient::from_store(Arc::new(DatabaseStore::new(pool.clone())))
+            .save_raw("job_paused_reminders", "true".into(), None)
+            .await?;
+
+        Ok(pool)
+    }
+
+    async fn all_reminders(pool: &AnyPool) -> Result<Vec<Reminder>> {
+        Ok(Reminder::repository(pool)
+            .list(&ReminderListOptions::default())
+            .await?
+            .items)
+    }
+
+    async fn assert_copied(source: &AnyPool, destination: &AnyPool) -> Result<()> {
+        assert_eq!(
+            all_reminders(source).await?,
+            all_reminders(destination).await?
+        );
+        assert_eq!(
+            GuildSettings::repository(source)
+                .all_guild_settings()
+                .await?,
+            GuildSettings::repository(destination)
+                .all_guild_settings()
+                .await?
+        );
+        assert_eq!(
+            GuildDeparture::repository(source)
+                .all_guild_departures()
+                .await?,
+            GuildDeparture::repository(destination)
+                .all_guild_departures()
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn copy_keeps_ids_and_verifies_counts() -> Result<()> {
+        let source = source_database().await?;
+        let destination = db::setup("sqlite::memory:").await?;
+
+        let summary = copy_database(&source, &destination, 2).await?;
+
+        assert_eq!(4, summary.copied_reminders);
+        assert!(summary.is_complete(), "{summary}");
+        assert_eq!(1, summary.kv_entries.destination);
+        assert_eq!(1, summary.undeliverable_reminders.destination);
+        assert_eq!(1, summary.guild_departures.destination);
+        assert_copied(&source, &destination).await
+    }
+
+    #[tokio::test]
+    async fn interrupted_copy_continues() -> Result<()> {
+        let source = source_database().await?;
+        let destination = db::setup("sqlite::memory:").await?;
+        let first_batch = &all_reminders(&source).await?[..2];
+        Reminder::repository(&destination)
+            .insert_with_ids(first_batch)
+            .await?;
+
+        let summary = copy_database(&source, &destination, 2).await?;
+
+        assert_eq!(2, summary.copied_reminders);
+        assert!(summary.is_complete(), "{summary}");
+        assert_copied(&source, &destination).await
+    }
+
+    #[tokio::test]
+    async fn copy_continues_after_the_last_copied_reminder_was_deleted() -> Result<()> {
+        let source = source_database().await?;
+        let destination = db::setup("sqlite::memory:").await?;
+        let first_batch = &all_reminders(&source).await?[..2];
+        Reminder::repository(&destination)
+            .insert_with_ids(first_batch)
+            .await?;
+        Reminder::repository(&source)
+            .delete_many(&[first_batch[1].id])
+            .await?;
+
+        let summary = copy_database(&source, &destination, DEFAULT_COPY_BATCH_SIZE).await?;
+
+        assert_eq!(2, summary.copied_reminders);
+        assert_eq!(3, summary.reminders.source);
+        assert_eq!(4, summary.reminders.destination);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn destination_with_other_reminders_is_refused() -> Result<()> {
+        let source = source_database().await?;
+        let destination = db::setup("sqlite::memory:").await?;
+        Reminder::repository(&destination)
+            .insert(&reminder("not from the source"))
+            .await?;
+
+        assert!(copy_database(&source, &destination, 2).await.is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn sqlite_copies_to_postgres() -> Result<()> {
+        use crate::testing::TempPostgresDatabase;
+
+        let Some(database) = TempPostgresDatabase::create("fercord_copy").await? else {
+            return Ok(());
+        };
+        let source = source_database().await?;
+        let destination = db::setup(&database.url).await?;
+
+        let result = async {
+            let summary = copy_database(&source, &destination, 2).await?;
+            assert!(summary.is_complete(), "{summary}");
+            assert_copied(&source, &destination).await?;
+
+            // The identity continues after the copied ids
+            let next_id = Reminder::repository(&destination)
+                .insert(&reminder("new"))
+                .await?;
+            assert!(next_id > 5);
+
+            anyhow::Ok(())
+        }
+        .await;
+        destination.close().await;
+        database.drop_database().await?;
+
+        result
+    }
+}